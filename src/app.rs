@@ -1,75 +1,439 @@
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::str::FromStr;
 use std::time::{Duration, Instant};
 
 use eframe;
 use egui;
 
 use crate::audio::AudioRenderer;
+use crate::cart::{self, Cart};
 use crate::consts::{
-    CYCLES_PER_FRAME, FRAME_INTERVAL, FRAME_RATE, SCALE_FACTOR, SCREEN_HEIGHT, SCREEN_WIDTH,
+    CYCLES_PER_FRAME, FRAME_INTERVAL, FRAME_RATE, ROM_BANK_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH,
 };
-use crate::gb::GB;
-use crate::video::VideoRenderer;
+use crate::cpu::InitialRegisters;
+use crate::crash;
+use crate::debugger::Debugger;
+use crate::disasm;
+use crate::gb::{self, Model, GB};
+use crate::ips::RamBaseline;
+use crate::joypad::JoypadButton;
+use crate::link::Link;
+use crate::mmu::WriteWatch;
+use crate::palette::{self, ColorCorrection, Palette};
+use crate::tiles::TileViewer;
+use crate::trace::{self, TraceComparer};
+use crate::video::{self, Crop, VideoRenderer};
+
+const APU_LOG_INTERVAL_FRAMES: u64 = 30;
+// --sync audio keeps about this much audio queued, emulating up to AUDIO_SYNC_MAX_FRAMES per
+// repaint to get there
+const AUDIO_SYNC_LATENCY: f32 = 0.05; // seconds
+const AUDIO_SYNC_MAX_FRAMES: u32 = 4;
+
+// What paces emulation, set with --sync
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+    Video, // a frame every FRAME_INTERVAL of wall time, audio may under- or overrun
+    Audio, // frames as the audio device drains samples, no crackle but frame timing jitters
+}
+
+impl FromStr for SyncMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "video" => Ok(SyncMode::Video),
+            "audio" => Ok(SyncMode::Audio),
+            _ => Err(format!("unknown sync mode '{}' (expected audio or video)", s)),
+        }
+    }
+}
+
+// Front-end settings collected from the command line
+pub struct Options {
+    pub turbo: bool,
+    pub dmg_palette_from_bootrom: bool,
+    pub accurate_ppu: bool,
+    pub autosave_interval: u32, // seconds between battery RAM flushes
+    pub toggle_buttons: Vec<JoypadButton>,
+    pub force_loadstate: bool,
+    pub compare: Option<String>, // reference trace path
+    pub crop: Crop,
+    pub scale: u32, // initial window size, in multiples of the (cropped) frame
+    pub fullscreen: bool,
+    pub integer_scale: bool,
+    pub show_fps: bool,
+    pub strict: bool,            // refuse ROMs with a bad header checksum
+    pub gamepad_deadzone: f32,   // left stick deflection (0-1) that counts as a direction
+    pub ips_out: Option<String>, // RAM diff patch path
+    pub autofire_buttons: Vec<JoypadButton>,
+    pub autofire_rate: f32, // presses per second
+    pub input_delay: u64,   // frames
+    pub ghosting: Option<f32>,
+    pub watch_vram: Option<WriteWatch>,
+    pub color_correction: Option<ColorCorrection>, // None picks by cartridge type
+    pub log_apu: bool,
+    pub save_dir: Option<String>,
+    pub echo_serial: bool,
+    pub boot_rom: Option<String>,
+    pub bootless_state: Option<InitialRegisters>,
+    pub model: Option<Model>, // None picks by cartridge type
+    pub palette: Palette,
+    pub no_sound: bool,
+    pub sync: SyncMode,
+    pub debug_vram: bool,
+    pub trace: bool,
+    pub link_server: Option<u16>, // port to wait for a link partner on
+    pub link_connect: Option<String>, // host:port of a --link-server instance
+}
 
 pub struct App {
     gb: GB,
-    rom_path: String,
+    save_path: String, // .sav and .st files are this path with the extension swapped
     video_renderer: VideoRenderer,
-    audio_renderer: AudioRenderer,
+    audio_renderer: Option<AudioRenderer>, // None with --no-sound
     next_frame_at: Instant,
+    sync: SyncMode,
+    audio_sync_samples: usize, // samples to keep queued with --sync audio
     turbo: bool,
+    autosave_interval_frames: u32,
+    frames_since_autosave: u32,
+    trace_comparer: Option<TraceComparer>,
+    ips_out: Option<(String, RamBaseline)>, // output path and the state to diff against
+    log_apu: bool,
+    apu_logged_frame: u64,
+    tile_viewer: Option<TileViewer>, // --debug-vram window, None once closed
+    trace: bool,
 }
 
 impl App {
-    pub fn new(rom_path: String, turbo: bool) -> Self {
-        let (audio_rendererer, producer) = AudioRenderer::new();
-        let gb = GB::new(&rom_path, producer, audio_rendererer.sample_rate);
+    pub fn new(rom_path: String, options: Options) -> Self {
+        let (audio_rendererer, mut gb) = if options.no_sound {
+            (None, GB::new_headless(&rom_path))
+        } else {
+            let (audio_rendererer, producer) = AudioRenderer::new();
+            let gb = GB::new(&rom_path, producer, audio_rendererer.sample_rate);
+            (Some(audio_rendererer), gb)
+        };
+        if options.strict && !gb.cart.header_checksum_ok() {
+            panic!("Error: {} has a bad header checksum (--strict)", rom_path);
+        }
+        if let Some(model) = options.model {
+            gb.set_model(model);
+        }
+        gb.ppu.accurate = options.accurate_ppu;
+        gb.force_loadstate = options.force_loadstate;
+        gb.mmu.serial.echo = options.echo_serial;
+        if let Some(boot_rom) = &options.boot_rom {
+            gb.load_boot_rom(boot_rom).unwrap_or_else(|e| panic!("Error: {}", e));
+        }
+        if let Some(registers) = &options.bootless_state {
+            registers.apply(&mut gb.cpu);
+        }
+        let link = match (options.link_server, &options.link_connect) {
+            (Some(port), _) => Some(Link::listen(port)),
+            (None, Some(addr)) => Some(Link::connect(addr)),
+            (None, None) => None,
+        };
+        if let Some(link) = link {
+            gb.connect_link(
+                link.unwrap_or_else(|e| panic!("Error: Unable to set up the link cable: {}", e)),
+            );
+        }
+        let save_path = save_path(&rom_path, options.save_dir.as_deref());
+        if gb.cart.battery_support {
+            gb.mmu.loadram(&save_path, &mut gb.cart);
+        }
+        let colorization = if options.dmg_palette_from_bootrom {
+            palette::colorization_for(&gb.cart)
+        } else {
+            None
+        };
+
+        let color_correction =
+            options.color_correction.unwrap_or(ColorCorrection::default_for(&gb.cart));
+
+        let tile_viewer = options
+            .debug_vram
+            .then(|| TileViewer::new(&mut gb, colorization.map_or(options.palette, |c| c.bg)));
+        let video_renderer = VideoRenderer::new(colorization, color_correction, &options);
+        gb.mmu.write_watch = options.watch_vram;
+        let ips_out = options.ips_out.map(|path| (path, RamBaseline::capture(&gb)));
+
+        let audio_sync_samples = audio_rendererer
+            .as_ref()
+            .map_or(0, |audio| (audio.sample_rate * AUDIO_SYNC_LATENCY) as usize);
 
         App {
             gb: gb,
-            rom_path: rom_path,
-            video_renderer: VideoRenderer::new(),
+            save_path,
+            video_renderer,
             audio_renderer: audio_rendererer,
             next_frame_at: Instant::now() + FRAME_INTERVAL,
-            turbo: turbo,
+            sync: options.sync,
+            audio_sync_samples,
+            turbo: options.turbo,
+            autosave_interval_frames: (options.autosave_interval as f32 * FRAME_RATE) as u32,
+            frames_since_autosave: 0,
+            trace_comparer: options.compare.map(|path| {
+                TraceComparer::open(&path).expect("Error: Unable to open the reference trace")
+            }),
+            ips_out,
+            log_apu: options.log_apu,
+            apu_logged_frame: 0,
+            tile_viewer,
+            trace: options.trace,
+        }
+    }
+
+    // Emulates until target_rate cycles have run, then does the once-per-frame bookkeeping
+    fn run_frame(&mut self, target_rate: u32) {
+        let gb = &mut self.gb;
+        let trace_comparer = &mut self.trace_comparer;
+        let trace = self.trace;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            gb.run_for(target_rate, |gb| {
+                if trace {
+                    let pc = gb.cpu.pc;
+                    println!("{}  {}", trace::trace_line(gb), disasm::disassemble_at(gb, pc));
+                }
+                if let Some(divergence) = trace_comparer.as_mut().and_then(|t| t.check(gb)) {
+                    println!("{}", divergence);
+                    std::process::exit(1);
+                }
+            });
+        }));
+        if let Err(payload) = result {
+            crash::write_crash_report(&self.gb, &self.save_path);
+            panic::resume_unwind(payload);
         }
+
+        // throttled to a couple of dumps per second of emulated time
+        let frame = self.gb.ppu.frame_count;
+        if self.log_apu && frame >= self.apu_logged_frame + APU_LOG_INTERVAL_FRAMES {
+            self.apu_logged_frame = frame;
+            println!("frame {}: {}", frame, self.gb.apu.debug_dump());
+        }
+
+        self.frames_since_autosave += 1;
+        if self.frames_since_autosave >= self.autosave_interval_frames {
+            self.frames_since_autosave = 0;
+            // skip the write when the game hasn't touched cart RAM since the last flush
+            if self.gb.cart.battery_support && self.gb.cart.ram_dirty {
+                if let Err(e) = self.gb.mmu.saveram(&self.save_path, &mut self.gb.cart) {
+                    println!("Warning: autosave failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+// With --save-dir, saves go to <dir>/<ROM file name> (e.g. saves/Tetris.sav and saves/Tetris.st
+// for roms/Tetris.gb), creating the directory if needed. Otherwise they sit next to the ROM.
+fn save_path(rom_path: &str, save_dir: Option<&str>) -> String {
+    let Some(save_dir) = save_dir else {
+        return rom_path.to_string();
+    };
+    fs::create_dir_all(save_dir)
+        .unwrap_or_else(|e| panic!("Error: Unable to create save directory {}: {}", save_dir, e));
+    let file_name = Path::new(rom_path).file_name().expect("Error: ROM path has no file name");
+    Path::new(save_dir).join(file_name).to_string_lossy().into_owned()
+}
+
+// the GB for the headless modes, on the --model hardware if one was given
+fn headless_gb(rom_path: &str, model: Option<Model>, accurate_ppu: bool) -> GB {
+    let mut gb = GB::new_headless(rom_path);
+    if let Some(model) = model {
+        gb.set_model(model);
     }
+    gb.ppu.accurate = accurate_ppu;
+    gb
 }
 
-pub fn run(rom_path: String, turbo: bool) -> eframe::Result<()> {
+// --hash-after: runs the ROM without a window or input and prints GB::framebuffer_hash of the
+// final frame for golden-image regression checks
+pub fn run_hash_after(rom_path: &str, frames: u64, model: Option<Model>, accurate_ppu: bool) {
+    let mut gb = headless_gb(rom_path, model, accurate_ppu);
+    gb.run_frames(frames);
+
+    println!(
+        "framebuffer-hash: {:016x} frames: {} rom: {}",
+        gb.framebuffer_hash(),
+        frames,
+        rom_path
+    );
+}
+
+// --frames: runs the ROM headless for exactly this many frames as fast as it can and prints the
+// throughput. --frames-png also saves the final frame, DMG shades through --palette and CGB
+// colors with the cart's default color correction.
+pub fn run_frames(
+    rom_path: &str,
+    frames: u64,
+    png_path: Option<&str>,
+    palette: Palette,
+    model: Option<Model>,
+    accurate_ppu: bool,
+) {
+    let mut gb = headless_gb(rom_path, model, accurate_ppu);
+
+    let start = Instant::now();
+    gb.run_frames(frames);
+    let elapsed = start.elapsed().as_secs_f32();
+    let fps = frames as f32 / elapsed;
+    println!(
+        "frames: {} in {:.2}s ({:.1} FPS, {:.0}% of real time) rom: {}",
+        frames,
+        elapsed,
+        fps,
+        fps / FRAME_RATE * 100.0,
+        rom_path
+    );
+
+    let Some(png_path) = png_path else {
+        return;
+    };
+    let pixels: Vec<egui::Color32> = if gb.mmu.cgb {
+        let correction = ColorCorrection::default_for(&gb.cart);
+        gb.ppu.colors().iter().map(|&rgb555| palette::cgb_color(rgb555, correction)).collect()
+    } else {
+        gb.ppu.framebuffer().iter().map(|&shade| palette.color(shade)).collect()
+    };
+    video::save_png(Path::new(png_path), SCREEN_WIDTH, SCREEN_HEIGHT, &pixels)
+        .unwrap_or_else(|e| panic!("Error: {}", e));
+    println!("Final frame saved: {}", png_path);
+}
+
+// --info: prints the cartridge header and exits without building a GB
+pub fn print_info(rom_path: &str) {
+    let rom = gb::read_rom(rom_path).unwrap_or_else(|e| panic!("Error: {}", e));
+    let cart = Cart::from_rom(rom);
+    let cgb = match cart.cgb_flag {
+        0xC0 => "CGB only",
+        0x80 => "CGB enhanced",
+        _ => "DMG",
+    };
+    let licensee = if cart.old_licensee_code == 0x33 {
+        format!("new \"{}\"", cart.new_licensee_code)
+    } else {
+        format!("old 0x{:02X}", cart.old_licensee_code)
+    };
+    let destination = if cart.destination_code == 0x00 { "Japan" } else { "overseas" };
+
+    println!("Title:            {}", cart.title);
+    println!(
+        "Cartridge type:   0x{:02X} ({})",
+        cart.cartridge_type,
+        cart::cartridge_type_name(cart.cartridge_type)
+    );
+    println!(
+        "ROM size:         {} KiB ({} banks)",
+        cart.rom_size_bytes / 1024,
+        cart.rom_size_bytes / ROM_BANK_SIZE as usize
+    );
+    println!("RAM size:         {} KiB", cart.ram_size_bytes / 1024);
+    println!("CGB flag:         0x{:02X} ({})", cart.cgb_flag, cgb);
+    println!(
+        "SGB flag:         0x{:02X} ({})",
+        cart.sgb_flag,
+        if cart.is_sgb_compatible() { "SGB" } else { "none" }
+    );
+    println!("Licensee:         {}", licensee);
+    println!("Destination:      0x{:02X} ({})", cart.destination_code, destination);
+    println!("Mask ROM version: 0x{:02X}", cart.mask_rom_version);
+    println!("Header checksum:  {}", if cart.header_checksum_ok() { "ok" } else { "BAD" });
+    println!("Global checksum:  {}", if cart.global_checksum_ok() { "ok" } else { "BAD" });
+}
+
+// --debug: the stdin debugger, headless like --hash-after
+pub fn run_debugger(
+    rom_path: &str,
+    model: Option<Model>,
+    accurate_ppu: bool,
+    registers: Option<&InitialRegisters>,
+) {
+    let mut gb = headless_gb(rom_path, model, accurate_ppu);
+    if let Some(registers) = registers {
+        registers.apply(&mut gb.cpu);
+    }
+    Debugger::new(gb).run();
+}
+
+pub fn run(rom_path: String, options: Options) -> eframe::Result<()> {
     let native_options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_title("Dot Matrix").with_inner_size([
-            (SCREEN_WIDTH * SCALE_FACTOR) as f32,
-            (SCREEN_HEIGHT * SCALE_FACTOR) as f32,
-        ]),
+        viewport: egui::ViewportBuilder::default()
+            .with_title("Dot Matrix")
+            .with_inner_size([
+                (options.crop.width * options.scale) as f32,
+                (options.crop.height * options.scale) as f32,
+            ])
+            .with_fullscreen(options.fullscreen),
         ..Default::default()
     };
 
     eframe::run_native(
         "Dot Matrix",
         native_options,
-        Box::new(|_| Ok(Box::new(App::new(rom_path, turbo)))),
+        Box::new(|_| Ok(Box::new(App::new(rom_path, options)))),
     )
 }
 
 impl eframe::App for App {
     fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
-        let target_rate = if !self.turbo { CYCLES_PER_FRAME } else { CYCLES_PER_FRAME * 20 };
+        // audio can't keep up with 20x speed, so it's muted rather than left to stutter
+        let turbo = self.turbo || self.video_renderer.turbo_held;
+        self.gb.apu.muted = turbo || self.audio_renderer.is_none();
+        let target_rate = if !turbo { CYCLES_PER_FRAME } else { CYCLES_PER_FRAME * 20 };
+
+        // turbo and --no-sound produce no samples to sync to, so they fall back to video timing
+        let audio_sync = self.sync == SyncMode::Audio && !self.gb.apu.muted;
 
         let now = Instant::now();
-        if now >= self.next_frame_at {
-            while self.gb.current_cycles < target_rate {
-                self.gb.step();
+        if self.video_renderer.paused {
+            // keep the schedule current so unpausing doesn't race to catch up
+            self.next_frame_at = now + FRAME_INTERVAL;
+        } else if audio_sync {
+            // the audio device drains the buffer at its own clock, which paces emulation
+            let mut frames = 0;
+            while self.gb.apu.buffered_samples() < self.audio_sync_samples
+                && frames < AUDIO_SYNC_MAX_FRAMES
+            {
+                self.run_frame(target_rate);
+                frames += 1;
             }
-            self.gb.current_cycles -= target_rate;
+            self.next_frame_at = now + FRAME_INTERVAL;
+        } else if now >= self.next_frame_at {
+            self.run_frame(target_rate);
             self.next_frame_at += FRAME_INTERVAL; // accumulator — no drift
         }
 
-        self.video_renderer.update(ui, &mut self.gb, &self.rom_path);
+        self.video_renderer.update(ui, &mut self.gb, &self.save_path);
+        if std::mem::take(&mut self.video_renderer.mark_ram_baseline) {
+            if let Some((_, baseline)) = &mut self.ips_out {
+                *baseline = RamBaseline::capture(&self.gb);
+                println!("RAM baseline marked");
+            }
+        }
+        if let Some(tile_viewer) = &mut self.tile_viewer {
+            if !tile_viewer.show(ui.ctx(), &mut self.gb) {
+                self.tile_viewer = None;
+            }
+        }
     }
 
     fn on_exit(&mut self) {
         if self.gb.cart.battery_support {
-            self.gb.mmu.saveram(&self.rom_path, &self.gb.cart);
+            if let Err(e) = self.gb.mmu.saveram(&self.save_path, &mut self.gb.cart) {
+                println!("Error: unable to write RAM contents: {}", e);
+            }
+        }
+        if let Some((path, baseline)) = &self.ips_out {
+            match baseline.write_ips(&self.gb, path) {
+                Ok(changed) => println!("RAM diff written: {} ({} bytes changed)", path, changed),
+                Err(e) => println!("RAM diff write failed ({}): {}", path, e),
+            }
         }
     }
 }
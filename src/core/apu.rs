@@ -1,6 +1,12 @@
 use crate::consts::{APU_RAM, AUDIO_INIT, CLOCK_SPEED};
-use ringbuf::{traits::Producer, HeapProd};
-
+use ringbuf::{
+    traits::{Observer, Producer, Split},
+    HeapProd, HeapRb,
+};
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
+
+#[derive(Serialize, Deserialize)]
 pub enum FrameSequencer {
     Step0, // Length Counter
     Step1, // None
@@ -12,6 +18,7 @@ pub enum FrameSequencer {
     Step7, // Volume Envelope
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Channel1 {
     pub enabled: bool,
 
@@ -26,6 +33,7 @@ pub struct Channel1 {
     pub sweep_enabled: bool,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Channel2 {
     pub enabled: bool,
 
@@ -36,14 +44,26 @@ pub struct Channel2 {
     pub envelope_timer: u8,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Channel3 {
     pub enabled: bool,
 
     pub frequency_timer: i32,
-    pub wave_position: u8,
+    pub wave_position: u8, // 0-31, the 4-bit sample in wave RAM
     pub length_timer: u16,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct Channel4 {
+    pub enabled: bool,
+
+    pub frequency_timer: i32,
+    pub lfsr: u16, // 15-bit linear feedback shift register, bit 0 is the output (inverted)
+    pub length_timer: u8,
+    pub envelope_volume: u8,
+    pub envelope_timer: u8,
+}
+
 pub const WAVE_PATTERN_DUTY: [u8; 4] = [
     0b00000001, // 12.5
     0b00000011, // 25
@@ -51,13 +71,20 @@ pub const WAVE_PATTERN_DUTY: [u8; 4] = [
     0b11111100, // 75
 ];
 
+#[derive(Serialize, Deserialize)]
 pub struct APU {
     master_enable: bool,
+    #[serde(with = "BigArray")]
     regs: [u8; 0x30],
     wave: [u8; 0x10],
 
+    // the audio output belongs to the host, not the emulated state, see load_state
+    #[serde(skip, default = "detached_sink")]
     sink: HeapProd<f32>,
+    #[serde(skip)]
     sample_rate: f32,
+    #[serde(skip)]
+    pub muted: bool, // emulates as usual but outputs nothing, e.g. while fast-forwarding
     current_cycles: f32, // fractional T-cycle counter
 
     frame_sequence_state: FrameSequencer,
@@ -65,9 +92,24 @@ pub struct APU {
     // phase: f32,
     channel1: Channel1,
     channel2: Channel2,
+    channel3: Channel3,
+    channel4: Channel4,
+}
+
+// placeholder output for a deserialized APU until load_state moves the real one over
+fn detached_sink() -> HeapProd<f32> {
+    HeapRb::<f32>::new(1).split().0
 }
 
 impl APU {
+    // Takes over the registers and channel state of a savestate's APU, keeping this one's
+    // audio output and sample rate
+    pub fn load_state(&mut self, mut saved: APU) {
+        std::mem::swap(&mut saved.sink, &mut self.sink);
+        saved.sample_rate = self.sample_rate;
+        *self = saved;
+    }
+
     pub fn new(sink: HeapProd<f32>, sample_rate: f32) -> APU {
         let mut regs = [0x0; 0x30];
         for &(addr, val) in AUDIO_INIT {
@@ -98,6 +140,18 @@ impl APU {
             envelope_timer: 0,
         };
 
+        let channel3 =
+            Channel3 { enabled: false, frequency_timer: 0, wave_position: 0, length_timer: 0 };
+
+        let channel4 = Channel4 {
+            enabled: false,
+            frequency_timer: 0,
+            lfsr: 0x7FFF,
+            length_timer: 0,
+            envelope_volume: 0,
+            envelope_timer: 0,
+        };
+
         return APU {
             master_enable: true,
             regs: regs,
@@ -105,9 +159,12 @@ impl APU {
             sink: sink,
             current_cycles: 0.0,
             sample_rate: sample_rate,
+            muted: false,
 
             channel1: channel1,
             channel2: channel2,
+            channel3,
+            channel4,
 
             // frame sequencer
             frame_sequence_state: FrameSequencer::Step0,
@@ -158,16 +215,41 @@ impl APU {
 
         while self.current_cycles >= cycles_per_sample {
             self.current_cycles -= cycles_per_sample;
-            if self.master_enable {
-                let channel1_output = self.output_channel1();
-                let channel2_output = self.output_channel2();
-                let _ = self.sink.try_push((channel1_output + channel2_output) / 2.0);
+            if self.muted {
+                continue;
             }
+            // silence is still pushed while the APU is off, --sync audio paces on the samples
+            let output = if self.master_enable {
+                (self.output_channel1()
+                    + self.output_channel2()
+                    + self.output_channel3()
+                    + self.output_channel4())
+                    / 4.0
+            } else {
+                0.0
+            };
+            let _ = self.sink.try_push(output);
         }
     }
 
+    // samples produced but not yet played by the audio device
+    pub fn buffered_samples(&self) -> usize {
+        self.sink.occupied_len()
+    }
+
     pub fn read_register(&self, addr: u16) -> u8 {
         match addr {
+            // bits 0-3 report which channels are playing, bits 4-6 are unused
+            APU_RAM::NR52 => {
+                let channels = [
+                    self.channel1.enabled,
+                    self.channel2.enabled,
+                    self.channel3.enabled,
+                    self.channel4.enabled,
+                ];
+                let status = (0..4).filter(|&i| channels[i]).fold(0, |bits, i| bits | 1 << i);
+                (self.master_enable as u8) << 7 | 0b0111_0000 | status
+            }
             APU_RAM::AUDIO_RAM_START..=APU_RAM::AUDIO_RAM_END => self.regs[addr as usize - 0xFF10],
             APU_RAM::WAVE_RAM_START..=APU_RAM::WAVE_RAM_END => self.wave[addr as usize - 0xFF30],
             _ => 0xFF,
@@ -188,50 +270,55 @@ impl APU {
             APU_RAM::NR14 => {
                 self.regs[addr as usize - 0xFF10] = val;
                 if val & 0b10000000 != 0 {
-                    self.channel1.enabled = true;
-                    if self.channel1.length_timer == 0 {
-                        self.channel1.length_timer = 64;
-                    }
-                    let period: i32 = (((self.read_register(APU_RAM::NR14)) as i32) & 7) << 8
-                        | (self.read_register(APU_RAM::NR13) as i32);
-                    self.channel1.frequency_timer = (2048 - period as i32) * 4;
-                    self.channel1.envelope_volume =
-                        (0b11110000 & self.read_register(APU_RAM::NR12)) >> 4;
-                    self.channel1.envelope_timer = 0b111 & self.read_register(APU_RAM::NR12);
-
-                    if self.channel1.length_timer == 0 {
-                        self.channel1.length_timer = 64;
-                    }
-
-                    self.trigger_sweep();
+                    self.trigger_channel1();
                 }
             }
 
             APU_RAM::NR21 => {
                 self.channel2.length_timer = 64 - (val & 0b11_1111);
+                self.regs[addr as usize - 0xFF10] = val
             }
 
             APU_RAM::NR24 => {
+                // stored first so the trigger sees the new period bits
+                self.regs[addr as usize - 0xFF10] = val;
+                if val & 0b1000_0000 != 0 {
+                    self.trigger_channel2();
+                }
+            }
+
+            APU_RAM::NR30 => {
+                // turning the DAC off silences the channel until it's triggered again
+                if val & 0b1000_0000 == 0 {
+                    self.channel3.enabled = false;
+                }
+                self.regs[addr as usize - 0xFF10] = val
+            }
+
+            APU_RAM::NR31 => {
+                self.channel3.length_timer = 256 - val as u16;
+                self.regs[addr as usize - 0xFF10] = val
+            }
+
+            APU_RAM::NR34 => {
+                self.regs[addr as usize - 0xFF10] = val;
                 if val & 0b1000_0000 != 0 {
-                    self.channel2.enabled = true;
-                    if self.channel2.length_timer == 0 {
-                        self.channel2.length_timer = 64;
-                    }
-                    let period: i32 = (((self.read_register(APU_RAM::NR24)) as i32) & 7) << 8
-                        | (self.read_register(APU_RAM::NR23) as i32);
-                    self.channel2.frequency_timer = (2048 - period as i32) * 4;
-                    self.channel2.envelope_volume =
-                        (0b1111_0000 & self.read_register(APU_RAM::NR22)) >> 4;
-                    self.channel2.envelope_timer = 0b111 & self.read_register(APU_RAM::NR22);
-
-                    if self.channel2.length_timer == 0 {
-                        self.channel2.length_timer = 64;
-                    }
+                    self.trigger_channel3();
                 }
+            }
 
+            APU_RAM::NR41 => {
+                self.channel4.length_timer = 64 - (val & 0b11_1111);
                 self.regs[addr as usize - 0xFF10] = val
             }
 
+            APU_RAM::NR44 => {
+                self.regs[addr as usize - 0xFF10] = val;
+                if val & 0b1000_0000 != 0 {
+                    self.trigger_channel4();
+                }
+            }
+
             APU_RAM::AUDIO_RAM_START..=APU_RAM::AUDIO_RAM_END => {
                 self.regs[addr as usize - 0xFF10] = val
             }
@@ -242,6 +329,137 @@ impl APU {
         }
     }
 
+    // Triggering restarts every sub-unit: a length of 0 reloads to the full 64 steps, the
+    // frequency timer and envelope reload from the registers, and the sweep shadow frequency
+    // is refreshed. The channel only turns on if its DAC is (NRx2 upper 5 bits non-zero).
+    pub fn trigger_channel1(&mut self) {
+        let nr12 = self.read_register(APU_RAM::NR12);
+        self.channel1.enabled = nr12 & 0b1111_1000 != 0;
+        if self.channel1.length_timer == 0 {
+            self.channel1.length_timer = 64;
+        }
+        let period: i32 = (((self.read_register(APU_RAM::NR14)) as i32) & 7) << 8
+            | (self.read_register(APU_RAM::NR13) as i32);
+        self.channel1.frequency_timer = (2048 - period) * 4;
+        self.channel1.envelope_volume = (0b1111_0000 & nr12) >> 4;
+        self.channel1.envelope_timer = 0b111 & nr12;
+
+        self.trigger_sweep();
+    }
+
+    pub fn trigger_channel2(&mut self) {
+        let nr22 = self.read_register(APU_RAM::NR22);
+        self.channel2.enabled = nr22 & 0b1111_1000 != 0;
+        if self.channel2.length_timer == 0 {
+            self.channel2.length_timer = 64;
+        }
+        let period: i32 = (((self.read_register(APU_RAM::NR24)) as i32) & 7) << 8
+            | (self.read_register(APU_RAM::NR23) as i32);
+        self.channel2.frequency_timer = (2048 - period) * 4;
+        self.channel2.envelope_volume = (0b1111_0000 & nr22) >> 4;
+        self.channel2.envelope_timer = 0b111 & nr22;
+    }
+
+    // The wave channel's DAC is NR30 bit 7 rather than the envelope bits. Its timer runs at
+    // twice the square channels' rate, one step per 4-bit sample.
+    pub fn trigger_channel3(&mut self) {
+        self.channel3.enabled = self.read_register(APU_RAM::NR30) & 0b1000_0000 != 0;
+        if self.channel3.length_timer == 0 {
+            self.channel3.length_timer = 256;
+        }
+        self.channel3.frequency_timer = (2048 - self.channel3_period()) * 2;
+        self.channel3.wave_position = 0;
+    }
+
+    pub fn trigger_channel4(&mut self) {
+        let nr42 = self.read_register(APU_RAM::NR42);
+        self.channel4.enabled = nr42 & 0b1111_1000 != 0;
+        if self.channel4.length_timer == 0 {
+            self.channel4.length_timer = 64;
+        }
+        self.channel4.frequency_timer = self.noise_period();
+        self.channel4.lfsr = 0x7FFF;
+        self.channel4.envelope_volume = (0b1111_0000 & nr42) >> 4;
+        self.channel4.envelope_timer = 0b111 & nr42;
+    }
+
+    fn channel3_period(&self) -> i32 {
+        (((self.read_register(APU_RAM::NR34)) as i32) & 7) << 8
+            | (self.read_register(APU_RAM::NR33) as i32)
+    }
+
+    // T-cycles between LFSR shifts: NR43's divisor code (0 meaning 8) times 16, shifted by
+    // the clock shift in the upper nibble
+    fn noise_period(&self) -> i32 {
+        let nr43 = self.read_register(APU_RAM::NR43);
+        let divisor = match nr43 & 0b111 {
+            0 => 8,
+            code => code as i32 * 16,
+        };
+        divisor << (nr43 >> 4)
+    }
+
+    // Register and channel state for --log-apu, one line per channel. Frequencies are the
+    // tone the period register produces, 131072 / (2048 - period) Hz for the square channels.
+    pub fn debug_dump(&self) -> String {
+        let reg = |addr: u16| self.read_register(addr);
+        let period = |lo: u16, hi: u16| ((reg(hi) as u32 & 7) << 8) | reg(lo) as u32;
+        let square_hz = |period: u32| 131072.0 / (2048 - period) as f32;
+
+        let mut lines = vec![format!(
+            "APU {}  NR50:{:02X} NR51:{:02X} NR52:{:02X}",
+            if self.master_enable { "on " } else { "off" },
+            reg(APU_RAM::NR50),
+            reg(APU_RAM::NR51),
+            reg(APU_RAM::NR52)
+        )];
+        lines.push(format!(
+            "  CH1 NR10:{:02X} NR11:{:02X} NR12:{:02X} NR13:{:02X} NR14:{:02X}  {:>8.1} Hz  vol {:>2}  {}",
+            reg(APU_RAM::NR10),
+            reg(APU_RAM::NR11),
+            reg(APU_RAM::NR12),
+            reg(APU_RAM::NR13),
+            reg(APU_RAM::NR14),
+            square_hz(period(APU_RAM::NR13, APU_RAM::NR14)),
+            self.channel1.envelope_volume,
+            if self.channel1.enabled { "on" } else { "off" }
+        ));
+        lines.push(format!(
+            "  CH2          NR21:{:02X} NR22:{:02X} NR23:{:02X} NR24:{:02X}  {:>8.1} Hz  vol {:>2}  {}",
+            reg(APU_RAM::NR21),
+            reg(APU_RAM::NR22),
+            reg(APU_RAM::NR23),
+            reg(APU_RAM::NR24),
+            square_hz(period(APU_RAM::NR23, APU_RAM::NR24)),
+            self.channel2.envelope_volume,
+            if self.channel2.enabled { "on" } else { "off" }
+        ));
+        // the wave channel's 32 samples make its tone 65536 / (2048 - period) Hz
+        lines.push(format!(
+            "  CH3 NR30:{:02X} NR31:{:02X} NR32:{:02X} NR33:{:02X} NR34:{:02X}  {:>8.1} Hz  vol  {}  {}",
+            reg(APU_RAM::NR30),
+            reg(APU_RAM::NR31),
+            reg(APU_RAM::NR32),
+            reg(APU_RAM::NR33),
+            reg(APU_RAM::NR34),
+            65536.0 / (2048 - period(APU_RAM::NR33, APU_RAM::NR34)) as f32,
+            reg(APU_RAM::NR32) >> 5 & 3,
+            if self.channel3.enabled { "on" } else { "off" }
+        ));
+        // for noise the rate is LFSR shifts per second
+        lines.push(format!(
+            "  CH4          NR41:{:02X} NR42:{:02X} NR43:{:02X} NR44:{:02X}  {:>8.1} Hz  vol {:>2}  {}",
+            reg(APU_RAM::NR41),
+            reg(APU_RAM::NR42),
+            reg(APU_RAM::NR43),
+            reg(APU_RAM::NR44),
+            CLOCK_SPEED as f32 / self.noise_period() as f32,
+            self.channel4.envelope_volume,
+            if self.channel4.enabled { "on" } else { "off" }
+        ));
+        lines.join("\n")
+    }
+
     pub fn clock_frequency_timers(&mut self, instruction_cycles: u32) {
         self.channel1.frequency_timer -= instruction_cycles as i32;
         while self.channel1.frequency_timer <= 0 {
@@ -258,30 +476,103 @@ impl APU {
             self.channel2.frequency_timer += (2048 - period as i32) * 4;
             self.channel2.duty_position = (self.channel2.duty_position + 1) % 8;
         }
+
+        self.channel3.frequency_timer -= instruction_cycles as i32;
+        while self.channel3.frequency_timer <= 0 {
+            self.channel3.frequency_timer += (2048 - self.channel3_period()) * 2;
+            self.channel3.wave_position = (self.channel3.wave_position + 1) % 32;
+        }
+
+        self.channel4.frequency_timer -= instruction_cycles as i32;
+        while self.channel4.frequency_timer <= 0 {
+            self.channel4.frequency_timer += self.noise_period();
+            // XOR of the two low bits shifts in at bit 14, and also at bit 6 in 7-bit mode
+            let lfsr = self.channel4.lfsr;
+            let feedback = (lfsr ^ (lfsr >> 1)) & 1;
+            let mut lfsr = (lfsr >> 1) | (feedback << 14);
+            if self.read_register(APU_RAM::NR43) & 0b1000 != 0 {
+                lfsr = (lfsr & !(1 << 6)) | (feedback << 6);
+            }
+            self.channel4.lfsr = lfsr;
+        }
     }
 
-    pub fn output_channel1(&self) -> f32 {
+    // 0 to 15, before the DAC
+    fn digital_channel1(&self) -> u8 {
         let duty_select = self.read_register(APU_RAM::NR11) >> 6 & 3;
         let pattern = WAVE_PATTERN_DUTY[duty_select as usize];
         let bit = (pattern >> self.channel1.duty_position) & 1;
-        // 0 to 15
-        let digital =
-            if self.channel1.enabled && bit == 1 { self.channel1.envelope_volume } else { 0 };
-        let analog = (digital as f32 / 7.5) - 1.0; // range: -1 to 1
-        return analog;
+        if self.channel1.enabled && bit == 1 {
+            self.channel1.envelope_volume
+        } else {
+            0
+        }
     }
 
-    pub fn output_channel2(&self) -> f32 {
+    fn digital_channel2(&self) -> u8 {
         let duty_select = self.read_register(APU_RAM::NR21) >> 6 & 3;
         let pattern = WAVE_PATTERN_DUTY[duty_select as usize];
         let bit = (pattern >> self.channel2.duty_position) & 1;
-        // 0 to 15
-        let digital =
-            if self.channel2.enabled && bit == 1 { self.channel2.envelope_volume } else { 0 };
+        if self.channel2.enabled && bit == 1 {
+            self.channel2.envelope_volume
+        } else {
+            0
+        }
+    }
+
+    // the current wave RAM sample (high nibble first) shifted right by the NR32 output level
+    fn digital_channel3(&self) -> u8 {
+        if !self.channel3.enabled {
+            return 0;
+        }
+        let byte = self.wave[self.channel3.wave_position as usize / 2];
+        let sample =
+            if self.channel3.wave_position.is_multiple_of(2) { byte >> 4 } else { byte & 0xF };
+        match self.read_register(APU_RAM::NR32) >> 5 & 3 {
+            0 => 0, // muted
+            level => sample >> (level - 1),
+        }
+    }
+
+    fn digital_channel4(&self) -> u8 {
+        if self.channel4.enabled && self.channel4.lfsr & 1 == 0 {
+            self.channel4.envelope_volume
+        } else {
+            0
+        }
+    }
+
+    // CGB PCM12 (0xFF76) and PCM34 (0xFF77): the current digital output of each channel, the
+    // lower-numbered one in the low nibble
+    pub fn pcm_amplitudes(&self) -> (u8, u8) {
+        (
+            self.digital_channel2() << 4 | self.digital_channel1(),
+            self.digital_channel4() << 4 | self.digital_channel3(),
+        )
+    }
+
+    pub fn output_channel1(&self) -> f32 {
+        let digital = self.digital_channel1();
         let analog = (digital as f32 / 7.5) - 1.0; // range: -1 to 1
         return analog;
     }
 
+    pub fn output_channel2(&self) -> f32 {
+        let digital = self.digital_channel2();
+        let analog = (digital as f32 / 7.5) - 1.0; // range: -1 to 1
+        return analog;
+    }
+
+    pub fn output_channel3(&self) -> f32 {
+        let digital = self.digital_channel3();
+        (digital as f32 / 7.5) - 1.0 // range: -1 to 1
+    }
+
+    pub fn output_channel4(&self) -> f32 {
+        let digital = self.digital_channel4();
+        (digital as f32 / 7.5) - 1.0 // range: -1 to 1
+    }
+
     pub fn trigger_sweep(&mut self) {
         self.channel1.sweep_frequency = (((self.read_register(APU_RAM::NR14)) as i32) & 7) << 8
             | (self.read_register(APU_RAM::NR13) as i32);
@@ -318,7 +609,18 @@ impl APU {
                 self.channel2.enabled = false
             }
         }
-        // Fill in other channels
+        if self.channel3.length_timer != 0 && self.read_register(APU_RAM::NR34) & 0b1000000 != 0 {
+            self.channel3.length_timer -= 1;
+            if self.channel3.length_timer == 0 {
+                self.channel3.enabled = false
+            }
+        }
+        if self.channel4.length_timer != 0 && self.read_register(APU_RAM::NR44) & 0b1000000 != 0 {
+            self.channel4.length_timer -= 1;
+            if self.channel4.length_timer == 0 {
+                self.channel4.enabled = false
+            }
+        }
     }
 
     pub fn clock_sweep(&mut self) {
@@ -353,14 +655,31 @@ impl APU {
         }
     }
 
+    // 64 Hz: every NRx2 pace ticks the volume one step up (bit 3 set) or down, stopping at
+    // 15 or 0. A pace of 0 leaves the volume alone.
     pub fn clock_envelope(&mut self) {
-        // let envelope_sweep_pace = (self.read_register(APU_RAM::NR10) & 0b111);
-        // if envelope_sweep_pace != 0 {
-        //     self.channel1.envelope_timer -= 1;
-        //     if self.channel1.envelope_timer == 0 {
-
-        //     }
-
-        // }
+        let nr12 = self.read_register(APU_RAM::NR12);
+        let nr22 = self.read_register(APU_RAM::NR22);
+        let nr42 = self.read_register(APU_RAM::NR42);
+        let envelopes = [
+            (&mut self.channel1.envelope_volume, &mut self.channel1.envelope_timer, nr12),
+            (&mut self.channel2.envelope_volume, &mut self.channel2.envelope_timer, nr22),
+            (&mut self.channel4.envelope_volume, &mut self.channel4.envelope_timer, nr42),
+        ];
+        for (volume, timer, nrx2) in envelopes {
+            let pace = nrx2 & 0b111;
+            if pace == 0 {
+                continue;
+            }
+            *timer = timer.saturating_sub(1);
+            if *timer == 0 {
+                *timer = pace;
+                if nrx2 & 0b1000 != 0 && *volume < 15 {
+                    *volume += 1;
+                } else if nrx2 & 0b1000 == 0 && *volume > 0 {
+                    *volume -= 1;
+                }
+            }
+        }
     }
 }
@@ -1,15 +1,58 @@
 use crate::consts::{RAM_BANK_SIZE, RAM_START_ADDR, ROM_BANK_SIZE};
-use chrono::{Date, DateTime, Local, Timelike};
+use chrono::{DateTime, Duration, Local};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
 enum MBC {
     None,
     MBC1,
+    MBC2,
     MBC3,
+    MBC5,
 }
 
-#[derive(Serialize, Deserialize)]
+const MBC2_RAM_SIZE: usize = 512; // 4-bit cells, stored one per byte
+
+fn mbc2_ram_index(addr: u16) -> usize {
+    (addr - RAM_START_ADDR) as usize % MBC2_RAM_SIZE
+}
+
+// Cartridge type byte (0x147) as the hardware on the board, e.g. 0x13 is "MBC3+RAM+BATTERY"
+pub fn cartridge_type_name(cartridge_type: u8) -> &'static str {
+    match cartridge_type {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0B => "MMM01",
+        0x0C => "MMM01+RAM",
+        0x0D => "MMM01+RAM+BATTERY",
+        0x0F => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1A => "MBC5+RAM",
+        0x1B => "MBC5+RAM+BATTERY",
+        0x1C => "MBC5+RUMBLE",
+        0x1D => "MBC5+RUMBLE+RAM",
+        0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xFC => "POCKET CAMERA",
+        0xFD => "BANDAI TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1+RAM+BATTERY",
+        _ => "unknown",
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum ClockCounterRegisters {
     None,
     RTCS,
@@ -19,17 +62,144 @@ pub enum ClockCounterRegisters {
     RTCDH,
 }
 
+pub const RTC_DAY_HIGH: u8 = 0x01;
+pub const RTC_HALT: u8 = 0x40;
+pub const RTC_DAY_CARRY: u8 = 0x80;
+const SECONDS_PER_DAY: i64 = 86400;
+pub const RTC_FOOTER_LEN: usize = 48;
+
+// MBC3 real time clock. The counters aren't ticked, they're derived from how long ago
+// `start_date` was (or frozen at `halt_elapsed` while halted). The game reads the latched
+// copies in seconds..dh, which only change when it latches the clock.
 #[derive(Serialize, Deserialize)]
 pub struct RTC {
     pub selected_reg: ClockCounterRegisters,
-    pub latched: bool,
+    pub latched: bool, // 0x00 was written to the latch register, a following 0x01 latches
 
     pub seconds: u8,
     pub minutes: u8,
     pub hours: u8,
-    pub dl: u8, // lower 8 bits of day counter
-    pub dh: u8, // upper 1 bit of day counter, carry bit, halt flag
-    pub start_date: DateTime<Local>,
+    pub dl: u8,                      // lower 8 bits of day counter
+    pub dh: u8, // upper 1 bit of day counter, carry bit, halt flag (halt and carry are live)
+    pub start_date: DateTime<Local>, // when all counters were 0
+    pub halt_elapsed: i64, // counter value in seconds while halted
+}
+
+impl RTC {
+    // seconds counted since all counters were 0
+    fn elapsed(&self) -> i64 {
+        if self.dh & RTC_HALT != 0 {
+            self.halt_elapsed
+        } else {
+            Local::now().signed_duration_since(self.start_date).num_seconds()
+        }
+    }
+
+    fn set_elapsed(&mut self, elapsed: i64) {
+        self.start_date = Local::now() - Duration::seconds(elapsed);
+        self.halt_elapsed = elapsed;
+    }
+
+    // live counters as (seconds, minutes, hours, days), days overflowing past 511 sets carry
+    fn counters(&mut self) -> (u8, u8, u8, u16) {
+        let elapsed = self.elapsed();
+        let days = elapsed / SECONDS_PER_DAY;
+        if days > 511 {
+            self.dh |= RTC_DAY_CARRY;
+        }
+        (
+            (elapsed % 60) as u8,
+            (elapsed / 60 % 60) as u8,
+            (elapsed / 3600 % 24) as u8,
+            (days % 512) as u16,
+        )
+    }
+
+    fn set_counters(&mut self, seconds: u8, minutes: u8, hours: u8, days: u16) {
+        self.set_elapsed(
+            seconds as i64
+                + minutes as i64 * 60
+                + hours as i64 * 3600
+                + days as i64 * SECONDS_PER_DAY,
+        );
+    }
+
+    pub fn latch(&mut self) {
+        let (seconds, minutes, hours, days) = self.counters();
+        self.seconds = seconds;
+        self.minutes = minutes;
+        self.hours = hours;
+        self.dl = days as u8;
+        self.dh = (self.dh & !RTC_DAY_HIGH) | (days >> 8) as u8;
+    }
+
+    pub fn read(&self) -> u8 {
+        match self.selected_reg {
+            ClockCounterRegisters::RTCS => self.seconds,
+            ClockCounterRegisters::RTCM => self.minutes,
+            ClockCounterRegisters::RTCH => self.hours,
+            ClockCounterRegisters::RTCDL => self.dl,
+            ClockCounterRegisters::RTCDH => self.dh | 0b0011_1110, // unused bits read 1
+            ClockCounterRegisters::None => 0xFF,
+        }
+    }
+
+    // writes go to the live counters (and the latched copy, so the game reads them back)
+    pub fn write(&mut self, val: u8) {
+        let (mut seconds, mut minutes, mut hours, mut days) = self.counters();
+        match self.selected_reg {
+            ClockCounterRegisters::RTCS => (seconds, self.seconds) = (val & 0x3F, val & 0x3F),
+            ClockCounterRegisters::RTCM => (minutes, self.minutes) = (val & 0x3F, val & 0x3F),
+            ClockCounterRegisters::RTCH => (hours, self.hours) = (val & 0x1F, val & 0x1F),
+            ClockCounterRegisters::RTCDL => {
+                days = (days & 0x100) | val as u16;
+                self.dl = val;
+            }
+            ClockCounterRegisters::RTCDH => {
+                days = (days & 0xFF) | ((val & RTC_DAY_HIGH) as u16) << 8;
+                // set_counters below freezes or restarts the clock from the current value
+                self.dh = val & (RTC_DAY_HIGH | RTC_HALT | RTC_DAY_CARRY);
+            }
+            ClockCounterRegisters::None => return,
+        }
+        self.set_counters(seconds, minutes, hours, days);
+    }
+
+    // The 48-byte footer other emulators append to MBC3 .sav files: the live and latched
+    // registers as 32-bit little-endian values (s, m, h, dl, dh each), then a 64-bit
+    // little-endian Unix timestamp of when it was written.
+    pub fn footer(&mut self) -> Vec<u8> {
+        let (seconds, minutes, hours, days) = self.counters();
+        let live =
+            [seconds, minutes, hours, days as u8, (self.dh & !RTC_DAY_HIGH) | (days >> 8) as u8];
+        let latched = [self.seconds, self.minutes, self.hours, self.dl, self.dh];
+
+        let mut footer = Vec::with_capacity(RTC_FOOTER_LEN);
+        for reg in live.iter().chain(&latched) {
+            footer.extend_from_slice(&(*reg as u32).to_le_bytes());
+        }
+        footer.extend_from_slice(&Local::now().timestamp().to_le_bytes());
+        footer
+    }
+
+    // restores a footer written by `footer`, catching up on the time spent powered off
+    pub fn load_footer(&mut self, footer: &[u8]) {
+        if footer.len() < RTC_FOOTER_LEN {
+            return;
+        }
+        let reg = |i: usize| footer[i * 4];
+        let saved_at = i64::from_le_bytes(footer[40..48].try_into().expect("8 byte slice"));
+
+        [self.seconds, self.minutes, self.hours, self.dl] = [reg(5), reg(6), reg(7), reg(8)];
+        // latched day high bit, live halt and carry
+        self.dh = (reg(9) & RTC_DAY_HIGH) | (reg(4) & (RTC_HALT | RTC_DAY_CARRY));
+        let days = reg(3) as u16 | ((reg(4) & RTC_DAY_HIGH) as u16) << 8;
+        self.set_counters(reg(0), reg(1), reg(2), days);
+        if self.dh & RTC_HALT == 0 {
+            let offline = Local::now().timestamp() - saved_at;
+            self.set_elapsed(self.elapsed() + offline.max(0));
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -37,38 +207,56 @@ pub struct Cart {
     #[serde(skip, default)]
     pub rom: Vec<u8>,
     pub title: String,
+    pub cgb_flag: u8, // 0x80 CGB enhanced, 0xC0 CGB only
+    pub sgb_flag: u8, // 0x03 uses SGB functions
+    pub old_licensee_code: u8,
+    pub new_licensee_code: String, // two ASCII characters, used when old_licensee_code is 0x33
+    pub destination_code: u8,      // 0x00 Japan, 0x01 overseas
+    pub mask_rom_version: u8,
     pub cartridge_type: u8,
     pub rom_size_code: u8,
     pub rom_size_bytes: usize,
     pub ram_size_code: u8,
     pub ram_size_bytes: usize,
-    pub ram_enabled: bool, //also does RTC registers for MBC3
-    pub rom_bank_selected: u8,
+    pub ram_enabled: bool,      //also does RTC registers for MBC3
+    pub rom_bank_selected: u16, // 9 bits on MBC5
     pub ram_bank_selected: u8,
-    pub cartridge_type_mbc: MBC,
+    cartridge_type_mbc: MBC,
     pub battery_support: bool,
     pub ram: Vec<u8>,
-    pub banking_mode: bool, // ranges locked to bank 0 by default
+    #[serde(skip)]
+    pub ram_dirty: bool, // written since the last .sav flush
+    pub banking_mode: bool, // MBC1 mode 0 (the default): 0x0000-0x3FFF and RAM stay on bank 0
 
     pub rtc: RTC,
 }
 
 impl Cart {
     pub fn from_rom(rom: Vec<u8>) -> Cart {
-        let title_bytes = &rom[0x134..0x144];
+        let cgb_flag = rom[0x143];
+        // CGB carts reuse the last title byte for the CGB flag
+        let title_end = if cgb_flag & 0x80 != 0 { 0x143 } else { 0x144 };
+        let title_bytes = &rom[0x134..title_end];
         let title =
             String::from_utf8_lossy(title_bytes.iter().cloned().collect::<Vec<u8>>().as_slice())
                 .trim_end_matches('\0')
                 .to_string();
+        let sgb_flag = rom[0x146];
+        let old_licensee_code = rom[0x14B];
+        let new_licensee_code = String::from_utf8_lossy(&rom[0x144..0x146]).into_owned();
+        let destination_code = rom[0x14A];
+        let mask_rom_version = rom[0x14C];
         let cartridge_type = rom[0x147];
         let cartridge_type_mbc = match cartridge_type {
             0x0 => MBC::None,
             0x1 | 0x2 | 0x3 => MBC::MBC1,
-            0x11 | 0x12 | 0x13 => MBC::MBC3,
+            0x5 | 0x6 => MBC::MBC2,
+            0x0F..=0x13 => MBC::MBC3,
+            0x19..=0x1E => MBC::MBC5,
             _ => MBC::None,
         };
         let battery_support =
-            cartridge_type == 0x03 || cartridge_type == 0x06 || cartridge_type == 0x09;
+            matches!(cartridge_type, 0x03 | 0x06 | 0x09 | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E);
 
         let rom_size_code = rom[0x148];
         let ram_size_code = rom[0x149];
@@ -90,9 +278,14 @@ impl Cart {
             0x01 => 2 * 1024,
             0x02 => 8 * 1024,
             0x03 => 32 * 1024,
+            0x04 => 128 * 1024,
+            0x05 => 64 * 1024,
             _ => panic!("Unsupported RAM size code: {}", ram_size_code),
         };
 
+        // MBC2 has its RAM built in and the header says 0
+        let ram_size_bytes =
+            if matches!(cartridge_type_mbc, MBC::MBC2) { MBC2_RAM_SIZE } else { ram_size_bytes };
         let ram = vec![0u8; ram_size_bytes as usize];
         let start_date = Local::now();
 
@@ -106,11 +299,18 @@ impl Cart {
             dh: 0, //;upper 1 bit of day counter, carry bit, halt flag
 
             start_date: start_date,
+            halt_elapsed: 0,
         };
 
-        Cart {
+        let cart = Cart {
             rom,
             title,
+            cgb_flag,
+            sgb_flag,
+            old_licensee_code,
+            new_licensee_code,
+            destination_code,
+            mask_rom_version,
             cartridge_type,
             rom_size_code,
             rom_size_bytes,
@@ -118,6 +318,7 @@ impl Cart {
             ram_size_bytes,
             ram_enabled: false,
             ram: ram,
+            ram_dirty: false,
             rom_bank_selected: 1,
             cartridge_type_mbc: cartridge_type_mbc,
             battery_support: battery_support,
@@ -125,54 +326,142 @@ impl Cart {
             banking_mode: true,
 
             rtc: rtc,
+        };
+        if !cart.header_checksum_ok() {
+            println!("Warning: header checksum mismatch, the ROM may be corrupt or truncated");
+        }
+        if !cart.global_checksum_ok() {
+            println!("Warning: global checksum mismatch");
         }
+        cart
     }
 
-    pub fn read_rom(&self, addr: u16) -> u8 {
-        match self.cartridge_type_mbc {
-            MBC::None => self.rom[addr as usize],
-            // Doesn't account for ROM bank bug in MBC1 (lower 5 bits set to 0 auto bump to 1)
-            MBC::MBC1 | MBC::MBC3 => match addr {
-                0x0000..=0x3FFF => self.rom[addr as usize],
-                0x4000..=0x7FFF => {
-                    let banked_addr = (self.rom_bank_selected as usize * ROM_BANK_SIZE as usize)
-                        + (addr as usize - ROM_BANK_SIZE as usize);
-                    self.rom[banked_addr as usize]
-                }
-                _ => panic!("Address out of ROM range: {:04X}", addr),
-            },
+    // 0x14D holds a checksum of 0x134-0x14C, the boot ROM locks up if it doesn't match
+    pub fn header_checksum_ok(&self) -> bool {
+        let checksum =
+            self.rom[0x134..=0x14C].iter().fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+        checksum == self.rom[0x14D]
+    }
+
+    // 0x14E-0x14F (big-endian) holds the sum of every other ROM byte. Nothing checks it on
+    // hardware, so a mismatch is only a hint that the dump is bad.
+    pub fn global_checksum_ok(&self) -> bool {
+        let sum = self
+            .rom
+            .iter()
+            .enumerate()
+            .filter(|&(addr, _)| addr != 0x14E && addr != 0x14F)
+            .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16));
+        sum == u16::from_be_bytes([self.rom[0x14E], self.rom[0x14F]])
+    }
+
+    // MBC3 + TIMER cartridge types
+    pub fn has_rtc(&self) -> bool {
+        matches!(self.cartridge_type, 0x0F | 0x10)
+    }
+
+    pub fn is_cgb_compatible(&self) -> bool {
+        self.cgb_flag & 0x80 != 0
+    }
+
+    pub fn is_sgb_compatible(&self) -> bool {
+        self.sgb_flag == 0x03
+    }
+
+    // old licensee 0x01, or 0x33 with new licensee "01"
+    pub fn is_nintendo_licensee(&self) -> bool {
+        match self.old_licensee_code {
+            0x01 => true,
+            0x33 => self.new_licensee_code == "01",
+            _ => false,
+        }
+    }
+
+    // sum of the 16 title bytes, used by the CGB boot ROM to pick a DMG palette
+    pub fn title_checksum(&self) -> u8 {
+        self.rom[0x134..0x144].iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+    }
+
+    // 64-bit FNV-1a over the whole ROM, identifies the exact dump a savestate belongs to
+    pub fn rom_hash(&self) -> u64 {
+        self.rom
+            .iter()
+            .fold(0xCBF29CE484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001B3))
+    }
+
+    // Bank numbers past the end of the ROM wrap, the unused high bits aren't connected
+    fn rom_bank_mask(&self) -> u16 {
+        (self.rom_size_bytes / ROM_BANK_SIZE as usize - 1) as u16
+    }
+
+    // Where addr (0x0000-0x7FFF) lands in the ROM through the current bank selection
+    pub fn rom_offset(&self, addr: u16) -> usize {
+        if matches!(self.cartridge_type_mbc, MBC::None) {
+            return addr as usize;
         }
+        let bank = match addr {
+            // MBC1 mode 1 also applies the upper two bank bits to the first ROM area
+            0x0000..=0x3FFF
+                if matches!(self.cartridge_type_mbc, MBC::MBC1) && !self.banking_mode =>
+            {
+                self.rom_bank_selected & 0x60 & self.rom_bank_mask()
+            }
+            0x0000..=0x3FFF => 0,
+            0x4000..=0x7FFF => self.rom_bank(),
+            _ => panic!("Address out of ROM range: {:04X}", addr),
+        };
+        bank as usize * ROM_BANK_SIZE as usize + addr as usize % ROM_BANK_SIZE as usize
+    }
+
+    // The bank mapped at 0x4000-0x7FFF: the selected bank without the bits the ROM is too small
+    // to have wired up
+    pub fn rom_bank(&self) -> u16 {
+        self.rom_bank_selected & self.rom_bank_mask()
+    }
+
+    pub fn read_rom(&self, addr: u16) -> u8 {
+        self.rom[self.rom_offset(addr)]
     }
 
     pub fn write_rom(&mut self, addr: u16, val: u8) {
         match self.cartridge_type_mbc {
             MBC::None => (),
             MBC::MBC1 => match addr {
-                0x0000..0x2000 => self.ram_enabled = val == 0x0A,
+                // only the low nibble is decoded, any XA value enables RAM
+                0x0000..0x2000 => self.ram_enabled = val & 0x0F == 0x0A,
                 0x2000..0x4000 => self.select_rom_bank(val),
+                // The 2-bit register is always the upper ROM bank bits for 0x4000-0x7FFF, and
+                // in mode 1 it also picks the RAM bank and the bank mapped at 0x0000-0x3FFF.
+                // Which of those a cart actually has wired up is handled by the size masks.
                 0x4000..0x6000 => {
                     let reg = val & 0x3;
-                    if self.banking_mode && self.ram_size_bytes >= 32 * 1024 {
-                        // min 32 KiB
-                        self.ram_bank_selected = reg;
-                    } else if self.rom_size_bytes >= 1 * 1024 * 1024 {
-                        // min 1 MiB
-                        self.rom_bank_selected = (reg << 5) | (self.rom_bank_selected & 0x1F);
-                    }
+                    self.rom_bank_selected = ((reg as u16) << 5) | (self.rom_bank_selected & 0x1F);
+                    self.update_mbc1_ram_bank();
                 }
                 0x6000..0x8000 => {
                     let reg = val & 0x1;
                     self.banking_mode = reg == 0;
+                    self.update_mbc1_ram_bank();
                 }
                 _ => panic!("Address out of ROM range: {:04X}", addr),
             },
+            // a single register range, address bit 8 picks RAM enable (clear) or ROM bank (set)
+            MBC::MBC2 => match addr {
+                0x0000..0x4000 if addr & 0x100 == 0 => self.ram_enabled = val & 0x0F == 0x0A,
+                0x0000..0x4000 => self.select_rom_bank(val),
+                0x4000..0x8000 => (),
+                _ => panic!("Address out of ROM range: {:04X}", addr),
+            },
             MBC::MBC3 => match addr {
-                0x0000..0x2000 => self.ram_enabled = val == 0x0A,
+                0x0000..0x2000 => self.ram_enabled = val & 0x0F == 0x0A,
                 0x2000..0x4000 => self.select_rom_bank(val),
                 0x4000..0x6000 => {
                     let reg = val & 0xF;
                     match reg {
-                        0x00..0x08 => self.ram_bank_selected = reg,
+                        0x00..0x08 => {
+                            self.ram_bank_selected = reg;
+                            self.rtc.selected_reg = ClockCounterRegisters::None;
+                        }
                         0x08 => self.rtc.selected_reg = ClockCounterRegisters::RTCS,
                         0x09 => self.rtc.selected_reg = ClockCounterRegisters::RTCM,
                         0x0A => self.rtc.selected_reg = ClockCounterRegisters::RTCH,
@@ -186,14 +475,41 @@ impl Cart {
                     if val == 0x0 {
                         self.rtc.latched = true;
                     } else if self.rtc.latched && val == 0x01 {
-                        self.update_rtc();
+                        self.rtc.latch();
                         self.rtc.latched = false;
                     }
                 }
                 _ => panic!("Address out of ROM range: {:04X}", addr),
             },
-            _ => panic!("Error: Unrecognized MBC"),
+            // the bank number is split across two registers, low 8 bits then bit 8
+            MBC::MBC5 => match addr {
+                0x0000..0x2000 => self.ram_enabled = val & 0x0F == 0x0A,
+                0x2000..0x3000 => self.select_rom_bank(val),
+                0x3000..0x4000 => {
+                    self.rom_bank_selected =
+                        ((val as u16 & 0x1) << 8) | (self.rom_bank_selected & 0xFF)
+                }
+                0x4000..0x6000 => self.ram_bank_selected = val & 0x0F,
+                0x6000..0x8000 => (),
+                _ => panic!("Address out of ROM range: {:04X}", addr),
+            },
+        }
+    }
+
+    // mode 0 pins RAM to bank 0, mode 1 uses the upper bank bits
+    fn update_mbc1_ram_bank(&mut self) {
+        self.ram_bank_selected =
+            if self.banking_mode { 0 } else { (self.rom_bank_selected >> 5) as u8 & 0x3 };
+    }
+
+    // offset into cart RAM for the selected bank, banks past the end of smaller RAMs wrap
+    pub fn banked_ram_index(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
         }
+        let banked_addr = (addr - RAM_START_ADDR) as usize
+            + self.ram_bank_selected as usize * RAM_BANK_SIZE as usize;
+        Some(banked_addr % self.ram.len())
     }
 
     pub fn read_ram(&self, addr: u16) -> u8 {
@@ -202,15 +518,14 @@ impl Cart {
         }
 
         match self.cartridge_type_mbc {
-            MBC::MBC1 => {
-                let banked_addr =
-                    (addr - RAM_START_ADDR) + (self.ram_bank_selected as u16 * RAM_BANK_SIZE);
-                return self.ram[banked_addr as usize];
+            // the RTC registers are mapped in place of RAM while one is selected
+            MBC::MBC3 if !matches!(self.rtc.selected_reg, ClockCounterRegisters::None) => {
+                self.rtc.read()
             }
-            MBC::MBC3 => {
-                let banked_addr =
-                    (addr - RAM_START_ADDR) + (self.ram_bank_selected as u16 * RAM_BANK_SIZE);
-                return self.ram[banked_addr as usize];
+            // 512 half-bytes repeating through 0xA000-0xBFFF, the upper nibble reads as 1s
+            MBC::MBC2 => self.ram[mbc2_ram_index(addr)] | 0xF0,
+            MBC::MBC1 | MBC::MBC3 | MBC::MBC5 => {
+                self.banked_ram_index(addr).map_or(0xFF, |index| self.ram[index])
             }
             _ => panic!("Error: Unrecognized MBC"),
         }
@@ -222,23 +537,28 @@ impl Cart {
         }
         match self.cartridge_type_mbc {
             MBC::None => (),
-            MBC::MBC1 => {
-                let banked_addr =
-                    (addr - RAM_START_ADDR) + (self.ram_bank_selected as u16 * RAM_BANK_SIZE);
-                self.ram[banked_addr as usize] = val;
+            MBC::MBC3 if !matches!(self.rtc.selected_reg, ClockCounterRegisters::None) => {
+                self.rtc.write(val);
+                self.ram_dirty = true; // the RTC is saved with the RAM
             }
-            MBC::MBC3 => {
-                if self.ram_bank_selected < 0x08 {
-                    let banked_addr =
-                        (addr - RAM_START_ADDR) + (self.ram_bank_selected as u16 * RAM_BANK_SIZE);
-                    self.ram[banked_addr as usize] = val;
+            MBC::MBC2 => {
+                self.ram[mbc2_ram_index(addr)] = val & 0x0F;
+                self.ram_dirty = true;
+            }
+            MBC::MBC1 | MBC::MBC3 | MBC::MBC5 => {
+                if let Some(index) = self.banked_ram_index(addr) {
+                    self.ram[index] = val;
+                    self.ram_dirty = true;
                 }
-                // TODO: rtc register write
             }
         }
     }
 
+    // Writes the low ROM bank register. Each MBC decodes a different width: MBC1 has 5 bits
+    // (the upper 2 come from the 0x4000 register), MBC2 4 bits, MBC3 7 bits and MBC5 the low
+    // 8 of 9 bits. MBC1-3 can't select bank 0 here, MBC5 can.
     pub fn select_rom_bank(&mut self, val: u8) {
+        let val = val as u16;
         match self.cartridge_type_mbc {
             MBC::MBC1 => {
                 let mut bank = val & 0x1F; // 5 bit register
@@ -255,25 +575,12 @@ impl Cart {
                     self.rom_bank_selected = bank;
                 }
             }
+            MBC::MBC2 => {
+                let bank = val & 0x0F; // 4 bit register
+                self.rom_bank_selected = if bank == 0 { 1 } else { bank };
+            }
+            MBC::MBC5 => self.rom_bank_selected = (self.rom_bank_selected & 0x100) | val,
             _ => panic!("Error: Unrecognized MBC"),
         }
     }
-
-    pub fn update_rtc(&mut self) {
-        let now = Local::now();
-        self.rtc.hours = now.hour() as u8;
-        self.rtc.minutes = now.minute() as u8;
-        self.rtc.seconds = now.second() as u8;
-
-        let duration = now.signed_duration_since(self.rtc.start_date);
-        let total_days = duration.num_days();
-        self.rtc.dl = (total_days & 0xFF) as u8;
-
-        if total_days > 255 {
-            self.rtc.dh |= 0x01;
-        }
-        if total_days > 511 {
-            self.rtc.dh |= 0x80;
-        }
-    }
 }
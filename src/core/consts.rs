@@ -260,8 +260,8 @@ pub const OPCODES: &[Opcode] = &[
     Opcode { opcode: 0xF9, mnemonic: "LD SP", bytes: 1, cycles: &[8] },
     Opcode { opcode: 0xFA, mnemonic: "LD", bytes: 3, cycles: &[16] },
     Opcode { opcode: 0xFB, mnemonic: "EI", bytes: 1, cycles: &[4] },
-    Opcode { opcode: 0xFC, mnemonic: "ILLEGAL_FC", bytes: 4, cycles: &[1] },
-    Opcode { opcode: 0xFD, mnemonic: "ILLEGAL_FD", bytes: 4, cycles: &[1] },
+    Opcode { opcode: 0xFC, mnemonic: "ILLEGAL_FC", bytes: 1, cycles: &[4] },
+    Opcode { opcode: 0xFD, mnemonic: "ILLEGAL_FD", bytes: 1, cycles: &[4] },
     Opcode { opcode: 0xFE, mnemonic: "CP", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0xFF, mnemonic: "RST", bytes: 1, cycles: &[16] },
 ];
@@ -401,7 +401,7 @@ pub const CB_OPCODES: &[Opcode] = &[
     Opcode { opcode: 0x83, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x84, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x85, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0x86, mnemonic: "RES", bytes: 2, cycles: &[12] },
+    Opcode { opcode: 0x86, mnemonic: "RES", bytes: 2, cycles: &[16] },
     Opcode { opcode: 0x87, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x88, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x89, mnemonic: "RES", bytes: 2, cycles: &[8] },
@@ -409,7 +409,7 @@ pub const CB_OPCODES: &[Opcode] = &[
     Opcode { opcode: 0x8B, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x8C, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x8D, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0x8E, mnemonic: "RES", bytes: 2, cycles: &[12] },
+    Opcode { opcode: 0x8E, mnemonic: "RES", bytes: 2, cycles: &[16] },
     Opcode { opcode: 0x8F, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x90, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x91, mnemonic: "RES", bytes: 2, cycles: &[8] },
@@ -417,7 +417,7 @@ pub const CB_OPCODES: &[Opcode] = &[
     Opcode { opcode: 0x93, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x94, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x95, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0x96, mnemonic: "RES", bytes: 2, cycles: &[12] },
+    Opcode { opcode: 0x96, mnemonic: "RES", bytes: 2, cycles: &[16] },
     Opcode { opcode: 0x97, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x98, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x99, mnemonic: "RES", bytes: 2, cycles: &[8] },
@@ -425,104 +425,104 @@ pub const CB_OPCODES: &[Opcode] = &[
     Opcode { opcode: 0x9B, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x9C, mnemonic: "RES", bytes: 2, cycles: &[8] },
     Opcode { opcode: 0x9D, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0x9E, mnemonic: "RES", bytes: 2, cycles: &[12] },
+    Opcode { opcode: 0x9E, mnemonic: "RES", bytes: 2, cycles: &[16] },
     Opcode { opcode: 0x9F, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xA0, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xA1, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xA2, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xA3, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xA4, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xA5, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xA6, mnemonic: "SET", bytes: 2, cycles: &[12] },
-    Opcode { opcode: 0xA7, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xA8, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xA9, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xAA, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xAB, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xAC, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xAD, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xAE, mnemonic: "SET", bytes: 2, cycles: &[12] },
-    Opcode { opcode: 0xAF, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xB0, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xB1, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xB2, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xB3, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xB4, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xB5, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xB6, mnemonic: "SET", bytes: 2, cycles: &[12] },
-    Opcode { opcode: 0xB7, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xB8, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xB9, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xBA, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xBB, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xBC, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xBD, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xBE, mnemonic: "SET", bytes: 2, cycles: &[12] },
-    Opcode { opcode: 0xBF, mnemonic: "SET", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xC0, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xC1, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xC2, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xC3, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xC4, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xC5, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xC6, mnemonic: "RES", bytes: 2, cycles: &[12] },
-    Opcode { opcode: 0xC7, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xC8, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xC9, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xCA, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xCB, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xCC, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xCD, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xCE, mnemonic: "RES", bytes: 2, cycles: &[12] },
-    Opcode { opcode: 0xCF, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xD0, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xD1, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xD2, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xD3, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xD4, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xD5, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xD6, mnemonic: "RES", bytes: 2, cycles: &[12] },
-    Opcode { opcode: 0xD7, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xD8, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xD9, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xDA, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xDB, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xDC, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xDD, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xDE, mnemonic: "RES", bytes: 2, cycles: &[12] },
-    Opcode { opcode: 0xDF, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xE0, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xE1, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xE2, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xE3, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xE4, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xE5, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xE6, mnemonic: "RES", bytes: 2, cycles: &[12] },
-    Opcode { opcode: 0xE7, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xE8, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xE9, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xEA, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xEB, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xEC, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xED, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xEE, mnemonic: "RES", bytes: 2, cycles: &[12] },
-    Opcode { opcode: 0xEF, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xF0, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xF1, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xF2, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xF3, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xF4, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xF5, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xF6, mnemonic: "RES", bytes: 2, cycles: &[12] },
-    Opcode { opcode: 0xF7, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xF8, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xF9, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xFA, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xFB, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xFC, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xFD, mnemonic: "RES", bytes: 2, cycles: &[8] },
-    Opcode { opcode: 0xFE, mnemonic: "RES", bytes: 2, cycles: &[12] },
-    Opcode { opcode: 0xFF, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xA0, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xA1, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xA2, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xA3, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xA4, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xA5, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xA6, mnemonic: "RES", bytes: 2, cycles: &[16] },
+    Opcode { opcode: 0xA7, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xA8, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xA9, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xAA, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xAB, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xAC, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xAD, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xAE, mnemonic: "RES", bytes: 2, cycles: &[16] },
+    Opcode { opcode: 0xAF, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xB0, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xB1, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xB2, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xB3, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xB4, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xB5, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xB6, mnemonic: "RES", bytes: 2, cycles: &[16] },
+    Opcode { opcode: 0xB7, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xB8, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xB9, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xBA, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xBB, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xBC, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xBD, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xBE, mnemonic: "RES", bytes: 2, cycles: &[16] },
+    Opcode { opcode: 0xBF, mnemonic: "RES", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xC0, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xC1, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xC2, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xC3, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xC4, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xC5, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xC6, mnemonic: "SET", bytes: 2, cycles: &[16] },
+    Opcode { opcode: 0xC7, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xC8, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xC9, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xCA, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xCB, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xCC, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xCD, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xCE, mnemonic: "SET", bytes: 2, cycles: &[16] },
+    Opcode { opcode: 0xCF, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xD0, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xD1, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xD2, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xD3, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xD4, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xD5, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xD6, mnemonic: "SET", bytes: 2, cycles: &[16] },
+    Opcode { opcode: 0xD7, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xD8, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xD9, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xDA, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xDB, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xDC, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xDD, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xDE, mnemonic: "SET", bytes: 2, cycles: &[16] },
+    Opcode { opcode: 0xDF, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xE0, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xE1, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xE2, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xE3, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xE4, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xE5, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xE6, mnemonic: "SET", bytes: 2, cycles: &[16] },
+    Opcode { opcode: 0xE7, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xE8, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xE9, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xEA, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xEB, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xEC, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xED, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xEE, mnemonic: "SET", bytes: 2, cycles: &[16] },
+    Opcode { opcode: 0xEF, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xF0, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xF1, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xF2, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xF3, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xF4, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xF5, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xF6, mnemonic: "SET", bytes: 2, cycles: &[16] },
+    Opcode { opcode: 0xF7, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xF8, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xF9, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xFA, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xFB, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xFC, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xFD, mnemonic: "SET", bytes: 2, cycles: &[8] },
+    Opcode { opcode: 0xFE, mnemonic: "SET", bytes: 2, cycles: &[16] },
+    Opcode { opcode: 0xFF, mnemonic: "SET", bytes: 2, cycles: &[8] },
 ];
 
 pub const CLOCK_SPEED: u32 = 4_194_304; // 4.194304 MHz
@@ -534,34 +534,73 @@ pub const SCREEN_WIDTH: u32 = 160;
 pub const SCREEN_HEIGHT: u32 = 144;
 pub const SCALE_FACTOR: u32 = 3;
 
+// PPU timing in dots (1 dot = 1 T-cycle on DMG)
+pub const DOTS_PER_SCANLINE: u32 = 456;
+pub const OAM_SCAN_DOTS: u32 = 80; // mode 2
+pub const MIN_VRAM_DOTS: u32 = 172; // mode 3 without SCX/sprite penalties
+pub const SCANLINES_PER_FRAME: u32 = 154; // 144 visible + 10 VBlank
+pub const DOTS_PER_FRAME: u32 = 70224;
+const _: () = assert!(DOTS_PER_SCANLINE * SCANLINES_PER_FRAME == DOTS_PER_FRAME);
+
 pub const ROM_BANK_SIZE: u32 = 0x4000; // 16384 bytes
 pub const RAM_BANK_SIZE: u16 = 0x2000; // 8192 bytes
 pub const RAM_START_ADDR: u16 = 0xA000;
 
-// DMG0 post boot ROM IO register state (ignoring audio)
-pub const DMG0_IO_INIT: &[(u16, u8)] = &[
+// DMG/MGB post boot ROM IO register state (audio is in AUDIO_INIT), matching the DMG CPU
+// registers set in CPU::new. See https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+pub const DMG_IO_INIT: &[(u16, u8)] = &[
     (0xFF00, 0xCF), // P1/JOYP
-    (0xFF01, 0x00), // SB
-    (0xFF02, 0x7E), // SC
-    (0xFF04, 0x18), // DIV
+    (0xFF01, 0x00), // SB (owned by Serial)
+    (0xFF02, 0x7E), // SC (owned by Serial)
+    (0xFF04, 0xAB), // DIV
     (0xFF05, 0x00), // TIMA
     (0xFF06, 0x00), // TMA
     (0xFF07, 0xF8), // TAC
     (0xFF0F, 0xE1), // IF
     (0xFF40, 0x91), // LCDC
-    (0xFF41, 0x81), // STAT
+    (0xFF41, 0x85), // STAT
     (0xFF42, 0x00), // SCY
     (0xFF43, 0x00), // SCX
-    (0xFF44, 0x91), // LY
+    // Hardware reads LY=0x00 at handoff because it's partway through line 153, where LY
+    // already reads 0. Start on line 153 so the PPU wraps to line 0 after one line.
+    (0xFF44, 0x99), // LY
     (0xFF45, 0x00), // LYC
     (0xFF46, 0xFF), // DMA
     (0xFF47, 0xFC), // BGP
-    // OBP0/OBP1 ($FF48/$FF49) are uninitialized on real DMG0 hardware.
+    // OBP0/OBP1 ($FF48/$FF49) are left uninitialized by the boot ROM.
     (0xFF4A, 0x00), // WY
     (0xFF4B, 0x00), // WX
+    // CGB-only registers are unmapped on DMG and read 0xFF
+    (0xFF4D, 0xFF), // KEY1
+    (0xFF4F, 0xFF), // VBK
+    (0xFF51, 0xFF), // HDMA1
+    (0xFF52, 0xFF), // HDMA2
+    (0xFF53, 0xFF), // HDMA3
+    (0xFF54, 0xFF), // HDMA4
+    (0xFF55, 0xFF), // HDMA5
+    (0xFF56, 0xFF), // RP
+    (0xFF68, 0xFF), // BCPS
+    (0xFF69, 0xFF), // BCPD
+    (0xFF6A, 0xFF), // OCPS
+    (0xFF6B, 0xFF), // OCPD
+    (0xFF70, 0xFF), // SVBK
     (0xFFFF, 0x00), // IE
 ];
 
+// CGB post boot ROM IO registers that differ from DMG_IO_INIT, applied over it. Registers
+// the MMU keeps outside of RAM (KEY1, VBK, SVBK, the palettes, HDMA) start from their own
+// state. Pan Docs leaves DIV, STAT and LY open on CGB: the boot ROM hands over at the start
+// of VBlank, with DIV depending on how long it ran.
+pub const CGB_IO_INIT: &[(u16, u8)] = &[
+    (0xFF04, 0x1E), // DIV
+    (0xFF41, 0x81), // STAT, mode 1
+    (0xFF44, 0x90), // LY
+    (0xFF46, 0x00), // DMA
+    (0xFF56, 0x3E), // RP
+];
+// DIV after the CGB boot ROM ran a DMG game, which takes longer for the palette selection
+pub const CGB_DMG_MODE_DIV: u8 = 0x26;
+
 pub const AUDIO_INIT: &[(u16, u8)] = &[
     (0xFF10, 0x80), // NR10
     (0xFF11, 0xBF), // NR11
@@ -1,12 +1,16 @@
 use crate::apu::APU;
 use crate::cart::Cart;
 use crate::consts::{CB_OPCODES, OPCODES};
+use crate::gb::Model;
 use crate::joypad::Joypad;
 use crate::mmu::MMU;
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::str::FromStr;
 
 pub const CPU_CLOCK_SPEED: u32 = 4_194_304;
 pub const DIVIDER_CLOCK_SPEED: u32 = 16_384;
+const RECENT_INSTRUCTIONS: usize = 16; // kept for crash reports
 
 #[derive(Copy, Clone)]
 pub enum FlagRegister {
@@ -25,6 +29,9 @@ pub enum InterruptBit {
     Joypad = 4,
 }
 
+// only the low 5 bits of IF/IE are wired to interrupt sources
+pub const INTERRUPT_MASK: u8 = 0x1F;
+
 pub enum InterruptSource {
     VBlank = 0x40,
     STAT = 0x48,
@@ -56,6 +63,95 @@ macro_rules! register_access {
     };
 }
 
+// --bootless-state: registers to start from instead of the post-boot values in CPU::new, as
+// hex NAME=VALUE pairs, e.g. "A=11,SP=DFF0,PC=0150". Registers not named keep their values.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InitialRegisters {
+    overrides: Vec<(Register, u16)>,
+}
+
+// the registers --bootless-state can set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    SP,
+    PC,
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "A" => Ok(Register::A),
+            "F" => Ok(Register::F),
+            "B" => Ok(Register::B),
+            "C" => Ok(Register::C),
+            "D" => Ok(Register::D),
+            "E" => Ok(Register::E),
+            "H" => Ok(Register::H),
+            "L" => Ok(Register::L),
+            "SP" => Ok(Register::SP),
+            "PC" => Ok(Register::PC),
+            _ => Err(format!("unknown register '{}' (expected A-L, F, SP or PC)", s)),
+        }
+    }
+}
+
+impl FromStr for InitialRegisters {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let overrides = s
+            .split(',')
+            .map(|pair| {
+                let (name, value) =
+                    pair.split_once('=').ok_or(format!("expected NAME=VALUE, got '{}'", pair))?;
+                let register: Register = name.trim().parse()?;
+                let value = value.trim();
+                let digits = value.strip_prefix("0x").or(value.strip_prefix("0X")).unwrap_or(value);
+                let value = u16::from_str_radix(digits, 16)
+                    .map_err(|e| format!("bad value '{}' for {:?}: {}", value, register, e))?;
+                if !matches!(register, Register::SP | Register::PC) && value > 0xFF {
+                    return Err(format!(
+                        "{:?} is an 8-bit register, {:X} is too large",
+                        register, value
+                    ));
+                }
+                Ok((register, value))
+            })
+            .collect::<Result<Vec<(Register, u16)>, String>>()?;
+        Ok(InitialRegisters { overrides })
+    }
+}
+
+impl InitialRegisters {
+    pub fn apply(&self, cpu: &mut CPU) {
+        for &(register, value) in &self.overrides {
+            let byte = value as u8;
+            match register {
+                Register::A => cpu.a = byte,
+                Register::F => cpu.f = byte & 0xF0, // the low nibble of F always reads 0
+                Register::B => cpu.b = byte,
+                Register::C => cpu.c = byte,
+                Register::D => cpu.d = byte,
+                Register::E => cpu.e = byte,
+                Register::H => cpu.h = byte,
+                Register::L => cpu.l = byte,
+                Register::SP => cpu.sp = value,
+                Register::PC => cpu.pc = value,
+            }
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CPU {
     pub a: u8,
@@ -71,11 +167,23 @@ pub struct CPU {
     pub sp: u16,
 
     pub ime: bool,
+    pub ime_pending: bool, // EI ran, IME turns on once the following instruction finishes
     pub stopped: bool,
     pub halted: bool,
+    pub halt_bug: bool, // the next opcode fetch doesn't advance PC, so its byte is read twice
+    pub locked: bool,   // an illegal opcode hung the CPU, nothing but a reset recovers
 
     pub div_cycles: u32,
     pub tima_cycles: u32,
+
+    #[serde(skip)]
+    pub recent: VecDeque<(u16, u8)>, // PC and opcode of the last instructions, oldest first
+}
+
+impl Default for CPU {
+    fn default() -> CPU {
+        CPU::new()
+    }
 }
 
 impl CPU {
@@ -95,13 +203,50 @@ impl CPU {
 
             ime: false,
             stopped: false,
+            ime_pending: false,
             halted: false,
+            halt_bug: false,
+            locked: false,
 
             div_cycles: 0,
             tima_cycles: 0,
+
+            recent: VecDeque::with_capacity(RECENT_INSTRUCTIONS),
         };
     }
 
+    // The registers each boot ROM hands over with. CGB values depend on whether the cart put it
+    // in CGB mode. CPU::new is the DMG.
+    pub fn for_model(model: Model, cgb_mode: bool) -> CPU {
+        let cpu = CPU::new();
+        match model {
+            Model::Dmg => cpu,
+            Model::Mgb => CPU { a: 0xFF, ..cpu },
+            Model::Cgb if cgb_mode => CPU {
+                a: 0x11,
+                f: 0x80,
+                b: 0x00,
+                c: 0x00,
+                d: 0xFF,
+                e: 0x56,
+                h: 0x00,
+                l: 0x0D,
+                ..cpu
+            },
+            Model::Cgb => CPU {
+                a: 0x11,
+                f: 0x80,
+                b: 0x00,
+                c: 0x00,
+                d: 0x00,
+                e: 0x08,
+                h: 0x00,
+                l: 0x7C,
+                ..cpu
+            },
+        }
+    }
+
     pub fn get_flag(&self, flag: FlagRegister) -> u8 {
         return (self.f & (1 << flag as u8)) >> flag as u8;
     }
@@ -131,7 +276,7 @@ impl CPU {
         // TAC: TIMA increment rate and timer enabled
         // tima_cycles tracks number of cycles to handle incrementing TIMA
 
-        let tima = mmu.read_byte(TimerSource::TimerCounter as u16, cart, joypad, apu);
+        let mut tima = mmu.read_byte(TimerSource::TimerCounter as u16, cart, joypad, apu);
         let tma = mmu.read_byte(TimerSource::TimerModulo as u16, cart, joypad, apu);
 
         let tac = mmu.read_byte(TimerSource::TimerControl as u16, cart, joypad, apu);
@@ -149,22 +294,20 @@ impl CPU {
             let increment_rate = CPU_CLOCK_SPEED / clock_freq;
             self.tima_cycles += instruction_cycles;
 
-            if self.tima_cycles >= increment_rate {
+            // at 262144 Hz a long instruction (or an interrupt dispatch) covers several ticks
+            while self.tima_cycles >= increment_rate {
                 self.tima_cycles -= increment_rate;
 
-                let new_tima = tima.wrapping_add(1);
+                tima = tima.wrapping_add(1);
 
                 // Request interrupt if TIMA overflows
-                if new_tima == 0 {
+                if tima == 0 {
                     // Reset TIMA to TMA value
-                    mmu.write_byte(TimerSource::TimerCounter as u16, tma, cart, joypad, apu);
+                    tima = tma;
                     self.request_interrupt(InterruptBit::Timer, mmu, cart, joypad, apu);
-                } else {
-                    mmu.write_byte(TimerSource::TimerCounter as u16, new_tima, cart, joypad, apu);
                 }
-            } else {
-                self.tima_cycles += instruction_cycles;
             }
+            mmu.write_byte(TimerSource::TimerCounter as u16, tima, cart, joypad, apu);
         }
     }
 
@@ -196,102 +339,92 @@ impl CPU {
         joypad: &mut Joypad,
         apu: &mut APU,
     ) {
+        // STOP halts the system clock, so DIV stays at the 0 STOP left it at until the wake-up
+        if self.stopped {
+            return;
+        }
         self.update_tima(instruction_cycles, mmu, cart, joypad, apu);
         self.update_div(instruction_cycles, mmu, cart, joypad, apu);
     }
 
+    // Services the highest priority pending interrupt (VBlank > STAT > Timer > Serial > Joypad,
+    // i.e. the lowest set bit) and returns the cycles the dispatch took. GB::step adds these to
+    // the instruction's cycles before ticking the timers and PPU. Waking from HALT costs 4
+    // more, which is the cycle execute() charges for the halted step that notices the wake-up.
     pub fn check_interrupts(
         &mut self,
         mmu: &mut MMU,
         cart: &mut Cart,
         joypad: &mut Joypad,
         apu: &mut APU,
-    ) {
+    ) -> u32 {
+        if !self.ime || self.locked {
+            return 0;
+        }
+
+        let pending = self.pending_interrupts(mmu, cart, joypad, apu);
+        let interrupt_bit = match pending.trailing_zeros() {
+            0 => InterruptBit::VBlank,
+            1 => InterruptBit::STAT,
+            2 => InterruptBit::Timer,
+            3 => InterruptBit::Serial,
+            4 => InterruptBit::Joypad,
+            _ => return 0,
+        };
+
+        // EI;HALT with an interrupt pending: the HALT bug's repeated fetch is the HALT itself,
+        // so the handler returns to the HALT and it runs again
+        if std::mem::take(&mut self.halt_bug) {
+            self.pc = self.pc.wrapping_sub(1);
+        }
+        self.handle_interrupt(interrupt_bit, mmu, cart, joypad, apu);
+        self.halted = false;
+        20 // 2 wait M-cycles, 2 to push PC, 1 to jump
+    }
+
+    // interrupts both requested and enabled, regardless of IME
+    pub fn pending_interrupts(
+        &self,
+        mmu: &mut MMU,
+        cart: &mut Cart,
+        joypad: &mut Joypad,
+        apu: &mut APU,
+    ) -> u8 {
         let interrupt_flag =
             mmu.read_byte(InterruptSource::InterruptFlag as u16, cart, joypad, apu);
         let interrupt_enable =
             mmu.read_byte(InterruptSource::InterruptEnable as u16, cart, joypad, apu);
-
-        if self.ime && (interrupt_flag & interrupt_enable) != 0 {
-            self.handle_interrupt(
-                interrupt_flag,
-                interrupt_enable,
-                InterruptBit::VBlank,
-                mmu,
-                cart,
-                joypad,
-                apu,
-            );
-            self.handle_interrupt(
-                interrupt_flag,
-                interrupt_enable,
-                InterruptBit::STAT,
-                mmu,
-                cart,
-                joypad,
-                apu,
-            );
-            self.handle_interrupt(
-                interrupt_flag,
-                interrupt_enable,
-                InterruptBit::Timer,
-                mmu,
-                cart,
-                joypad,
-                apu,
-            );
-            self.handle_interrupt(
-                interrupt_flag,
-                interrupt_enable,
-                InterruptBit::Serial,
-                mmu,
-                cart,
-                joypad,
-                apu,
-            );
-            self.handle_interrupt(
-                interrupt_flag,
-                interrupt_enable,
-                InterruptBit::Joypad,
-                mmu,
-                cart,
-                joypad,
-                apu,
-            );
-            self.halted = false;
-        }
+        interrupt_flag & interrupt_enable & INTERRUPT_MASK
     }
 
     pub fn handle_interrupt(
         &mut self,
-        interrupt_flag: u8,
-        interrupt_enable: u8,
         interrupt_bit: InterruptBit,
         mmu: &mut MMU,
         cart: &mut Cart,
         joypad: &mut Joypad,
         apu: &mut APU,
     ) {
-        if self.ime && (interrupt_flag & interrupt_enable & (1 << interrupt_bit as u8)) != 0 {
-            self.ime = false;
-
-            let new_interrupt_flag = interrupt_flag & !(1 << interrupt_bit as u8);
-            mmu.write_byte(
-                InterruptSource::InterruptFlag as u16,
-                new_interrupt_flag,
-                cart,
-                joypad,
-                apu,
-            );
-
-            self.push(self.pc, mmu, cart, joypad, apu);
-            match interrupt_bit {
-                InterruptBit::VBlank => self.pc = InterruptSource::VBlank as u16,
-                InterruptBit::STAT => self.pc = InterruptSource::STAT as u16,
-                InterruptBit::Timer => self.pc = InterruptSource::Timer as u16,
-                InterruptBit::Serial => self.pc = InterruptSource::Serial as u16,
-                InterruptBit::Joypad => self.pc = InterruptSource::Joypad as u16,
-            }
+        self.ime = false;
+
+        let interrupt_flag =
+            mmu.read_byte(InterruptSource::InterruptFlag as u16, cart, joypad, apu);
+        let new_interrupt_flag = interrupt_flag & !(1 << interrupt_bit as u8);
+        mmu.write_byte(
+            InterruptSource::InterruptFlag as u16,
+            new_interrupt_flag,
+            cart,
+            joypad,
+            apu,
+        );
+
+        self.push(self.pc, mmu, cart, joypad, apu);
+        match interrupt_bit {
+            InterruptBit::VBlank => self.pc = InterruptSource::VBlank as u16,
+            InterruptBit::STAT => self.pc = InterruptSource::STAT as u16,
+            InterruptBit::Timer => self.pc = InterruptSource::Timer as u16,
+            InterruptBit::Serial => self.pc = InterruptSource::Serial as u16,
+            InterruptBit::Joypad => self.pc = InterruptSource::Joypad as u16,
         }
     }
 
@@ -344,7 +477,8 @@ impl CPU {
 
         self.set_flag(FlagRegister::Zero, result == 0);
         self.set_flag(FlagRegister::Sub, false);
-        self.set_flag(FlagRegister::HalfCarry, (result & 0x0F) == 0x00);
+        // carry out of bit 3, only possible from a low nibble of 0xF
+        self.set_flag(FlagRegister::HalfCarry, (reg & 0x0F) == 0x0F);
 
         return result;
     }
@@ -354,7 +488,8 @@ impl CPU {
 
         self.set_flag(FlagRegister::Zero, result == 0);
         self.set_flag(FlagRegister::Sub, true);
-        self.set_flag(FlagRegister::HalfCarry, (result & 0x0F) == 0x0F);
+        // borrow from bit 4, only possible from a low nibble of 0x0
+        self.set_flag(FlagRegister::HalfCarry, (reg & 0x0F) == 0x00);
 
         return result;
     }
@@ -538,16 +673,17 @@ impl CPU {
         self.a = result;
     }
 
-    // adds to sp
-    pub fn add_signed(&mut self, value: i8) {
-        let result = self.sp.wrapping_add(value as u16);
-
+    // SP + e8 for ADD SP,e8 (0xE8) and LD HL,SP+e8 (0xF8). The result uses the sign-extended
+    // offset, but H and C come from adding the raw byte to SP's low byte as unsigned 8-bit
+    // values, so e.g. SP 0x0001 + 0xFF (-1) gives 0x0000 with both set.
+    pub fn sp_plus_offset(&mut self, offset: u8) -> u16 {
+        let low = self.sp as u8;
         self.set_flag(FlagRegister::Zero, false);
         self.set_flag(FlagRegister::Sub, false);
-        self.set_flag(FlagRegister::HalfCarry, (self.sp & 0x0F) + (value as u16 & 0x0F) > 0x0F);
-        self.set_flag(FlagRegister::Carry, (self.sp & 0xFF) + (value as u16 & 0xFF) > 0xFF);
+        self.set_flag(FlagRegister::HalfCarry, (low & 0x0F) + (offset & 0x0F) > 0x0F);
+        self.set_flag(FlagRegister::Carry, low.checked_add(offset).is_none());
 
-        self.sp = result;
+        self.sp.wrapping_add(offset as i8 as u16)
     }
 
     pub fn sbc(&mut self, value: u8) {
@@ -556,8 +692,11 @@ impl CPU {
 
         self.set_flag(FlagRegister::Zero, result == 0);
         self.set_flag(FlagRegister::Sub, true);
-        self.set_flag(FlagRegister::HalfCarry, (self.a & 0x0F) < (value & 0x0F) + carry);
-        self.set_flag(FlagRegister::Carry, (self.a as u16) < (value as u16) + (carry as u16));
+        // borrows out of bit 4 and bit 8, computed signed so the carry-in can't wrap anything
+        let half_difference = (self.a & 0x0F) as i16 - (value & 0x0F) as i16 - carry as i16;
+        let difference = self.a as i16 - value as i16 - carry as i16;
+        self.set_flag(FlagRegister::HalfCarry, half_difference < 0);
+        self.set_flag(FlagRegister::Carry, difference < 0);
 
         self.a = result;
     }
@@ -662,24 +801,41 @@ impl CPU {
         joypad: &mut Joypad,
         apu: &mut APU,
     ) -> u8 {
-        if self.halted {
-            let interrupt_flag = mmu.read_byte(0xFF0F, cart, joypad, apu);
-            let interrupt_enable = mmu.read_byte(0xFFFF, cart, joypad, apu);
+        if self.locked {
+            return 4;
+        }
 
-            if interrupt_flag & interrupt_enable != 0 {
+        if self.stopped {
+            // STOP only ends once a button is pressed
+            if joypad.any_pressed() {
+                self.stopped = false;
+            }
+            return 4;
+        }
+
+        if self.halted {
+            if self.pending_interrupts(mmu, cart, joypad, apu) != 0 {
                 self.halted = false;
             };
             return 4;
         }
 
-        let arg_u8: u8 = mmu.read_byte(self.pc + 1, cart, joypad, apu);
-        let arg_u16: u16 = mmu.read_short(self.pc + 1, cart, joypad, apu);
+        // after the HALT bug the opcode byte doubles as the first operand byte
+        let opcode_len = if std::mem::take(&mut self.halt_bug) { 0 } else { 1 };
+        let arg_u8: u8 = mmu.read_byte(self.pc.wrapping_add(opcode_len), cart, joypad, apu);
+        let arg_u16: u16 = mmu.read_short(self.pc.wrapping_add(opcode_len), cart, joypad, apu);
 
-        if opcode == 0xCB {
-            self.pc += CB_OPCODES[arg_u8 as usize].bytes as u16;
+        let bytes = if opcode == 0xCB {
+            CB_OPCODES[arg_u8 as usize].bytes as u16
         } else {
-            self.pc += OPCODES[opcode as usize].bytes as u16;
+            OPCODES[opcode as usize].bytes as u16
+        };
+        let opcode_pc = self.pc;
+        self.pc = self.pc.wrapping_add(bytes - 1 + opcode_len);
+        if self.recent.len() == RECENT_INSTRUCTIONS {
+            self.recent.pop_front();
         }
+        self.recent.push_back((opcode_pc, opcode));
 
         match opcode {
             // 8 bit load instructions
@@ -1016,11 +1172,11 @@ impl CPU {
 
             0xEA => {
                 mmu.write_byte(arg_u16, self.a, cart, joypad, apu);
-                12
+                16
             }
             0xF0 => {
                 self.a = mmu.read_byte(0xFF00 + arg_u8 as u16, cart, joypad, apu);
-                8
+                12
             }
             0xF2 => {
                 self.a = mmu.read_byte(0xFF00 + self.c as u16, cart, joypad, apu);
@@ -1028,7 +1184,7 @@ impl CPU {
             }
             0xFA => {
                 self.a = mmu.read_byte(arg_u16, cart, joypad, apu);
-                8
+                16
             }
 
             // 16 bit load instructions
@@ -1089,19 +1245,8 @@ impl CPU {
                 16
             }
             0xF8 => {
-                let temp = self.sp.wrapping_add((arg_u8 as i8) as u16);
-                self.set_hl(temp);
-
-                self.set_flag(FlagRegister::Zero, false);
-                self.set_flag(FlagRegister::Sub, false);
-                self.set_flag(
-                    FlagRegister::HalfCarry,
-                    (self.sp & 0x0F) + (arg_u8 as u16 & 0x0F) > 0x0F,
-                );
-                self.set_flag(
-                    FlagRegister::Carry,
-                    (self.sp & 0xFF) + (arg_u8 as u16 & 0xFF) > 0xFF,
-                );
+                let result = self.sp_plus_offset(arg_u8);
+                self.set_hl(result);
                 12
             }
             0xF9 => {
@@ -1527,7 +1672,7 @@ impl CPU {
                 8
             }
             0xE8 => {
-                self.add_signed(arg_u8 as i8);
+                self.sp = self.sp_plus_offset(arg_u8);
                 16
             }
 
@@ -1548,15 +1693,21 @@ impl CPU {
                 self.rra();
                 4
             }
-            0xCB => {
-                self.execute_cb(arg_u8, mmu, cart, joypad, apu);
-                4
-            }
+            // execute_cb's cycles include the prefix
+            0xCB => self.execute_cb(arg_u8, mmu, cart, joypad, apu),
 
             // CPU control instructions
             0x00 => 4,
+            // STOP is 2 bytes (0x10 0x00); the opcode table skips the second byte whatever it is
             0x10 => {
-                self.stopped = true;
+                // STOP always resets the divider
+                self.div_cycles = 0;
+                mmu.write_byte(TimerSource::DividerRegister as u16, 0, cart, joypad, apu);
+                // An armed CGB speed switch completes instead of stopping, and with a button
+                // already held STOP would wake straight away, so only the DIV reset remains
+                if !mmu.speed_switch() && !joypad.any_pressed() {
+                    self.stopped = true;
+                }
                 4
             }
             0x27 => {
@@ -1581,15 +1732,21 @@ impl CPU {
                 4
             }
             0x76 => {
-                self.halted = true;
+                // a pending interrupt ends HALT right away, so back-to-back HALTs can't wedge.
+                // With IME off that's the HALT bug: the CPU doesn't halt and fails to advance
+                // PC past the next opcode.
+                let pending = self.pending_interrupts(mmu, cart, joypad, apu) != 0;
+                self.halted = !pending;
+                self.halt_bug = pending && !self.ime;
                 4
             }
             0xF3 => {
                 self.ime = false;
+                self.ime_pending = false;
                 4
             }
             0xFB => {
-                self.ime = true;
+                self.ime_pending = true;
                 4
             }
 
@@ -1705,7 +1862,7 @@ impl CPU {
             0xD0 => {
                 if self.get_flag(FlagRegister::Carry) == 0 {
                     self.pc = self.pop(mmu, cart, joypad, apu);
-                    12
+                    20
                 } else {
                     8
                 }
@@ -1792,7 +1949,17 @@ impl CPU {
                 self.pc = 0x38;
                 16
             }
-            _ => unreachable!(),
+            // 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB-0xED, 0xF4, 0xFC and 0xFD hang the CPU for good,
+            // interrupts included. PC stays on the opcode so debuggers show where it happened.
+            _ => {
+                println!(
+                    "Illegal opcode 0x{:02X} at PC={:04X}, the CPU has locked up",
+                    opcode, opcode_pc
+                );
+                self.pc = opcode_pc;
+                self.locked = true;
+                4
+            }
         }
     }
 
@@ -0,0 +1,118 @@
+use crate::consts::{CB_OPCODES, OPCODES};
+use crate::gb::GB;
+
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const R16: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const R16_STACK: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const R16_MEM: [&str; 4] = ["(BC)", "(DE)", "(HL+)", "(HL-)"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const CB_SHIFTS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+// Mnemonic with operands, e.g. "LD B,0x12" or "JP NZ,0x4000". arg_u8/arg_u16 are the bytes
+// after the opcode, as CPU::execute fetches them; for 0xCB, arg_u8 is the CB opcode. Relative
+// jumps show the signed offset from the next instruction.
+pub fn disassemble(opcode: u8, arg_u8: u8, arg_u16: u16) -> String {
+    let n8 = format!("0x{:02X}", arg_u8);
+    let n16 = format!("0x{:04X}", arg_u16);
+    let e8 = arg_u8 as i8;
+    // the regular blocks: register fields in bits 0-2 and 3-5, pairs in bits 4-5
+    let r8_low = R8[(opcode & 0b111) as usize];
+    let r8_mid = R8[(opcode >> 3 & 0b111) as usize];
+    let r16 = (opcode >> 4 & 0b11) as usize;
+    let condition = CONDITIONS[(opcode >> 3 & 0b11) as usize];
+
+    match opcode {
+        0x00 => "NOP".to_string(),
+        0x10 => "STOP".to_string(),
+        0x76 => "HALT".to_string(),
+        0xCB => disassemble_cb(arg_u8),
+        0x40..=0x7F => format!("LD {},{}", r8_mid, r8_low),
+        0x80..=0xBF => format!("{}{}", ALU[(opcode >> 3 & 0b111) as usize], r8_low),
+
+        0x01 | 0x11 | 0x21 | 0x31 => format!("LD {},{}", R16[r16], n16),
+        0x02 | 0x12 | 0x22 | 0x32 => format!("LD {},A", R16_MEM[r16]),
+        0x0A | 0x1A | 0x2A | 0x3A => format!("LD A,{}", R16_MEM[r16]),
+        0x03 | 0x13 | 0x23 | 0x33 => format!("INC {}", R16[r16]),
+        0x0B | 0x1B | 0x2B | 0x3B => format!("DEC {}", R16[r16]),
+        0x09 | 0x19 | 0x29 | 0x39 => format!("ADD HL,{}", R16[r16]),
+        0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => format!("INC {}", r8_mid),
+        0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => format!("DEC {}", r8_mid),
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => format!("LD {},{}", r8_mid, n8),
+        0x07 => "RLCA".to_string(),
+        0x0F => "RRCA".to_string(),
+        0x17 => "RLA".to_string(),
+        0x1F => "RRA".to_string(),
+        0x27 => "DAA".to_string(),
+        0x2F => "CPL".to_string(),
+        0x37 => "SCF".to_string(),
+        0x3F => "CCF".to_string(),
+        0x08 => format!("LD ({}),SP", n16),
+        0x18 => format!("JR {:+}", e8),
+        0x20 | 0x28 | 0x30 | 0x38 => format!("JR {},{:+}", condition, e8),
+
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => format!("RET {}", condition),
+        0xC2 | 0xCA | 0xD2 | 0xDA => format!("JP {},{}", condition, n16),
+        0xC4 | 0xCC | 0xD4 | 0xDC => format!("CALL {},{}", condition, n16),
+        0xC1 | 0xD1 | 0xE1 | 0xF1 => format!("POP {}", R16_STACK[r16]),
+        0xC5 | 0xD5 | 0xE5 | 0xF5 => format!("PUSH {}", R16_STACK[r16]),
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+            format!("RST 0x{:02X}", opcode & 0b0011_1000)
+        }
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
+            format!("{}{}", ALU[(opcode >> 3 & 0b111) as usize], n8)
+        }
+        0xC3 => format!("JP {}", n16),
+        0xC9 => "RET".to_string(),
+        0xCD => format!("CALL {}", n16),
+        0xD9 => "RETI".to_string(),
+        0xE0 => format!("LDH (0xFF{:02X}),A", arg_u8),
+        0xF0 => format!("LDH A,(0xFF{:02X})", arg_u8),
+        0xE2 => "LD (0xFF00+C),A".to_string(),
+        0xF2 => "LD A,(0xFF00+C)".to_string(),
+        0xE8 => format!("ADD SP,{:+}", e8),
+        0xF8 => format!("LD HL,SP{:+}", e8),
+        0xE9 => "JP HL".to_string(),
+        0xF9 => "LD SP,HL".to_string(),
+        0xEA => format!("LD ({}),A", n16),
+        0xFA => format!("LD A,({})", n16),
+        0xF3 => "DI".to_string(),
+        0xFB => "EI".to_string(),
+        // 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB-0xED, 0xF4, 0xFC, 0xFD lock up the CPU
+        _ => format!("DB 0x{:02X}", opcode),
+    }
+}
+
+fn disassemble_cb(cb_opcode: u8) -> String {
+    let r8 = R8[(cb_opcode & 0b111) as usize];
+    let bit = cb_opcode >> 3 & 0b111;
+    match cb_opcode {
+        0x00..=0x3F => format!("{} {}", CB_SHIFTS[bit as usize], r8),
+        0x40..=0x7F => format!("BIT {},{}", bit, r8),
+        0x80..=0xBF => format!("RES {},{}", bit, r8),
+        0xC0..=0xFF => format!("SET {},{}", bit, r8),
+    }
+}
+
+// The instruction at addr with its raw bytes, e.g. "C3 50 01  JP 0x0150", reading through the
+// MMU. The length comes from the OPCODES/CB_OPCODES tables.
+pub fn disassemble_at(gb: &mut GB, addr: u16) -> String {
+    let mut read = |offset: u16| {
+        gb.mmu.read_byte(addr.wrapping_add(offset), &gb.cart, &gb.joypad, &mut gb.apu)
+    };
+    let opcode = read(0);
+    let arg_u8 = read(1);
+    let arg_u16 = (read(2) as u16) << 8 | arg_u8 as u16;
+
+    let len = if opcode == 0xCB {
+        CB_OPCODES[arg_u8 as usize].bytes
+    } else {
+        OPCODES[opcode as usize].bytes
+    };
+    let raw = [opcode, arg_u8, (arg_u16 >> 8) as u8][..len as usize]
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<String>>()
+        .join(" ");
+    format!("{:<8}  {}", raw, disassemble(opcode, arg_u8, arg_u16))
+}
@@ -1,14 +1,118 @@
 use crate::apu::APU;
 use crate::cart::Cart;
-use crate::consts::{CB_OPCODES, CYCLES_PER_FRAME, OPCODES};
-use crate::cpu::CPU;
+use crate::consts::{
+    CB_OPCODES, CGB_DMG_MODE_DIV, CGB_IO_INIT, CYCLES_PER_FRAME, DMG_IO_INIT, OPCODES,
+};
+use crate::cpu::{InterruptBit, CPU};
 use crate::joypad::Joypad;
-use crate::mmu::MMU;
-use crate::ppu::PPU;
-use ringbuf::HeapProd;
+use crate::link::Link;
+use crate::mmu::{VramHeatmap, BOOT_ROM_SIZE, MMU};
+use crate::ppu::{PPUMemory, PPU};
+use ringbuf::{traits::Split, HeapProd, HeapRb};
 use std::fs;
-use std::io::Cursor;
+use std::io::{Cursor, Read};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use zip::ZipArchive;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const HEADLESS_SAMPLE_RATE: f32 = 44100.0;
+// Savestates start with this magic and a format version, bumped whenever a serialized struct
+// changes so old states are refused instead of misread
+const STATE_MAGIC: &[u8; 4] = b"DMST";
+const STATE_VERSION: u32 = 10;
+const LINK_POLL_CYCLES: u32 = 456; // check the link cable once per scanline
+const FNV_OFFSET: u64 = 0xCBF29CE484222325;
+
+// Reads a ROM file, extracting it first if it's a zip archive (by extension or magic bytes)
+pub fn read_rom(rom_path: &str) -> Result<Vec<u8>, String> {
+    let bytes = fs::read(rom_path).map_err(|e| format!("Unable to read {}: {}", rom_path, e))?;
+    if !rom_path.to_ascii_lowercase().ends_with(".zip") && !bytes.starts_with(ZIP_MAGIC) {
+        return Ok(bytes);
+    }
+
+    let mut archive = ZipArchive::new(Cursor::new(bytes))
+        .map_err(|e| format!("{} is not a valid zip archive: {}", rom_path, e))?;
+    let rom_name = archive
+        .file_names()
+        .find(|name| {
+            let name = name.to_ascii_lowercase();
+            name.ends_with(".gb") || name.ends_with(".gbc")
+        })
+        .map(|name| name.to_string());
+    let Some(rom_name) = rom_name else {
+        let names: Vec<&str> = archive.file_names().collect();
+        return Err(format!("No .gb/.gbc ROM in {} (contains: {})", rom_path, names.join(", ")));
+    };
+
+    let mut rom = Vec::new();
+    archive
+        .by_name(&rom_name)
+        .and_then(|mut file| Ok(file.read_to_end(&mut rom)?))
+        .map_err(|e| format!("Unable to extract {} from {}: {}", rom_name, rom_path, e))?;
+    Ok(rom)
+}
+
+fn savestate_path(rom_path: &str, slot: Option<u8>) -> PathBuf {
+    let mut path = PathBuf::from(Path::new(rom_path));
+    match slot {
+        Some(slot) => path.set_extension(format!("st{}", slot)),
+        None => path.set_extension("st"),
+    };
+    path
+}
+
+// The hardware being emulated, set with --model. The boot ROMs leave different register values
+// behind, which games check to detect a CGB (A = 0x11). Only a CGB runs CGB games in color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    Mgb, // Game Boy Pocket
+    Cgb,
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "cgb" => Ok(Model::Cgb),
+            _ => Err(format!("unknown model '{}' (expected dmg, mgb or cgb)", s)),
+        }
+    }
+}
+
+impl Model {
+    // CGB-aware carts get a CGB, everything else the original DMG
+    pub fn default_for(cart: &Cart) -> Model {
+        if cart.is_cgb_compatible() {
+            Model::Cgb
+        } else {
+            Model::Dmg
+        }
+    }
+}
+
+// Lightweight CPU/PPU state handed to the VBlank callback alongside the framebuffer
+#[derive(Clone, Copy, Debug)]
+pub struct VBlankSnapshot {
+    pub frame: u64,
+    pub pc: u16,
+    pub sp: u16,
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub ime: bool,
+    pub halted: bool,
+    pub lcdc: u8,
+    pub scx: u8,
+    pub scy: u8,
+}
+
+pub type VBlankCallback = Box<dyn FnMut(&[u8; 144 * 160], &VBlankSnapshot)>;
 
 pub struct GB {
     pub apu: APU,
@@ -17,26 +121,138 @@ pub struct GB {
     pub ppu: PPU,
     pub cart: Cart,
     pub joypad: Joypad,
-    pub current_cycles: u32,
+    pub current_cycles: u32, // cycles run past the last frame budget, see run_for
+    pub total_cycles: u64,   // single-speed cycles since power-on
+    pub force_loadstate: bool, // load savestates taken from a different ROM
+    vblank_callback: Option<VBlankCallback>,
+    link: Option<Link>,
+    link_cycles: u32,
 }
 
 impl GB {
-    pub fn new(rom_path: &String, sink: HeapProd<f32>, sample_rate: f32) -> GB {
-        let rom = fs::read(&rom_path).expect("Error: Unable to read the file");
-        return GB {
+    pub fn new(rom_path: &str, sink: HeapProd<f32>, sample_rate: f32) -> GB {
+        let rom = read_rom(rom_path).unwrap_or_else(|e| panic!("Error: {}", e));
+        let cart = Cart::from_rom(rom);
+        let model = Model::default_for(&cart);
+
+        let mut gb = GB {
             apu: APU::new(sink, sample_rate),
             cpu: CPU::new(),
             mmu: MMU::new(),
             ppu: PPU::new(),
-            cart: Cart::from_rom(rom),
+            cart,
             joypad: Joypad::new(),
             current_cycles: 0,
+            total_cycles: 0,
+            force_loadstate: false,
+            vblank_callback: None,
+            link: None,
+            link_cycles: 0,
         };
+        gb.set_model(model);
+        gb
+    }
+
+    // Switches to another model's post-boot CPU and IO registers and, for CGB-aware carts on a
+    // CGB, to CGB mode. GB::new picks Model::default_for the cart; call this before running.
+    pub fn set_model(&mut self, model: Model) {
+        if self.cart.cgb_flag == 0xC0 && model != Model::Cgb {
+            println!("Warning: {} only runs on a CGB", self.cart.title);
+        }
+        self.mmu.cgb = model == Model::Cgb && self.cart.is_cgb_compatible();
+        self.mmu.serial.cgb = self.mmu.cgb;
+        self.cpu = CPU::for_model(model, self.mmu.cgb);
+
+        self.mmu.init_io(DMG_IO_INIT);
+        if model == Model::Cgb {
+            self.mmu.init_io(CGB_IO_INIT);
+            if !self.mmu.cgb {
+                self.mmu.ram[0xFF04] = CGB_DMG_MODE_DIV;
+            }
+        }
+    }
+
+    // For test harnesses and tools: no audio device is attached, so the APU's samples are
+    // dropped once its one-sample buffer fills. Drive it with step() or run_until_vblank().
+    pub fn new_headless(rom_path: &str) -> GB {
+        let (producer, _consumer) = HeapRb::<f32>::new(1).split();
+        GB::new(rom_path, producer, HEADLESS_SAMPLE_RATE)
+    }
+
+    // Maps a 256-byte DMG boot ROM over 0x0000-0x00FF and starts from power-on (PC 0x0000,
+    // registers zeroed, LCD off) so it plays the logo and hands over to the game itself.
+    // Call before running; without one the CPU starts at 0x100 in the post-boot state.
+    pub fn load_boot_rom(&mut self, path: &str) -> Result<(), String> {
+        let boot_rom =
+            fs::read(path).map_err(|e| format!("Unable to read boot ROM {}: {}", path, e))?;
+        if boot_rom.len() != BOOT_ROM_SIZE {
+            return Err(format!(
+                "{} is {} bytes, a DMG boot ROM is {}",
+                path,
+                boot_rom.len(),
+                BOOT_ROM_SIZE
+            ));
+        }
+
+        self.mmu.boot_rom = boot_rom;
+        self.mmu.ram[PPUMemory::LCDC as usize] = 0x00;
+        self.mmu.ram[0xFF04] = 0x00; // DIV
+        self.cpu = CPU {
+            a: 0,
+            f: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            pc: 0x0000,
+            sp: 0x0000,
+            ..CPU::new()
+        };
+        Ok(())
+    }
+
+    // Registers a callback run at every VBlank with the finished frame, for external tools.
+    // It runs synchronously inside step(), so emulation waits until it returns: keep it short
+    // and hand heavy work off to another thread. Without a callback the check is a single
+    // branch per step.
+    pub fn with_vblank_callback(
+        mut self,
+        callback: impl FnMut(&[u8; 144 * 160], &VBlankSnapshot) + 'static,
+    ) -> GB {
+        self.vblank_callback = Some(Box::new(callback));
+        self
+    }
+
+    fn vblank_snapshot(&self) -> VBlankSnapshot {
+        let cpu = &self.cpu;
+        let pair = |hi: u8, lo: u8| (hi as u16) << 8 | lo as u16;
+        VBlankSnapshot {
+            frame: self.ppu.frame_count,
+            pc: cpu.pc,
+            sp: cpu.sp,
+            af: pair(cpu.a, cpu.f),
+            bc: pair(cpu.b, cpu.c),
+            de: pair(cpu.d, cpu.e),
+            hl: pair(cpu.h, cpu.l),
+            ime: cpu.ime,
+            halted: cpu.halted,
+            lcdc: self.mmu.ram[PPUMemory::LCDC as usize],
+            scx: self.mmu.ram[PPUMemory::SCX as usize],
+            scy: self.mmu.ram[PPUMemory::SCY as usize],
+        }
     }
 
-    pub fn step(&mut self) {
+    // Executes one instruction (or one halted/stopped M-cycle), services interrupts and advances
+    // the timers, PPU, APU and serial port by the same cycles. Returns the elapsed time in
+    // single-speed cycles: in CGB double speed the PPU and APU only see half the CPU's cycles.
+    pub fn step(&mut self) -> u32 {
+        let pc = self.cpu.pc;
         let instruction = self.mmu.read_byte(self.cpu.pc, &self.cart, &self.joypad, &mut self.apu);
 
+        // an EI before this instruction takes effect after it, unless it was a DI
+        let ime_pending = self.cpu.ime_pending;
         let instruction_cycles = self.cpu.execute(
             instruction,
             &mut self.mmu,
@@ -44,62 +260,255 @@ impl GB {
             &mut self.joypad,
             &mut self.apu,
         );
-        self.cpu.check_interrupts(&mut self.mmu, &mut self.cart, &mut self.joypad, &mut self.apu);
+        if ime_pending && self.cpu.ime_pending {
+            self.cpu.ime = true;
+            self.cpu.ime_pending = false;
+        }
+        let interrupt_cycles = self.cpu.check_interrupts(
+            &mut self.mmu,
+            &mut self.cart,
+            &mut self.joypad,
+            &mut self.apu,
+        );
+        // a VRAM DMA started by the instruction (or at the last HBlank) holds the CPU meanwhile
+        let cycles = instruction_cycles as u32 + interrupt_cycles + self.mmu.take_hdma_stall();
+        let dots = if self.mmu.double_speed() { cycles / 2 } else { cycles };
         self.cpu.update_timers(
-            instruction_cycles as u32,
+            cycles,
             &mut self.mmu,
             &mut self.cart,
             &mut self.joypad,
             &mut self.apu,
         );
+        let frame_count = self.ppu.frame_count;
         self.ppu.update(
-            instruction_cycles as u32,
+            dots,
             &mut self.mmu,
             &mut self.cpu,
             &mut self.cart,
             &mut self.joypad,
             &mut self.apu,
         );
-        self.apu.update(instruction_cycles as u32);
+        if self.vblank_callback.is_some() && self.ppu.frame_count != frame_count {
+            let snapshot = self.vblank_snapshot();
+            if let Some(callback) = &mut self.vblank_callback {
+                callback(self.ppu.framebuffer(), &snapshot);
+            }
+        }
+        if let Some(watch) = &mut self.mmu.write_watch {
+            watch.triggered |= !watch.hits.is_empty();
+            for (addr, val) in watch.hits.drain(..) {
+                println!("Watch: PC {:04X} wrote {:02X} to {:04X}", pc, val, addr);
+            }
+        }
+        if let Some(heatmap) = &mut self.mmu.vram_heatmap {
+            heatmap.frame = self.ppu.frame_count;
+        }
+        self.mmu.update_dma(cycles, &self.cart);
+        self.apu.update(dots);
+        self.update_link(dots);
+        if self.mmu.serial.update(cycles) {
+            self.cpu.request_interrupt(
+                InterruptBit::Serial,
+                &mut self.mmu,
+                &mut self.cart,
+                &mut self.joypad,
+                &mut self.apu,
+            );
+        }
+
+        if self.joypad.update() {
+            self.cpu.request_interrupt(
+                InterruptBit::Joypad,
+                &mut self.mmu,
+                &mut self.cart,
+                &mut self.joypad,
+                &mut self.apu,
+            );
+        }
+
+        self.current_cycles += dots;
+        self.total_cycles += dots as u64;
+        dots
+    }
+
+    // Runs instructions until `budget` cycles have passed, calling before_step ahead of each
+    // one. An instruction can't be split, so the last one usually overshoots: the excess stays
+    // in current_cycles and comes off the next budget, the PPU and timers having already run
+    // it. Over any number of calls the emulated time matches the summed budgets to within one
+    // instruction.
+    pub fn run_for(&mut self, budget: u32, mut before_step: impl FnMut(&mut GB)) {
+        while self.current_cycles < budget {
+            before_step(self);
+            self.step();
+        }
+        self.current_cycles -= budget;
+    }
+
+    // Runs until the PPU enters VBlank and returns the completed framebuffer (shades, 160x144).
+    // Embedders should call this once per frame at FRAME_RATE (~59.7 Hz) for real-time speed.
+    // Set input on `joypad` before the call: it's read whenever the game polls P1 during the
+    // frame, which for most games is inside the VBlank handler right after this returns.
+    pub fn run_until_vblank(&mut self) -> &[u8] {
+        let mut cycles = 0;
+        // with the LCD off the PPU never reaches VBlank, so give up after one frame's worth
+        while !self.ppu.frame_ready && cycles < CYCLES_PER_FRAME {
+            cycles += self.step();
+        }
+        self.ppu.frame_ready = false;
+        self.current_cycles = self.current_cycles.saturating_sub(cycles);
+
+        self.ppu.framebuffer()
+    }
+
+    // Runs exactly `frames` frames through run_until_vblank, for benchmarks and snapshots
+    pub fn run_frames(&mut self, frames: u64) {
+        for _ in 0..frames {
+            self.run_until_vblank();
+        }
+    }
+
+    // 64-bit FNV-1a over the last completed frame in row order: the shade indices (0-3), or the
+    // little-endian BGR555 colors in CGB mode. Doesn't depend on palettes or the platform, so a
+    // known-good value after a fixed number of frames catches PPU regressions.
+    pub fn framebuffer_hash(&self) -> u64 {
+        let fnv = |hash: u64, byte: u8| (hash ^ byte as u64).wrapping_mul(0x100000001B3);
+        if self.mmu.cgb {
+            self.ppu.colors().iter().flat_map(|color| color.to_le_bytes()).fold(FNV_OFFSET, fnv)
+        } else {
+            self.ppu.framebuffer().iter().copied().fold(FNV_OFFSET, fnv)
+        }
+    }
 
-        self.current_cycles += instruction_cycles as u32;
+    // Plugs in a link cable, serial transfers are exchanged with the other end from now on
+    pub fn connect_link(&mut self, link: Link) {
+        self.mmu.serial.linked = true;
+        self.link = Some(link);
     }
 
-    pub fn savestate(&self, rom_path: &String) {
-        let mut path = PathBuf::from(Path::new(rom_path));
-        path.set_extension("st");
+    fn update_link(&mut self, cycles: u32) {
+        let Some(link) = &mut self.link else {
+            return;
+        };
+        self.link_cycles += cycles;
+        if self.link_cycles < LINK_POLL_CYCLES {
+            return;
+        }
+        self.link_cycles = 0;
+        if let Err(e) = link.update(&mut self.mmu.serial) {
+            println!("Link cable disconnected: {}", e);
+            self.mmu.serial.unlink();
+            self.link = None;
+        }
+    }
+
+    // Bytes the game has sent over the serial port, e.g. Blargg's "Passed"/"Failed" reports
+    pub fn serial_output(&self) -> &[u8] {
+        &self.mmu.serial.output
+    }
+
+    // Starts or stops tracking VRAM tile writes for the tile viewer overlay
+    pub fn set_vram_heatmap(&mut self, enabled: bool) {
+        self.mmu.vram_heatmap = if enabled { Some(Box::new(VramHeatmap::new())) } else { None };
+    }
 
-        let mut bytes = Vec::new();
+    // Copies bytes into memory starting at addr, wrapping at 0xFFFF. This bypasses the MBC and
+    // ROM write protection (ROM and cart RAM are patched where the CPU currently reads addr
+    // from), so it's meant for test setup and tooling, not for emulated writes.
+    pub fn load_bytes(&mut self, addr: u16, bytes: &[u8]) {
+        for (offset, &byte) in bytes.iter().enumerate() {
+            let addr = addr.wrapping_add(offset as u16);
+            let target = match addr {
+                0x0000..=0x7FFF => {
+                    let offset = self.cart.rom_offset(addr);
+                    self.cart.rom.get_mut(offset)
+                }
+                0xA000..=0xBFFF => {
+                    self.cart.banked_ram_index(addr).and_then(|index| self.cart.ram.get_mut(index))
+                }
+                _ => self.mmu.ram.get_mut(addr as usize),
+            };
+            // carts without RAM have nothing to patch
+            if let Some(target) = target {
+                *target = byte;
+            }
+        }
+    }
+
+    // The whole machine: CPU registers, MMU RAM and IO, PPU mode and cycle counters and the
+    // cart's RAM and bank selections. Slots 0-3 get their own files (.st0-.st3) next to the
+    // default .st state.
+    pub fn savestate(&self, rom_path: &str, slot: Option<u8>) -> Result<(), String> {
+        let path = savestate_path(rom_path, slot);
+
+        let mut bytes = STATE_MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, &STATE_VERSION).expect("serialize state version");
+        bincode::serialize_into(&mut bytes, &self.cart.rom_hash()).expect("serialize rom hash");
         bincode::serialize_into(&mut bytes, &self.cpu).expect("serialize cpu");
         bincode::serialize_into(&mut bytes, &self.ppu).expect("serialize ppu");
         bincode::serialize_into(&mut bytes, &self.mmu).expect("serialize mmu");
         bincode::serialize_into(&mut bytes, &self.cart).expect("serialize cart");
+        bincode::serialize_into(&mut bytes, &self.apu).expect("serialize apu");
 
-        fs::write(&path, &bytes).expect("Failed to write savestate file");
+        fs::write(&path, &bytes)
+            .map_err(|e| format!("Savestate save failed ({}): {}", path.display(), e))?;
         println!("Savestate saved: {}", path.display());
+        Ok(())
     }
 
-    pub fn loadstate(&mut self, rom_path: &String) {
-        let mut path = PathBuf::from(Path::new(rom_path));
-        path.set_extension("st");
+    // Every part is parsed before any of it is applied, so a truncated or corrupt file leaves
+    // the running game untouched
+    pub fn loadstate(&mut self, rom_path: &str, slot: Option<u8>) -> Result<(), String> {
+        let path = savestate_path(rom_path, slot);
+        let fail = |e: &dyn std::fmt::Display| {
+            format!("Savestate load failed ({}): {}", path.display(), e)
+        };
+
+        let bytes = fs::read(&path).map_err(|e| fail(&e))?;
+        if !bytes.starts_with(STATE_MAGIC) {
+            return Err(fail(&"not a savestate"));
+        }
+        let mut cursor = Cursor::new(&bytes[STATE_MAGIC.len()..]);
+        let version: u32 = bincode::deserialize_from(&mut cursor).map_err(|e| fail(&e))?;
+        if version != STATE_VERSION {
+            return Err(fail(&format!(
+                "format version {}, this build reads version {}",
+                version, STATE_VERSION
+            )));
+        }
 
-        let bytes = match fs::read(&path) {
-            Ok(b) => b,
-            Err(e) => {
-                println!("Savestate load failed ({}): {}", path.display(), e);
-                return;
+        // states are only valid for the exact ROM they were taken from
+        let rom_hash: u64 = bincode::deserialize_from(&mut cursor).map_err(|e| fail(&e))?;
+        if rom_hash != self.cart.rom_hash() {
+            if !self.force_loadstate {
+                return Err(fail(
+                    &"taken from a different ROM, pass --force-loadstate to load it anyway",
+                ));
             }
-        };
-        let mut cursor = Cursor::new(bytes);
+            println!("Warning: loading a savestate taken from a different ROM");
+        }
 
-        self.cpu = bincode::deserialize_from(&mut cursor).expect("deserialize cpu");
-        self.ppu = bincode::deserialize_from(&mut cursor).expect("deserialize ppu");
-        self.mmu = bincode::deserialize_from(&mut cursor).expect("deserialize mmu");
+        let mut deserialize = || -> bincode::Result<(CPU, PPU, MMU, Cart, APU)> {
+            Ok((
+                bincode::deserialize_from(&mut cursor)?,
+                bincode::deserialize_from(&mut cursor)?,
+                bincode::deserialize_from(&mut cursor)?,
+                bincode::deserialize_from(&mut cursor)?,
+                bincode::deserialize_from(&mut cursor)?,
+            ))
+        };
+        let (cpu, mut ppu, mut mmu, mut cart, apu) = deserialize().map_err(|e| fail(&e))?;
 
-        let rom = std::mem::take(&mut self.cart.rom);
-        self.cart = bincode::deserialize_from(&mut cursor).expect("deserialize cart");
-        self.cart.rom = rom;
+        self.cpu = cpu;
+        ppu.accurate = self.ppu.accurate;
+        self.ppu = ppu;
+        mmu.serial.linked = self.mmu.serial.linked;
+        self.mmu = mmu;
+        cart.rom = std::mem::take(&mut self.cart.rom);
+        self.cart = cart;
+        self.apu.load_state(apu);
 
         println!("Savestate loaded: {}", path.display());
+        Ok(())
     }
 }
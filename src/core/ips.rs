@@ -0,0 +1,63 @@
+use std::fs;
+use std::io;
+
+use crate::gb::GB;
+
+// Work RAM regions included in the diff, as CPU addresses
+const WRAM: std::ops::RangeInclusive<usize> = 0xC000..=0xDFFF;
+const HRAM: std::ops::RangeInclusive<usize> = 0xFF80..=0xFFFE;
+// cart RAM is diffed by byte index (all banks), offset past the 16-bit address space
+pub const CART_RAM_OFFSET: usize = 0x10000;
+
+// Snapshot of work RAM and cart RAM, diffed against the live state by --ips-out.
+//
+// The output is a standard IPS patch: "PATCH", then records of a 3-byte big-endian offset,
+// a 2-byte big-endian length and that many bytes of new data, then "EOF". Offsets below
+// 0x10000 are CPU addresses in WRAM (C000-DFFF) or HRAM (FF80-FFFE); offsets from 0x10000
+// on are 0x10000 + the byte index into the cartridge RAM.
+pub struct RamBaseline {
+    ram: Vec<u8>,
+    cart_ram: Vec<u8>,
+}
+
+impl RamBaseline {
+    pub fn capture(gb: &GB) -> RamBaseline {
+        RamBaseline { ram: gb.mmu.ram.clone(), cart_ram: gb.cart.ram.clone() }
+    }
+
+    // writes the patch and returns how many bytes differ
+    pub fn write_ips(&self, gb: &GB, path: &str) -> io::Result<usize> {
+        let mut changes: Vec<(usize, u8)> = Vec::new();
+        for addr in WRAM.chain(HRAM) {
+            if gb.mmu.ram[addr] != self.ram[addr] {
+                changes.push((addr, gb.mmu.ram[addr]));
+            }
+        }
+        for (index, (&now, &then)) in gb.cart.ram.iter().zip(&self.cart_ram).enumerate() {
+            if now != then {
+                changes.push((CART_RAM_OFFSET + index, now));
+            }
+        }
+
+        let mut patch = b"PATCH".to_vec();
+        let mut i = 0;
+        while i < changes.len() {
+            // merge runs of consecutive changed bytes into one record
+            let start = changes[i].0;
+            let mut end = i + 1;
+            while end < changes.len() && changes[end].0 == start + (end - i) {
+                end += 1;
+            }
+
+            let length = end - i;
+            patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+            patch.extend_from_slice(&(length as u16).to_be_bytes());
+            patch.extend(changes[i..end].iter().map(|&(_, byte)| byte));
+            i = end;
+        }
+        patch.extend_from_slice(b"EOF");
+
+        fs::write(path, patch)?;
+        Ok(changes.len())
+    }
+}
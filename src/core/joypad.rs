@@ -1,4 +1,6 @@
-#[derive(Clone, Copy)]
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum JoypadButton {
     Right,
     Left,
@@ -10,6 +12,24 @@ pub enum JoypadButton {
     Start,
 }
 
+impl FromStr for JoypadButton {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "right" => Ok(JoypadButton::Right),
+            "left" => Ok(JoypadButton::Left),
+            "up" => Ok(JoypadButton::Up),
+            "down" => Ok(JoypadButton::Down),
+            "a" => Ok(JoypadButton::A),
+            "b" => Ok(JoypadButton::B),
+            "select" => Ok(JoypadButton::Select),
+            "start" => Ok(JoypadButton::Start),
+            _ => Err(format!("unknown button '{}'", s)),
+        }
+    }
+}
+
 pub const SELECT_BUTTON_BIT: u8 = 0x20;
 pub const SELECT_DIRECTION_BIT: u8 = 0x10;
 
@@ -27,11 +47,19 @@ pub struct Joypad {
 
     direction_buttons: u8,
     action_buttons: u8,
+
+    lines: u8, // selected P10-P13 input lines as of the last update, for edge detection
+}
+
+impl Default for Joypad {
+    fn default() -> Joypad {
+        Joypad::new()
+    }
 }
 
 impl Joypad {
     pub fn new() -> Joypad {
-        Joypad { select_buttons: 0x30, direction_buttons: 0x0F, action_buttons: 0x0F }
+        Joypad { select_buttons: 0x30, direction_buttons: 0x0F, action_buttons: 0x0F, lines: 0x0F }
     }
 
     pub fn press_button(&mut self, button: JoypadButton) {
@@ -62,6 +90,11 @@ impl Joypad {
         }
     }
 
+    // buttons are active low, so any cleared bit means a button is held
+    pub fn any_pressed(&self) -> bool {
+        self.direction_buttons & 0x0F != 0x0F || self.action_buttons & 0x0F != 0x0F
+    }
+
     pub fn read(&self) -> u8 {
         let mut result: u8 = 0xFF;
 
@@ -74,6 +107,16 @@ impl Joypad {
         return result;
     }
 
+    // Returns true when a selected input line goes from high to low since the last call, which
+    // is what requests the joypad interrupt. Holding a button keeps the line low, so it only
+    // fires once on the initial press (or when a held button's group gets selected).
+    pub fn update(&mut self) -> bool {
+        let lines = self.read() & 0x0F;
+        let falling = self.lines & !lines != 0;
+        self.lines = lines;
+        falling
+    }
+
     pub fn write(&mut self, value: u8) {
         self.select_buttons = value & 0x30;
     }
@@ -1,39 +1,416 @@
 use crate::apu::APU;
-use crate::cart::Cart;
-use crate::consts::DMG0_IO_INIT;
+use crate::cart::{Cart, RTC_FOOTER_LEN};
+use crate::consts::DMG_IO_INIT;
 use crate::joypad::Joypad;
+use crate::ppu::PPUMode;
+use crate::serial::{Serial, SB, SC};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::Read;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
+// Snapshot of the banking state, for the debugger and info displays
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryMapInfo {
+    pub rom_bank: u16,
+    pub ram_bank: u8,
+    pub ram_enabled: bool,
+    pub banking_mode: bool,
+    pub boot_rom_mapped: bool,
+    pub dma_active: bool,
+}
+
+pub const TILE_DATA_START: u16 = 0x8000;
+pub const TILE_DATA_END: u16 = 0x97FF;
+pub const TILE_COUNT: usize = 384; // 16 bytes each
+pub const HEATMAP_FADE_FRAMES: u64 = 60;
+
+// Frame of the last write to each tile's data, so a tile viewer can tint recently changed tiles.
+// Only allocated while enabled, so tracking costs a None check per VRAM write when off.
+pub struct VramHeatmap {
+    pub frame: u64, // advanced by GB::step at each VBlank
+    last_write: [Option<u64>; TILE_COUNT],
+}
+
+impl Default for VramHeatmap {
+    fn default() -> VramHeatmap {
+        VramHeatmap::new()
+    }
+}
+
+impl VramHeatmap {
+    pub fn new() -> VramHeatmap {
+        VramHeatmap { frame: 0, last_write: [None; TILE_COUNT] }
+    }
+
+    pub fn record_write(&mut self, addr: u16) {
+        self.last_write[(addr - TILE_DATA_START) as usize / 16] = Some(self.frame);
+    }
+
+    // 1.0 for a tile written this frame, fading to 0.0 over HEATMAP_FADE_FRAMES
+    pub fn heat(&self, tile: usize) -> f32 {
+        match self.last_write[tile] {
+            Some(frame) => {
+                let age = (self.frame - frame).min(HEATMAP_FADE_FRAMES);
+                1.0 - age as f32 / HEATMAP_FADE_FRAMES as f32
+            }
+            None => 0.0,
+        }
+    }
+}
+
+pub const VRAM_START: u16 = 0x8000;
+pub const VRAM_END: u16 = 0x9FFF;
+pub const OAM_START: u16 = 0xFE00;
+pub const OAM_END: u16 = 0xFE9F;
+
+// mirrors work RAM at 0xC000-0xDDFF
+const ECHO_START: u16 = 0xE000;
+const ECHO_END: u16 = 0xFDFF;
+const ECHO_OFFSET: usize = 0x2000;
+
+// unusable on DMG: reads 0x00, or 0xFF while OAM is locked, and writes are ignored
+const PROHIBITED_START: u16 = 0xFEA0;
+const PROHIBITED_END: u16 = 0xFEFF;
+
+// CGB undocumented registers: FF72, FF73 and FF74 are plain read/write bytes, only bits 4-6
+// of FF75 are writable (the rest read as 1), FF76/FF77 are the read-only PCM amplitudes
+const UNDOCUMENTED_START: u16 = 0xFF72;
+const UNDOCUMENTED_END: u16 = 0xFF77;
+const FF75: u16 = 0xFF75;
+const FF75_WRITABLE: u8 = 0b0111_0000;
+const PCM12: u16 = 0xFF76;
+const PCM34: u16 = 0xFF77;
+
+// CGB speed switch register: bit 0 arms a switch for the next STOP, bit 7 is the current speed
+const KEY1: u16 = 0xFF4D;
+const KEY1_ARMED: u8 = 0b0000_0001;
+const KEY1_DOUBLE_SPEED: u8 = 0b1000_0000;
+
+// CGB VRAM bank select, bit 0 maps bank 1 over 0x8000-0x9FFF for the CPU
+const VBK: u16 = 0xFF4F;
+const VRAM_SIZE: usize = 0x2000;
+
+// CGB WRAM bank select, bits 0-2 map bank 1-7 over 0xD000-0xDFFF (0 selects bank 1)
+const SVBK: u16 = 0xFF70;
+const SVBK_BANK: u8 = 0b0000_0111;
+const WRAM_BANK_START: u16 = 0xD000;
+const WRAM_BANK_END: u16 = 0xDFFF;
+const WRAM_BANK_SIZE: usize = 0x1000;
+const WRAM_BANKS: usize = 8;
+
+// CGB palette RAM: a spec register (index in bits 0-5, auto-increment in bit 7) and a data
+// register reading/writing the byte at that index, for BG (BCPS/BCPD) and OBJ (OCPS/OCPD)
+const BCPS: u16 = 0xFF68;
+const BCPD: u16 = 0xFF69;
+const OCPS: u16 = 0xFF6A;
+const OCPD: u16 = 0xFF6B;
+
+// CGB VRAM DMA: HDMA1/HDMA2 are the source, HDMA3/HDMA4 the VRAM destination (both write-only,
+// 16-byte aligned) and HDMA5 starts a transfer of (bits 0-6 + 1) 16-byte blocks
+const HDMA1: u16 = 0xFF51;
+const HDMA2: u16 = 0xFF52;
+const HDMA3: u16 = 0xFF53;
+const HDMA4: u16 = 0xFF54;
+const HDMA5: u16 = 0xFF55;
+const HDMA_BLOCK_LEN: u16 = 0x10;
+const HDMA_BLOCK_CYCLES: u32 = 32; // CPU cycles stalled per block at single speed
+
+pub const BOOT_ROM_SIZE: usize = 0x100;
+const BOOT_ROM_DISABLE: u16 = 0xFF50; // writing bit 0 unmaps the boot ROM for good
+
+const OAM_DMA_CYCLES: u32 = 640; // 160 bytes at one byte per M-cycle
+
+// while the DMA runs the CPU can only read I/O and HRAM, everything below is on its bus
+const DMA_BUS_END: u16 = 0xFEFF;
+
+// 8 palettes of 4 little-endian BGR555 colors
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ColorPalettes {
+    spec: u8,
+    data: Vec<u8>,
+}
+
+impl Default for ColorPalettes {
+    fn default() -> ColorPalettes {
+        ColorPalettes::new()
+    }
+}
+
+impl ColorPalettes {
+    const AUTO_INCREMENT: u8 = 0b1000_0000;
+    const INDEX: u8 = 0b0011_1111;
+
+    pub fn new() -> ColorPalettes {
+        // white, like the CGB boot ROM leaves the BG palettes
+        ColorPalettes { spec: 0, data: vec![0xFF; 64] }
+    }
+
+    fn read_spec(&self) -> u8 {
+        self.spec | 0b0100_0000 // bit 6 is unused
+    }
+
+    fn write_spec(&mut self, val: u8) {
+        self.spec = val & (Self::AUTO_INCREMENT | Self::INDEX);
+    }
+
+    fn read_data(&self) -> u8 {
+        self.data[(self.spec & Self::INDEX) as usize]
+    }
+
+    // a blocked write (mode 3) is dropped but still advances the index
+    fn write_data(&mut self, val: u8, blocked: bool) {
+        let index = self.spec & Self::INDEX;
+        if !blocked {
+            self.data[index as usize] = val;
+        }
+        if self.spec & Self::AUTO_INCREMENT != 0 {
+            self.spec = Self::AUTO_INCREMENT | (index + 1) & Self::INDEX;
+        }
+    }
+
+    // color 0-3 of palette 0-7
+    pub fn color(&self, palette: u8, color: u8) -> u16 {
+        let index = (palette as usize & 0b111) * 8 + color as usize * 2;
+        u16::from_le_bytes([self.data[index], self.data[index + 1]])
+    }
+}
+
+// Bit 7 of HDMA5 starts an HBlank DMA (one block at the start of each HBlank) instead of a
+// general-purpose one (everything at once). Both stall the CPU while they copy.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Hdma {
+    source: u16,
+    dest: u16, // offset into VRAM
+    // bit 7 is set while no HBlank DMA runs, bits 0-6 are the blocks left minus one
+    hdma5: u8,
+    stall_cycles: u32, // owed to the CPU, collected by GB::step
+}
+
+impl Default for Hdma {
+    fn default() -> Hdma {
+        Hdma::new()
+    }
+}
+
+impl Hdma {
+    const IDLE: u8 = 0b1000_0000;
+    const HBLANK: u8 = 0b1000_0000; // in a HDMA5 write
+    const LENGTH: u8 = 0b0111_1111;
+
+    pub fn new() -> Hdma {
+        Hdma { source: 0, dest: 0, hdma5: 0xFF, stall_cycles: 0 }
+    }
+
+    pub fn hblank_active(&self) -> bool {
+        self.hdma5 & Self::IDLE == 0
+    }
+}
+
+// Armed VRAM/OAM address range. Matching CPU writes are queued as (address, value) and
+// reported with the writing PC by GB::step.
+#[derive(Clone, Debug)]
+pub struct WriteWatch {
+    pub start: u16,
+    pub end: u16,
+    pub hits: Vec<(u16, u8)>,
+    pub triggered: bool, // GB::step reported a hit, cleared by whoever stops on it
+}
+
+impl WriteWatch {
+    pub fn new(start: u16, end: u16) -> Result<WriteWatch, String> {
+        let in_vram = VRAM_START <= start && end <= VRAM_END;
+        let in_oam = OAM_START <= start && end <= OAM_END;
+        if start > end || !(in_vram || in_oam) {
+            return Err(format!(
+                "watch range must lie within VRAM ({:04X}-{:04X}) or OAM ({:04X}-{:04X})",
+                VRAM_START, VRAM_END, OAM_START, OAM_END
+            ));
+        }
+        Ok(WriteWatch { start, end, hits: Vec::new(), triggered: false })
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct MMU {
     pub ram: Vec<u8>,
+    pub serial: Serial,
+    #[serde(skip)]
+    pub vram_heatmap: Option<Box<VramHeatmap>>,
+    #[serde(skip)]
+    pub write_watch: Option<WriteWatch>,
+    // the 0xFF46 write starts the transfer, update_dma copies one byte per M-cycle after it
+    dma_source: u16,
+    dma_cycles_remaining: u32,
+    // set by the PPU on every STAT refresh, locks the CPU out of VRAM in mode 3 and OAM in
+    // modes 2 and 3
+    #[serde(skip)]
+    pub ppu_mode: PPUMode,
+    key1: u8,
+    // CGB mode, a CGB running a CGB-compatible cart (see GB::set_model). Enables the
+    // registers below.
+    pub cgb: bool,
+    vram_bank: u8,
+    vram_bank1: Vec<u8>, // bank 0 lives in ram
+    svbk: u8,
+    wram_banks: Vec<u8>, // banks 2-7, bank 1 lives in ram
+    pub bg_palettes: ColorPalettes,
+    pub obj_palettes: ColorPalettes,
+    pub hdma: Hdma,
+    // overlays 0x0000-0x00FF while mapped, empty once the game unmaps it (or without one)
+    pub boot_rom: Vec<u8>,
+}
+
+impl Default for MMU {
+    fn default() -> MMU {
+        MMU::new()
+    }
 }
 
 impl MMU {
     pub fn new() -> MMU {
-        let mut ram = vec![0; 0x10000];
-        for &(addr, val) in DMG0_IO_INIT {
-            ram[addr as usize] = val;
+        let mut mmu = MMU {
+            ram: vec![0; 0x10000],
+            serial: Serial::new(),
+            vram_heatmap: None,
+            write_watch: None,
+            dma_source: 0,
+            dma_cycles_remaining: 0,
+            ppu_mode: PPUMode::HBlank,
+            key1: 0,
+            cgb: false,
+            vram_bank: 0,
+            vram_bank1: vec![0; VRAM_SIZE],
+            svbk: 0,
+            wram_banks: vec![0; (WRAM_BANKS - 2) * WRAM_BANK_SIZE],
+            bg_palettes: ColorPalettes::new(),
+            obj_palettes: ColorPalettes::new(),
+            hdma: Hdma::new(),
+            boot_rom: Vec::new(),
+        };
+        mmu.init_io(DMG_IO_INIT);
+        mmu
+    }
+
+    // Writes a post-boot IO register table straight to RAM, see GB::set_model
+    pub fn init_io(&mut self, registers: &[(u16, u8)]) {
+        for &(addr, val) in registers {
+            self.ram[addr as usize] = val;
         }
-        return MMU { ram };
     }
 
     pub fn read_byte(&self, addr: u16, cart: &Cart, joypad: &Joypad, apu: &mut APU) -> u8 {
         match addr {
+            0x0000..=DMA_BUS_END if self.dma_active() => 0xFF,
+            0x0000..=0x00FF if !self.boot_rom.is_empty() => self.boot_rom[addr as usize],
             0x0..=0x7FFF => cart.read_rom(addr),
-            0xA000..0xBFFF => cart.read_ram(addr), // if this exists
+            0xA000..=0xBFFF => cart.read_ram(addr), // if this exists
+            WRAM_BANK_START..=WRAM_BANK_END => self.read_wram(addr),
+            ECHO_START..=ECHO_END => self.read_wram(addr - ECHO_OFFSET as u16),
             0xFF00 => joypad.read(),
             0xFF10..=0xFF3F => apu.read_register(addr),
-            0xFF01 => 0xFF, // Dummy value for serial data register
+            SB | SC => self.serial.read(addr),
+            // CGB undocumented registers, absent (open bus) outside CGB mode
+            UNDOCUMENTED_START..=UNDOCUMENTED_END if !self.cgb => 0xFF,
+            FF75 => self.ram[addr as usize] | !FF75_WRITABLE,
+            KEY1 if self.cgb => self.key1 | !(KEY1_ARMED | KEY1_DOUBLE_SPEED),
+            PCM12 => apu.pcm_amplitudes().0,
+            PCM34 => apu.pcm_amplitudes().1,
+            OAM_START..=OAM_END if self.oam_blocked() => 0xFF,
+            VRAM_START..=VRAM_END if self.vram_blocked() => 0xFF,
+            VRAM_START..=VRAM_END => self.read_vram_bank(self.vram_bank, addr),
+            VBK if self.cgb => self.vram_bank | 0b1111_1110,
+            SVBK if self.cgb => self.svbk | !SVBK_BANK,
+            BCPS if self.cgb => self.bg_palettes.read_spec(),
+            OCPS if self.cgb => self.obj_palettes.read_spec(),
+            BCPD | OCPD if self.cgb && self.vram_blocked() => 0xFF,
+            HDMA1..=HDMA4 if self.cgb => 0xFF,
+            HDMA5 if self.cgb => self.hdma.hdma5,
+            BCPD if self.cgb => self.bg_palettes.read_data(),
+            OCPD if self.cgb => self.obj_palettes.read_data(),
+            PROHIBITED_START..=PROHIBITED_END => {
+                if self.oam_blocked() {
+                    0xFF
+                } else {
+                    0x00
+                }
+            }
             _ => self.ram[addr as usize],
         }
     }
 
+    // The PPU has its own path to OAM, so it keeps seeing sprites while the CPU is locked out
+    pub fn read_oam(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    // Same for VRAM, the PPU fetches tiles during the mode 3 it blocks the CPU in
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    // CGB tile attributes and tiles in bank 1, bank 0 is read_vram
+    pub fn read_vram_bank(&self, bank: u8, addr: u16) -> u8 {
+        if bank == 0 {
+            self.ram[addr as usize]
+        } else {
+            self.vram_bank1[(addr - VRAM_START) as usize]
+        }
+    }
+
+    // Work RAM as the CPU sees it at 0xC000-0xDFFF, with the SVBK bank at 0xD000-0xDFFF
+    fn read_wram(&self, addr: u16) -> u8 {
+        match self.wram_bank_offset(addr) {
+            Some(offset) => self.wram_banks[offset],
+            None => self.ram[addr as usize],
+        }
+    }
+
+    fn write_wram(&mut self, addr: u16, val: u8) {
+        match self.wram_bank_offset(addr) {
+            Some(offset) => self.wram_banks[offset] = val,
+            None => self.ram[addr as usize] = val,
+        }
+    }
+
+    // where addr lands in wram_banks, None for bank 0 and bank 1 which live in ram
+    fn wram_bank_offset(&self, addr: u16) -> Option<usize> {
+        let bank = self.svbk.max(1) as usize;
+        if bank == 1 || !(WRAM_BANK_START..=WRAM_BANK_END).contains(&addr) {
+            return None;
+        }
+        Some((bank - 2) * WRAM_BANK_SIZE + (addr - WRAM_BANK_START) as usize)
+    }
+
+    fn vram_blocked(&self) -> bool {
+        matches!(self.ppu_mode, PPUMode::VRAM)
+    }
+
+    fn oam_blocked(&self) -> bool {
+        matches!(self.ppu_mode, PPUMode::OAM | PPUMode::VRAM)
+    }
+
+    pub fn dma_active(&self) -> bool {
+        self.dma_cycles_remaining > 0
+    }
+
+    // Copies the bytes the DMA reached during these cycles
+    pub fn update_dma(&mut self, cycles: u32, cart: &Cart) {
+        if !self.dma_active() {
+            return;
+        }
+        let copied = ((OAM_DMA_CYCLES - self.dma_cycles_remaining) / 4) as u16;
+        self.dma_cycles_remaining = self.dma_cycles_remaining.saturating_sub(cycles);
+        let reached = ((OAM_DMA_CYCLES - self.dma_cycles_remaining) / 4) as u16;
+        for i in copied..reached {
+            let val = self.dma_read(self.dma_source + i, cart);
+            self.ram[(OAM_START + i) as usize] = val;
+        }
+    }
+
     pub fn write_byte(
         &mut self,
         addr: u16,
@@ -42,19 +419,139 @@ impl MMU {
         joypad: &mut Joypad,
         apu: &mut APU,
     ) {
+        if let Some(watch) = &mut self.write_watch {
+            if (watch.start..=watch.end).contains(&addr) {
+                watch.hits.push((addr, val));
+            }
+        }
+
         match addr {
-            0x0000..0x7FFF => cart.write_rom(addr, val),
-            0xA000..0xBFFF => cart.write_ram(addr, val),
+            0x0000..=0x7FFF => cart.write_rom(addr, val),
+            0xA000..=0xBFFF => cart.write_ram(addr, val),
+            WRAM_BANK_START..=WRAM_BANK_END => self.write_wram(addr, val),
+            ECHO_START..=ECHO_END => self.write_wram(addr - ECHO_OFFSET as u16, val),
             0xFF00 => joypad.write(val),
-            0xFF10..0xFF3F => apu.write_register(addr, val),
-            0xFF46 => self.oam_dma_transfer(val, cart, joypad, apu),
+            SB | SC => self.serial.write(addr, val),
+            0xFF10..=0xFF3F => apu.write_register(addr, val),
+            0xFF46 => {
+                self.ram[addr as usize] = val;
+                self.oam_dma_transfer(val)
+            }
+            BOOT_ROM_DISABLE => {
+                if val & 0x01 != 0 {
+                    self.boot_rom.clear();
+                }
+                self.ram[addr as usize] = val
+            }
+            UNDOCUMENTED_START..=UNDOCUMENTED_END if !self.cgb => {}
+            FF75 => self.ram[addr as usize] = val & FF75_WRITABLE,
+            KEY1 if self.cgb => self.key1 = (self.key1 & KEY1_DOUBLE_SPEED) | (val & KEY1_ARMED),
+            PCM12 | PCM34 => {} // read-only
+            // the DMA owns OAM until it finishes, and the PPU owns OAM/VRAM in its modes, CPU
+            // writes are dropped
+            OAM_START..=OAM_END if self.dma_active() || self.oam_blocked() => {}
+            VRAM_START..=VRAM_END if self.vram_blocked() => {}
+            VRAM_START..=VRAM_END if self.vram_bank == 1 => {
+                self.vram_bank1[(addr - VRAM_START) as usize] = val
+            }
+            VBK if self.cgb => self.vram_bank = val & 0x01,
+            SVBK if self.cgb => self.svbk = val & SVBK_BANK,
+            HDMA1 if self.cgb => self.hdma.source = (val as u16) << 8 | self.hdma.source & 0xF0,
+            HDMA2 if self.cgb => self.hdma.source = self.hdma.source & 0xFF00 | (val & 0xF0) as u16,
+            HDMA3 if self.cgb => {
+                self.hdma.dest = ((val & 0x1F) as u16) << 8 | self.hdma.dest & 0xF0
+            }
+            HDMA4 if self.cgb => self.hdma.dest = self.hdma.dest & 0x1F00 | (val & 0xF0) as u16,
+            HDMA5 if self.cgb => self.start_hdma(val, cart),
+            BCPS if self.cgb => self.bg_palettes.write_spec(val),
+            OCPS if self.cgb => self.obj_palettes.write_spec(val),
+            BCPD if self.cgb => self.bg_palettes.write_data(val, self.vram_blocked()),
+            OCPD if self.cgb => self.obj_palettes.write_data(val, self.vram_blocked()),
+            PROHIBITED_START..=PROHIBITED_END => {}
+            TILE_DATA_START..=TILE_DATA_END => {
+                if let Some(heatmap) = &mut self.vram_heatmap {
+                    heatmap.record_write(addr);
+                }
+                self.ram[addr as usize] = val
+            }
             _ => self.ram[addr as usize] = val,
         }
     }
 
+    // Called by STOP. Completes an armed CGB speed switch and returns whether one happened.
+    pub fn speed_switch(&mut self) -> bool {
+        if self.key1 & KEY1_ARMED == 0 {
+            return false;
+        }
+        self.key1 = (self.key1 ^ KEY1_DOUBLE_SPEED) & !KEY1_ARMED;
+        true
+    }
+
+    // An HBlank DMA is cancelled by a general-purpose start, which otherwise copies everything
+    // right away
+    fn start_hdma(&mut self, val: u8, cart: &Cart) {
+        if self.hdma.hblank_active() && val & Hdma::HBLANK == 0 {
+            self.hdma.hdma5 |= Hdma::IDLE;
+        } else if val & Hdma::HBLANK != 0 {
+            self.hdma.hdma5 = val & Hdma::LENGTH;
+        } else {
+            for _ in 0..=(val & Hdma::LENGTH) {
+                self.hdma_copy_block(cart);
+            }
+            self.hdma.hdma5 = 0xFF;
+        }
+    }
+
+    // Like the OAM DMA, the copy has its own bus and ignores the PPU mode locks
+    fn hdma_copy_block(&mut self, cart: &Cart) {
+        for i in 0..HDMA_BLOCK_LEN {
+            let val = self.dma_read(self.hdma.source.wrapping_add(i), cart);
+            let addr = VRAM_START + ((self.hdma.dest + i) & (VRAM_SIZE as u16 - 1));
+            if self.vram_bank == 1 {
+                self.vram_bank1[(addr - VRAM_START) as usize] = val;
+            } else {
+                self.ram[addr as usize] = val;
+            }
+        }
+        self.hdma.source = self.hdma.source.wrapping_add(HDMA_BLOCK_LEN);
+        self.hdma.dest = (self.hdma.dest + HDMA_BLOCK_LEN) & (VRAM_SIZE as u16 - 1);
+        self.hdma.stall_cycles += HDMA_BLOCK_CYCLES << self.double_speed() as u32;
+    }
+
+    // Called by the PPU as each visible line enters HBlank
+    pub fn hblank_dma(&mut self, cart: &Cart) {
+        if !self.hdma.hblank_active() {
+            return;
+        }
+        self.hdma_copy_block(cart);
+        self.hdma.hdma5 = if self.hdma.hdma5 == 0 { 0xFF } else { self.hdma.hdma5 - 1 };
+    }
+
+    // CPU cycles the VRAM DMA has stalled the CPU for since the last call
+    pub fn take_hdma_stall(&mut self) -> u32 {
+        std::mem::take(&mut self.hdma.stall_cycles)
+    }
+
+    // In double speed the CPU, timers, serial and OAM DMA run at twice the PPU/APU clock
+    pub fn double_speed(&self) -> bool {
+        self.key1 & KEY1_DOUBLE_SPEED != 0
+    }
+
+    pub fn memory_map(&self, cart: &Cart) -> MemoryMapInfo {
+        MemoryMapInfo {
+            rom_bank: cart.rom_bank(),
+            ram_bank: cart.ram_bank_selected,
+            ram_enabled: cart.ram_enabled,
+            banking_mode: cart.banking_mode,
+            boot_rom_mapped: !self.boot_rom.is_empty(),
+            dma_active: self.dma_active(),
+        }
+    }
+
+    // the high byte comes from addr + 1, wrapping 0xFFFF around to 0x0000 like the stack does
     pub fn read_short(&self, addr: u16, cart: &Cart, joypad: &Joypad, apu: &mut APU) -> u16 {
         (self.read_byte(addr, cart, joypad, apu) as u16)
-            | ((self.read_byte(addr + 1, cart, joypad, apu) as u16) << 8)
+            | ((self.read_byte(addr.wrapping_add(1), cart, joypad, apu) as u16) << 8)
     }
 
     pub fn write_short(
@@ -66,30 +563,66 @@ impl MMU {
         apu: &mut APU,
     ) {
         self.write_byte(addr, (val & 0xFF) as u8, cart, joypad, apu);
-        self.write_byte(addr + 1, (val >> 8) as u8, cart, joypad, apu);
+        self.write_byte(addr.wrapping_add(1), (val >> 8) as u8, cart, joypad, apu);
     }
 
-    // copy 160 bytes to OAM (0xFE00)
-    pub fn oam_dma_transfer(
-        &mut self,
-        source_high: u8,
-        cart: &Cart,
-        joypad: &Joypad,
-        apu: &mut APU,
-    ) {
+    // starts copying 160 bytes from XX00 to OAM (0xFE00), restarting any transfer in progress
+    pub fn oam_dma_transfer(&mut self, source_high: u8) {
         // convert XX to XX00
-        let source = (source_high as u16) << 8;
-        for i in 0x0 as u16..0xA0 as u16 {
-            let val = self.read_byte(source + i, cart, joypad, apu);
-            let dest = 0xFE00 as u16 + i;
-            self.ram[dest as usize] = val;
+        self.dma_source = (source_high as u16) << 8;
+        self.dma_cycles_remaining = OAM_DMA_CYCLES;
+    }
+
+    // The DMA unit has the bus to itself, so unlike CPU reads through read_byte it isn't
+    // subject to PPU mode access blocking (e.g. it can copy from VRAM during mode 3)
+    fn dma_read(&self, addr: u16, cart: &Cart) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => cart.read_rom(addr),
+            VRAM_START..=VRAM_END => self.read_vram_bank(self.vram_bank, addr),
+            0xA000..=0xBFFF => cart.read_ram(addr),
+            0xC000..=0xDFFF => self.read_wram(addr),
+            // sources past 0xDFFF read the work RAM echo
+            0xE000..=0xFFFF => self.read_wram(addr - ECHO_OFFSET as u16),
+        }
+    }
+
+    // Restores battery RAM from the .sav written by saveram, if there is one. MBC3 carts with
+    // a clock have the RTC footer after the RAM. A file of the wrong size only fills what fits.
+    pub fn loadram(&mut self, rom_path: &String, cart: &mut Cart) {
+        let mut save_path = PathBuf::from(Path::new(rom_path));
+        save_path.set_extension("sav");
+        let Ok(bytes) = fs::read(&save_path) else {
+            return;
+        };
+
+        let expected_len = cart.ram.len() + if cart.has_rtc() { RTC_FOOTER_LEN } else { 0 };
+        if bytes.len() != expected_len {
+            println!(
+                "Warning: {} is {} bytes, expected {}",
+                save_path.display(),
+                bytes.len(),
+                expected_len
+            );
         }
+        let len = bytes.len().min(cart.ram.len());
+        cart.ram[..len].copy_from_slice(&bytes[..len]);
+        if cart.has_rtc() {
+            cart.rtc.load_footer(&bytes[len..]);
+        }
+        println!("Save RAM loaded: {}", save_path.display());
     }
 
-    pub fn saveram(&mut self, rom_path: &String, cart: &Cart) {
+    // Errors leave ram_dirty set, so the next autosave tries again
+    pub fn saveram(&mut self, rom_path: &String, cart: &mut Cart) -> io::Result<()> {
         let rom_path = Path::new(rom_path);
         let mut save_path = PathBuf::from(rom_path);
         save_path.set_extension("sav");
-        fs::write(&save_path, &cart.ram).expect("Error: unable to write RAM contents")
+        let mut bytes = cart.ram.clone();
+        if cart.has_rtc() {
+            bytes.extend(cart.rtc.footer());
+        }
+        fs::write(&save_path, &bytes)?;
+        cart.ram_dirty = false;
+        Ok(())
     }
 }
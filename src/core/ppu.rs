@@ -1,6 +1,6 @@
 use crate::apu::APU;
 use crate::cart::Cart;
-use crate::consts::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::consts::{DOTS_PER_SCANLINE, MIN_VRAM_DOTS, OAM_SCAN_DOTS, SCREEN_HEIGHT, SCREEN_WIDTH};
 use crate::cpu::{InterruptBit, CPU};
 use crate::joypad::Joypad;
 use crate::mmu::MMU;
@@ -10,12 +10,57 @@ use serde_big_array::BigArray;
 
 #[derive(Serialize, Deserialize)]
 pub struct PPU {
+    // scanlines are drawn into back_buffer, which is copied to the front buffer at VBlank entry
+    // so readers never see a partially drawn frame
     #[serde(with = "BigArray")]
-    pub framebuffer: [u8; 144 * 160],
+    framebuffer: [u8; 144 * 160],
+    #[serde(with = "BigArray")]
+    back_buffer: [u8; 144 * 160],
+    // which layer and palette drew each pixel, so renderers can color OBJ pixels separately
+    #[serde(with = "BigArray")]
+    layers: [Layer; 144 * 160],
+    #[serde(with = "BigArray")]
+    back_layers: [Layer; 144 * 160],
+    // in CGB mode pixels are drawn here as BGR555 from the color palettes instead
+    #[serde(with = "BigArray")]
+    colors: [u16; 144 * 160],
+    #[serde(with = "BigArray")]
+    back_colors: [u16; 144 * 160],
     pub current_mode: PPUMode,
     pub current_cycles: u32,
+    pub vram_dots: u32, // length of mode 3 on the current line, HBlank gets the rest
     pub stat_line: bool,
     pub window_line_counter: u8,
+    pub wy_triggered: bool, // LY matched WY at some point this frame, window may draw from here on
+    pub frame_ready: bool,  // set on VBlank entry, cleared by whoever consumes the frame
+    pub frame_count: u64,   // frames completed since power-on
+    pub lcd_on: bool,       // LCDC bit 7 as of the last update, to catch the edges
+    // line 0 right after the LCD is switched on has no OAM scan: it reports mode 0 and starts
+    // mode 3 a little early
+    pub first_line: bool,
+
+    // Accurate mode samples BGP while mode 3 runs so writes mid-scanline only recolor the
+    // pixels drawn after them. The fast renderer reads BGP once when the line is drawn and
+    // ignores mid-line palette writes.
+    #[serde(skip)]
+    pub accurate: bool,
+    #[serde(skip)]
+    bgp_writes: Vec<(u32, u8)>, // (dot within mode 3, BGP value) for the current line
+    // BG/window color index (0-3, before BGP) per pixel of the line being drawn, for the
+    // OBJ-behind-BG priority check. The drawn shade can't be used since BGP may map index 0
+    // to any shade.
+    #[serde(skip)]
+    bg_color_indices: Vec<u8>,
+    // CGB tile attribute bit 7 per pixel of the line: the BG/window pixel covers sprites
+    #[serde(skip)]
+    bg_priorities: Vec<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Layer {
+    Bg, // background or window, through BGP
+    Obj0,
+    Obj1,
 }
 
 pub enum PPUMemory {
@@ -33,8 +78,9 @@ pub enum PPUMemory {
     WX = 0xFF4B, //window
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub enum PPUMode {
+    #[default]
     HBlank = 0,
     VBlank = 1,
     OAM = 2,
@@ -46,6 +92,18 @@ pub enum OAMAttributesBits {
     YFlip = 6,
     XFlip = 5,
     PaletteNumber = 4,
+    VramBank = 3, // CGB
+}
+
+// CGB only: bits 0-2 of a sprite's attributes pick its color palette
+const CGB_PALETTE_MASK: u8 = 0b111;
+
+// CGB BG map attributes, stored in VRAM bank 1 at the same address as the tile index
+pub enum TileAttributesBits {
+    VramBank = 3,
+    XFlip = 5,
+    YFlip = 6,
+    Priority = 7, // bits 0-2 are the color palette
 }
 
 pub enum LCDCBits {
@@ -66,28 +124,65 @@ pub enum LCDStatBits {
     Mode0IntSelect = 3, // H-Blank
     Mode1IntSelect = 4, // V-Blank
     Mode2IntSelect = 5, // OAM Scan
-    LCDIntSelect = 6,
+    LYCIntSelect = 6,
 }
 
-// Color constants for better readability
-pub const COLOR_WHITE: u8 = 0xFF;
-pub const COLOR_LIGHT_GRAY: u8 = 0xAA;
-pub const COLOR_DARK_GRAY: u8 = 0x55;
-pub const COLOR_BLACK: u8 = 0x00;
+// The framebuffer holds shades after BGP/OBP mapping, 0 (lightest) to 3 (darkest). The
+// renderer picks the actual colors.
+pub const SHADE_WHITE: u8 = 0;
+const CGB_WHITE: u16 = 0x7FFF;
+
+const FIRST_LINE_SHORTENING: u32 = 4; // dots missing from line 0 after the LCD is switched on
+
+impl Default for PPU {
+    fn default() -> PPU {
+        PPU::new()
+    }
+}
 
 impl PPU {
     pub fn new() -> PPU {
-        let framebuffer = [0xFFu8; 144 * 160];
+        let framebuffer = [SHADE_WHITE; 144 * 160];
 
         PPU {
             framebuffer: framebuffer,
+            back_buffer: framebuffer,
+            layers: [Layer::Bg; 144 * 160],
+            back_layers: [Layer::Bg; 144 * 160],
+            colors: [CGB_WHITE; 144 * 160],
+            back_colors: [CGB_WHITE; 144 * 160],
             current_mode: PPUMode::VBlank,
             current_cycles: 0,
+            vram_dots: MIN_VRAM_DOTS,
             stat_line: false,
             window_line_counter: 0,
+            wy_triggered: false,
+            frame_ready: false,
+            frame_count: 0,
+            lcd_on: true,
+            first_line: false,
+            accurate: false,
+            bgp_writes: Vec::new(),
+            bg_color_indices: vec![0; SCREEN_WIDTH as usize],
+            bg_priorities: vec![false; SCREEN_WIDTH as usize],
         }
     }
 
+    // the last completed frame (shades, 160x144)
+    pub fn framebuffer(&self) -> &[u8; 144 * 160] {
+        &self.framebuffer
+    }
+
+    // the layer each pixel of framebuffer() came from
+    pub fn layers(&self) -> &[Layer; 144 * 160] {
+        &self.layers
+    }
+
+    // the last completed frame in CGB mode (BGR555, 160x144), framebuffer() is unused there
+    pub fn colors(&self) -> &[u16; 144 * 160] {
+        &self.colors
+    }
+
     pub fn update(
         &mut self,
         cycles: u32,
@@ -102,44 +197,75 @@ impl PPU {
         let lcdc = mmu.read_byte(PPUMemory::LCDC as u16, cart, joypad, apu);
 
         if lcdc & (1 << LCDCBits::LCDDisplayEnable as u8) == 0 {
-            mmu.write_byte(PPUMemory::LY as u16, 0, cart, joypad, apu);
-            self.current_cycles = 0;
-            self.current_mode = PPUMode::OAM;
-            self.stat_line = false;
+            if self.lcd_on {
+                self.turn_off(mmu, cart, joypad, apu);
+            }
             return;
         }
+        if !self.lcd_on {
+            self.lcd_on = true;
+            self.first_line = true;
+        }
 
         self.update_stat(scanline, mmu, cpu, cart, joypad, apu);
 
+        if self.accurate && matches!(self.current_mode, PPUMode::VRAM) {
+            self.sample_bgp(mmu, cart, joypad, apu);
+        }
+
         self.current_cycles += cycles;
 
         match self.current_mode {
             // Mode 2
             PPUMode::OAM => {
-                if self.current_cycles > 80 {
-                    self.current_cycles -= 80;
+                let oam_dots = if self.first_line {
+                    OAM_SCAN_DOTS - FIRST_LINE_SHORTENING
+                } else {
+                    OAM_SCAN_DOTS
+                };
+                if self.current_cycles >= oam_dots {
+                    self.current_cycles -= oam_dots;
+                    self.first_line = false;
+                    self.vram_dots = self.mode3_dots(scanline, mmu);
                     self.current_mode = PPUMode::VRAM;
-                    self.update_stat(scanline, mmu, cpu, cart, joypad, apu);
+                    self.bgp_writes.clear();
+                    // WY is only compared against LY here, later WY writes can't move a window
+                    // that has already started or retroactively start it on an earlier line
+                    let wy = mmu.read_byte(PPUMemory::WY as u16, cart, joypad, apu);
+                    if scanline == wy {
+                        self.wy_triggered = true;
+                    }
+                    if self.accurate {
+                        let bgp = mmu.read_byte(PPUMemory::BGP as u16, cart, joypad, apu);
+                        self.bgp_writes.push((0, bgp));
+                    }
                 }
             }
             // Mode 3
             PPUMode::VRAM => {
-                if self.current_cycles > 172 {
-                    self.current_cycles -= 172;
+                if self.current_cycles >= self.vram_dots {
+                    self.current_cycles -= self.vram_dots;
                     self.current_mode = PPUMode::HBlank;
                     self.draw_scanline(scanline, mmu, cart, joypad, apu);
+                    mmu.hblank_dma(cart);
                 }
             }
             // Mode 0
             PPUMode::HBlank => {
-                if self.current_cycles > 204 {
-                    self.update_stat(scanline, mmu, cpu, cart, joypad, apu);
-                    self.current_cycles -= 204;
+                let hblank_dots = DOTS_PER_SCANLINE - OAM_SCAN_DOTS - self.vram_dots;
+                if self.current_cycles >= hblank_dots {
+                    self.current_cycles -= hblank_dots;
                     if scanline == SCREEN_HEIGHT as u8 - 1 {
                         cpu.request_interrupt(InterruptBit::VBlank, mmu, cart, joypad, apu);
                         mmu.write_byte(PPUMemory::LY as u16, scanline + 1, cart, joypad, apu);
                         self.current_mode = PPUMode::VBlank;
                         self.window_line_counter = 0;
+                        self.wy_triggered = false;
+                        self.framebuffer = self.back_buffer;
+                        self.layers = self.back_layers;
+                        self.colors = self.back_colors;
+                        self.frame_ready = true;
+                        self.frame_count += 1;
                     } else {
                         mmu.write_byte(PPUMemory::LY as u16, scanline + 1, cart, joypad, apu);
                         self.current_mode = PPUMode::OAM;
@@ -148,19 +274,91 @@ impl PPU {
             }
             // Mode 1
             PPUMode::VBlank => {
-                if self.current_cycles > 456 {
+                if self.current_cycles >= DOTS_PER_SCANLINE {
                     if scanline == SCREEN_HEIGHT as u8 + 9 {
                         mmu.write_byte(PPUMemory::LY as u16, 0, cart, joypad, apu);
                         self.current_mode = PPUMode::OAM;
                     } else {
                         mmu.write_byte(PPUMemory::LY as u16, scanline + 1, cart, joypad, apu);
                     }
-
-                    self.update_stat(scanline, mmu, cpu, cart, joypad, apu);
-                    self.current_cycles -= 456;
+                    self.current_cycles -= DOTS_PER_SCANLINE;
                 }
             }
         }
+
+        // refresh the mode bits and STAT line right away so a mode 0/1/2 interrupt fires on
+        // the transition itself rather than on the next update
+        let scanline = mmu.read_byte(PPUMemory::LY as u16, cart, joypad, apu);
+        self.update_stat(scanline, mmu, cpu, cart, joypad, apu);
+    }
+
+    // LCDC bit 7 was just cleared: LY, the mode and the dot counter all reset, and the next
+    // frame starts from line 0 without passing through VBlank. The screen goes blank (white)
+    // instead of holding the last frame.
+    fn turn_off(&mut self, mmu: &mut MMU, cart: &mut Cart, joypad: &mut Joypad, apu: &mut APU) {
+        self.lcd_on = false;
+        self.framebuffer.fill(SHADE_WHITE);
+        self.layers.fill(Layer::Bg);
+        self.colors.fill(CGB_WHITE);
+        mmu.write_byte(PPUMemory::LY as u16, 0, cart, joypad, apu);
+        let stat = mmu.read_byte(PPUMemory::STAT as u16, cart, joypad, apu);
+        mmu.write_byte(PPUMemory::STAT as u16, stat & !0b11, cart, joypad, apu);
+        self.current_cycles = 0;
+        self.current_mode = PPUMode::OAM;
+        self.stat_line = false;
+        mmu.ppu_mode = PPUMode::HBlank; // the CPU has free access while the LCD is off
+        self.window_line_counter = 0;
+        self.wy_triggered = false;
+    }
+
+    // the mode STAT and the CPU's VRAM/OAM access see
+    fn visible_mode(&self) -> PPUMode {
+        if self.first_line && matches!(self.current_mode, PPUMode::OAM) {
+            PPUMode::HBlank
+        } else {
+            self.current_mode.clone()
+        }
+    }
+
+    // records BGP at the current mode 3 dot if it changed since the last sample
+    fn sample_bgp(&mut self, mmu: &mut MMU, cart: &mut Cart, joypad: &mut Joypad, apu: &mut APU) {
+        let bgp = mmu.read_byte(PPUMemory::BGP as u16, cart, joypad, apu);
+        if self.bgp_writes.last().is_none_or(|&(_, last)| last != bgp) {
+            self.bgp_writes.push((self.current_cycles, bgp));
+        }
+    }
+
+    // BGP in effect when pixel x was shifted out, approximating one pixel per mode 3 dot
+    fn bgp_at(&self, x: u16, line_bgp: u8) -> u8 {
+        self.bgp_writes
+            .iter()
+            .rev()
+            .find(|&&(dot, _)| dot <= x as u32)
+            .map_or(line_bgp, |&(_, bgp)| bgp)
+    }
+
+    // dot within the current scanline (0-455)
+    fn line_dot(&self) -> u32 {
+        match self.current_mode {
+            PPUMode::OAM => self.current_cycles,
+            PPUMode::VRAM => OAM_SCAN_DOTS + self.current_cycles,
+            PPUMode::HBlank => OAM_SCAN_DOTS + self.vram_dots + self.current_cycles,
+            PPUMode::VBlank => self.current_cycles % DOTS_PER_SCANLINE,
+        }
+    }
+
+    // LY as the LYC comparator sees it. The comparison happens at dot 4, so for the first dots
+    // of a line nothing matches (except line 0, whose LY was already 0 during line 153). On
+    // hardware line 153 only reads as 153 for a few dots before LY reads 0, only the comparator
+    // sees that here: the LY register keeps reading 153 to the CPU for the whole line.
+    fn lyc_compare_ly(&self, scanline: u8) -> Option<u8> {
+        let dot = self.line_dot();
+        match scanline {
+            0 => Some(0),
+            153 if dot >= 8 => Some(0),
+            _ if dot < 4 => None,
+            _ => Some(scanline),
+        }
     }
 
     pub fn update_stat(
@@ -175,28 +373,29 @@ impl PPU {
         let mut stat = mmu.read_byte(PPUMemory::STAT as u16, cart, joypad, apu);
 
         // Update read-only stat information
-        // LYC = LY, bit 2 check
+        // LYC = LY, bit 2 check. This runs on every update, VBlank lines included, so LY
+        // changes and CPU writes to LYC both take effect within the same step
         let lyc = mmu.read_byte(PPUMemory::LYC as u16, cart, joypad, apu);
-        if scanline == lyc {
+        let lyc_match = self.lyc_compare_ly(scanline) == Some(lyc);
+        if lyc_match {
             stat |= 1 << LCDStatBits::LYCEqualsLY as u8;
-            // if (stat & (1 << LCDStatBits::LCDIntSelect as u8)) != 0 {
-            // }
         } else {
             stat &= !(1 << LCDStatBits::LYCEqualsLY as u8);
         }
-        let mode = self.current_mode.clone() as u8;
+        let mode = self.visible_mode() as u8;
 
         // bit 1 set
         stat &= !0b11; // Clear mode bits
-        stat |= self.current_mode.clone() as u8;
+        stat |= mode;
         mmu.write_byte(PPUMemory::STAT as u16, stat, cart, joypad, apu);
+        mmu.ppu_mode = self.visible_mode();
         let current_stat_line = (mode == PPUMode::HBlank as u8
             && (stat & (1 << LCDStatBits::Mode0IntSelect as u8)) != 0)
             || (mode == PPUMode::VBlank as u8
                 && (stat & (1 << LCDStatBits::Mode1IntSelect as u8)) != 0)
             || (mode == PPUMode::OAM as u8
                 && (stat & (1 << LCDStatBits::Mode2IntSelect as u8)) != 0)
-            || (scanline == lyc && (stat & (1 << LCDStatBits::LCDIntSelect as u8)) != 0);
+            || (lyc_match && (stat & (1 << LCDStatBits::LYCIntSelect as u8)) != 0);
 
         if !self.stat_line && current_stat_line {
             cpu.request_interrupt(InterruptBit::STAT, mmu, cart, joypad, apu);
@@ -219,11 +418,24 @@ impl PPU {
             return;
         }
 
-        if (lcdc & (1 << LCDCBits::BackgroundWindowEnable as u8)) != 0 {
+        // with BG/window disabled every pixel counts as color 0, so sprites always show
+        self.bg_color_indices.clear();
+        self.bg_color_indices.resize(SCREEN_WIDTH as usize, 0);
+        self.bg_priorities.clear();
+        self.bg_priorities.resize(SCREEN_WIDTH as usize, false);
+
+        // on DMG, LCDC bit 0 blanks the window too, and a window that isn't drawn doesn't
+        // advance its line counter. On CGB both are always drawn and the bit only takes away
+        // their priority over sprites.
+        let bg_enabled = (lcdc & (1 << LCDCBits::BackgroundWindowEnable as u8)) != 0;
+        if bg_enabled || mmu.cgb {
             self.draw_background_scanline(scanline, mmu, cart, joypad, apu);
+            if (lcdc & (1 << LCDCBits::WindowDisplayEnable as u8)) != 0 {
+                self.draw_window_scanline(scanline, mmu, cart, joypad, apu);
+            }
         }
-        if (lcdc & (1 << LCDCBits::WindowDisplayEnable as u8)) != 0 {
-            self.draw_window_scanline(scanline, mmu, cart, joypad, apu);
+        if !bg_enabled {
+            self.bg_color_indices.fill(0);
         }
 
         if (lcdc & (1 << LCDCBits::ObjectDisplayEnable as u8)) != 0 {
@@ -249,6 +461,7 @@ impl PPU {
 
         let scx = mmu.read_byte(PPUMemory::SCX as u16, cart, joypad, apu);
         let scy = mmu.read_byte(PPUMemory::SCY as u16, cart, joypad, apu);
+        let line_bgp = mmu.read_byte(PPUMemory::BGP as u16, cart, joypad, apu);
 
         for x in 0..SCREEN_WIDTH as u16 {
             // 32 tiles per row so going down one row requires * 32, / 8 because each tile is 8 * 8 px
@@ -259,7 +472,8 @@ impl PPU {
             let tile_map_col_offset = (background_x / 8) as u16;
 
             let tile_map_offset: u16 = tile_map_row_offset + tile_map_col_offset;
-            let tile_index = mmu.read_byte(tile_map_base + tile_map_offset, cart, joypad, apu);
+            let tile_index = mmu.read_vram(tile_map_base + tile_map_offset);
+            let attributes = Self::tile_attributes(mmu, tile_map_base + tile_map_offset);
 
             // 8800 + (127 + 128) * 16 = 97F0 (can grab the last 2 bytes of memory for tile data)
             // 8800 + (-128 + 128) * 16 = 8800
@@ -270,34 +484,36 @@ impl PPU {
                 tile_data_base + ((signed_index as i16 + 128) * 16) as u16
             };
 
-            let tile_data_line = (background_y % 8) as u16; //within the tile, the line looked at
+            //within the tile, the line looked at
+            let tile_data_line = Self::flip_y(background_y % 8, attributes);
+            let bank = Self::attribute(attributes, TileAttributesBits::VramBank);
 
             // 2BPP calculations below to get a pixel
             // Ex. 8000 + (2F * 0x10) = 82F0
             // Get the two bytes for the line (there are 16 bytes per tile, 2 bytes per line)
             let tile_data_byte_1 =
-                mmu.read_byte(tile_data_address + (tile_data_line * 2), cart, joypad, apu);
+                mmu.read_vram_bank(bank, tile_data_address + (tile_data_line * 2));
             let tile_data_byte_2 =
-                mmu.read_byte(tile_data_address + (tile_data_line * 2 + 1), cart, joypad, apu);
+                mmu.read_vram_bank(bank, tile_data_address + (tile_data_line * 2 + 1));
 
             // Get the two bits for the pixel within the line (that's why x is used), bits go from 7 - 0
-            let tile_data_byte_index = 7 - (background_x % 8);
+            let tile_data_byte_index = 7 - Self::flip_x(background_x % 8, attributes);
             let tile_data_bit_1 = (tile_data_byte_1 >> tile_data_byte_index) & 1;
             let tile_data_bit_2 = (tile_data_byte_2 >> tile_data_byte_index) & 1;
 
             // originally called tile_data_bit_color, values from 0 - 3
             let color_index = (tile_data_bit_2 << 1) | tile_data_bit_1;
-            let palette = mmu.read_byte(PPUMemory::BGP as u16, cart, joypad, apu);
-
-            let color = match (palette >> (color_index * 2)) & 0b11 {
-                0 => COLOR_WHITE,
-                1 => COLOR_LIGHT_GRAY,
-                2 => COLOR_DARK_GRAY,
-                3 => COLOR_BLACK,
-                _ => COLOR_WHITE,
-            };
+            let palette = self.bgp_at(x, line_bgp);
 
-            self.framebuffer[((scanline as u32 * SCREEN_WIDTH) + x as u32) as usize] = color;
+            let color = (palette >> (color_index * 2)) & 0b11;
+
+            let index = ((scanline as u32 * SCREEN_WIDTH) + x as u32) as usize;
+            self.back_buffer[index] = color;
+            self.back_layers[index] = Layer::Bg;
+            self.bg_color_indices[x as usize] = color_index;
+            if mmu.cgb {
+                self.set_bg_color(index, x, attributes, color_index, mmu);
+            }
         }
     }
 
@@ -310,14 +526,14 @@ impl PPU {
         apu: &mut APU,
     ) {
         let wx = mmu.read_byte(PPUMemory::WX as u16, cart, joypad, apu);
-        let wy = mmu.read_byte(PPUMemory::WY as u16, cart, joypad, apu);
 
-        if scanline < wy || wx >= 166 {
+        if !self.wy_triggered || wx >= 166 {
             return;
         }
 
         let window_y = self.window_line_counter;
         self.window_line_counter += 1;
+        let line_bgp = mmu.read_byte(PPUMemory::BGP as u16, cart, joypad, apu);
 
         for x in 0..SCREEN_WIDTH as u16 {
             if x + 7 < wx as u16 {
@@ -335,11 +551,12 @@ impl PPU {
             let tile_data_base: u16 = if tile_data_base_bit == 0 { 0x8800 } else { 0x8000 };
 
             let window_x = x + 7 - wx as u16;
-            let tile_map_row_offset = ((window_y as u16 / 8) * 32) as u16;
-            let tile_map_col_offset = (window_x as u16 / 8) as u16;
+            let tile_map_row_offset = (window_y as u16 / 8) * 32;
+            let tile_map_col_offset = window_x / 8;
 
             let tile_map_offset: u16 = tile_map_row_offset + tile_map_col_offset;
-            let tile_index = mmu.read_byte(tile_map_base + tile_map_offset, cart, joypad, apu);
+            let tile_index = mmu.read_vram(tile_map_base + tile_map_offset);
+            let attributes = Self::tile_attributes(mmu, tile_map_base + tile_map_offset);
 
             let tile_data_address: u16 = if tile_data_base == 0x8000 {
                 tile_data_base + (tile_index as u16 * 16)
@@ -348,43 +565,83 @@ impl PPU {
                 tile_data_base + ((signed_index as i16 + 128) * 16) as u16
             };
 
-            let tile_data_line = (window_y % 8) as u16; //within the tile, the line looked at
+            //within the tile, the line looked at
+            let tile_data_line = Self::flip_y(window_y as u16 % 8, attributes);
+            let bank = Self::attribute(attributes, TileAttributesBits::VramBank);
 
             let tile_data_byte_1 =
-                mmu.read_byte(tile_data_address + (tile_data_line * 2), cart, joypad, apu);
+                mmu.read_vram_bank(bank, tile_data_address + (tile_data_line * 2));
             let tile_data_byte_2 =
-                mmu.read_byte(tile_data_address + (tile_data_line * 2 + 1), cart, joypad, apu);
+                mmu.read_vram_bank(bank, tile_data_address + (tile_data_line * 2 + 1));
 
-            let tile_data_byte_index = 7 - (window_x % 8);
+            let tile_data_byte_index = 7 - Self::flip_x(window_x % 8, attributes);
             let tile_data_bit_1 = (tile_data_byte_1 >> tile_data_byte_index) & 1;
             let tile_data_bit_2 = (tile_data_byte_2 >> tile_data_byte_index) & 1;
 
             let color_index = (tile_data_bit_2 << 1) | tile_data_bit_1;
-            let palette = mmu.read_byte(PPUMemory::BGP as u16, cart, joypad, apu);
-
-            let color = match (palette >> (color_index * 2)) & 0b11 {
-                0 => COLOR_WHITE,
-                1 => COLOR_LIGHT_GRAY,
-                2 => COLOR_DARK_GRAY,
-                3 => COLOR_BLACK,
-                _ => COLOR_WHITE,
-            };
+            let palette = self.bgp_at(x, line_bgp);
+
+            let color = (palette >> (color_index * 2)) & 0b11;
 
-            self.framebuffer[((scanline as u32 * SCREEN_WIDTH) + x as u32) as usize] = color;
+            let index = ((scanline as u32 * SCREEN_WIDTH) + x as u32) as usize;
+            self.back_buffer[index] = color;
+            self.back_layers[index] = Layer::Bg;
+            self.bg_color_indices[x as usize] = color_index;
+            if mmu.cgb {
+                self.set_bg_color(index, x, attributes, color_index, mmu);
+            }
         }
     }
 
-    pub fn draw_sprites_scanline(
-        &mut self,
-        scanline: u8,
-        mmu: &mut MMU,
-        cart: &mut Cart,
-        joypad: &mut Joypad,
-        apu: &mut APU,
-    ) {
-        let lcdc = mmu.read_byte(PPUMemory::LCDC as u16, cart, joypad, apu);
+    // BG map attributes for the tile at map_addr, always 0 on DMG
+    fn tile_attributes(mmu: &MMU, map_addr: u16) -> u8 {
+        if mmu.cgb {
+            mmu.read_vram_bank(1, map_addr)
+        } else {
+            0
+        }
+    }
+
+    fn attribute(attributes: u8, bit: TileAttributesBits) -> u8 {
+        (attributes >> bit as u8) & 1
+    }
+
+    // row 0-7 within a tile, mirrored by the Y flip attribute
+    fn flip_y(line: u16, attributes: u8) -> u16 {
+        if Self::attribute(attributes, TileAttributesBits::YFlip) != 0 {
+            7 - line
+        } else {
+            line
+        }
+    }
+
+    // column 0-7 within a tile, mirrored by the X flip attribute
+    fn flip_x(column: u16, attributes: u8) -> u16 {
+        if Self::attribute(attributes, TileAttributesBits::XFlip) != 0 {
+            7 - column
+        } else {
+            column
+        }
+    }
+
+    fn set_bg_color(&mut self, index: usize, x: u16, attributes: u8, color_index: u8, mmu: &MMU) {
+        self.back_colors[index] = mmu.bg_palettes.color(attributes & CGB_PALETTE_MASK, color_index);
+        self.bg_priorities[x as usize] =
+            Self::attribute(attributes, TileAttributesBits::Priority) != 0;
+    }
+
+    fn sprite_height(lcdc: u8) -> u8 {
         let sprite_size_bit = (lcdc >> LCDCBits::ObjectSize as u8) & 1;
-        let sprite_height: u8 = if sprite_size_bit == 0 { 8 } else { 16 };
+        if sprite_size_bit == 0 {
+            8
+        } else {
+            16
+        }
+    }
+
+    // Mode 2: the first 10 sprites in OAM order that cover this line, as
+    // (x, y, tile index, attributes, OAM index) in screen coordinates
+    fn scan_oam(scanline: u8, sprite_height: u8, mmu: &MMU) -> Vec<(i16, i16, u8, u8, u8)> {
         let mut visible_sprites: Vec<(i16, i16, u8, u8, u8)> = Vec::with_capacity(10);
 
         let oam_base: u16 = 0xFE00;
@@ -394,11 +651,13 @@ impl PPU {
             // each sprite is 4 bytes in OAM
             let oam_addr = oam_base + sprite_index * 4;
 
-            let sprite_y = mmu.read_byte(oam_addr, cart, joypad, apu) as i16 - 16;
-            let sprite_x = mmu.read_byte(oam_addr + 1, cart, joypad, apu) as i16 - 8;
-            let tile_index = mmu.read_byte(oam_addr + 2, cart, joypad, apu);
-            let attributes = mmu.read_byte(oam_addr + 3, cart, joypad, apu);
+            let sprite_y = mmu.read_oam(oam_addr) as i16 - 16;
+            let sprite_x = mmu.read_oam(oam_addr + 1) as i16 - 8;
+            let tile_index = mmu.read_oam(oam_addr + 2);
+            let attributes = mmu.read_oam(oam_addr + 3);
 
+            // Only Y decides selection: Y=0 (or Y>=160) never intersects a visible line, but a
+            // sprite parked off-screen with X=0 or X>=168 still takes one of the 10 slots
             if sprite_y <= (scanline as i16) && (scanline as i16) < sprite_y + sprite_height as i16
             {
                 visible_sprites.push((
@@ -413,9 +672,72 @@ impl PPU {
                 }
             }
         }
+        visible_sprites
+    }
+
+    // Mode 3 takes longer than MIN_VRAM_DOTS when the fetcher has to wait: the SCX fine scroll
+    // discards SCX & 7 pixels, starting the window restarts the fetch (6 dots) and each sprite
+    // costs 6 dots plus up to 5 more for the BG fetch it interrupts, paid only by the first
+    // sprite over each BG tile. HBlank shrinks by the same amount.
+    fn mode3_dots(&self, scanline: u8, mmu: &MMU) -> u32 {
+        let lcdc = mmu.ram[PPUMemory::LCDC as usize];
+        let scx = mmu.ram[PPUMemory::SCX as usize];
+        let mut dots = MIN_VRAM_DOTS + (scx & 7) as u32;
+
+        let bg_enabled = lcdc & (1 << LCDCBits::BackgroundWindowEnable as u8) != 0;
+        let window_enabled = lcdc & (1 << LCDCBits::WindowDisplayEnable as u8) != 0;
+        let wy = mmu.ram[PPUMemory::WY as usize];
+        let wx = mmu.ram[PPUMemory::WX as usize];
+        if (bg_enabled || mmu.cgb)
+            && window_enabled
+            && (self.wy_triggered || scanline == wy)
+            && wx < 166
+        {
+            dots += 6;
+        }
 
+        if lcdc & (1 << LCDCBits::ObjectDisplayEnable as u8) == 0 {
+            return dots;
+        }
+        let mut sprites = Self::scan_oam(scanline, Self::sprite_height(lcdc), mmu);
+        sprites.sort_by_key(|sprite| sprite.0);
+        let mut penalized_tiles = Vec::new();
+        for (sprite_x, ..) in sprites {
+            // OAM X 168 and up is past the right edge and never fetched
+            if sprite_x >= SCREEN_WIDTH as i16 {
+                continue;
+            }
+            // OAM X 0 always stalls for the full 11 dots
+            if sprite_x == -8 {
+                dots += 11;
+                continue;
+            }
+            let bg_x = (sprite_x + scx as i16).rem_euclid(256);
+            let tile = bg_x / 8;
+            if !penalized_tiles.contains(&tile) {
+                penalized_tiles.push(tile);
+                dots += 5u32.saturating_sub((bg_x % 8) as u32);
+            }
+            dots += 6;
+        }
+        dots
+    }
+
+    pub fn draw_sprites_scanline(
+        &mut self,
+        scanline: u8,
+        mmu: &mut MMU,
+        cart: &mut Cart,
+        joypad: &mut Joypad,
+        apu: &mut APU,
+    ) {
+        let lcdc = mmu.read_byte(PPUMemory::LCDC as u16, cart, joypad, apu);
+        let sprite_height = Self::sprite_height(lcdc);
+        let mut visible_sprites = Self::scan_oam(scanline, sprite_height, mmu);
+
+        // drawn back to front: on DMG the lowest X wins, on CGB only the OAM index counts
         visible_sprites.sort_by(|a, b| {
-            if a.0 != b.0 {
+            if a.0 != b.0 && !mmu.cgb {
                 b.0.cmp(&a.0)
             } else {
                 // OAM index
@@ -425,6 +747,11 @@ impl PPU {
 
         // Draw sprites
         for (sprite_x, sprite_y, tile_index, attributes, _) in visible_sprites.into_iter() {
+            // hidden horizontally, nothing to draw but it was still counted above
+            if sprite_x <= -8 || sprite_x >= SCREEN_WIDTH as i16 {
+                continue;
+            }
+
             let palette_select = (attributes >> OAMAttributesBits::PaletteNumber as u8) & 1;
             let x_flip = ((attributes >> OAMAttributesBits::XFlip as u8) & 1) != 0;
             let y_flip = ((attributes >> OAMAttributesBits::YFlip as u8) & 1) != 0;
@@ -455,9 +782,10 @@ impl PPU {
 
             let tile_data_line = (sprite_line as u16) % 8;
 
-            let byte1 = mmu.read_byte(tile_data_address + tile_data_line * 2, cart, joypad, apu);
-            let byte2 =
-                mmu.read_byte(tile_data_address + tile_data_line * 2 + 1, cart, joypad, apu);
+            let bank =
+                if mmu.cgb { (attributes >> OAMAttributesBits::VramBank as u8) & 1 } else { 0 };
+            let byte1 = mmu.read_vram_bank(bank, tile_data_address + tile_data_line * 2);
+            let byte2 = mmu.read_vram_bank(bank, tile_data_address + tile_data_line * 2 + 1);
 
             for pixel in 0u8..8u8 {
                 let bit_index_u8 = if x_flip { pixel } else { 7u8 - pixel };
@@ -477,13 +805,7 @@ impl PPU {
                     apu,
                 );
 
-                let color = match (palette >> (color_index * 2)) & 0b11 {
-                    0 => COLOR_WHITE,
-                    1 => COLOR_LIGHT_GRAY,
-                    2 => COLOR_DARK_GRAY,
-                    3 => COLOR_BLACK,
-                    _ => COLOR_WHITE,
-                };
+                let color = (palette >> (color_index * 2)) & 0b11;
 
                 let px = sprite_x + pixel as i16;
                 if px < 0 || px >= SCREEN_WIDTH as i16 {
@@ -492,16 +814,21 @@ impl PPU {
 
                 let framebuffer_index = ((scanline as u32 * SCREEN_WIDTH) + px as u32) as usize;
 
-                // If background priority is set, sprite is behind background except where background color is 0 (white)
-                if background_priority {
-                    let bg_color = self.framebuffer[framebuffer_index];
-                    if bg_color != COLOR_WHITE {
-                        // background has priority, skip drawing this pixel
-                        continue;
-                    }
+                // If background priority is set (by the sprite or, on CGB, the BG tile), sprite
+                // is behind background except where the background color index is 0
+                if (background_priority || self.bg_priorities[px as usize])
+                    && self.bg_color_indices[px as usize] != 0
+                {
+                    continue;
                 }
 
-                self.framebuffer[framebuffer_index] = color;
+                if mmu.cgb {
+                    self.back_colors[framebuffer_index] =
+                        mmu.obj_palettes.color(attributes & CGB_PALETTE_MASK, color_index);
+                }
+                self.back_buffer[framebuffer_index] = color;
+                self.back_layers[framebuffer_index] =
+                    if palette_select == 0 { Layer::Obj0 } else { Layer::Obj1 };
             }
         }
     }
@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+pub const SB: u16 = 0xFF01; // serial transfer data
+pub const SC: u16 = 0xFF02; // serial transfer control
+
+pub const SC_TRANSFER_ENABLE_BIT: u8 = 0x80;
+pub const SC_CLOCK_SPEED_BIT: u8 = 0x02; // CGB only
+pub const SC_INTERNAL_CLOCK_BIT: u8 = 0x01;
+
+// internal clock shifts at 8192 Hz (4.194304 MHz / 512), or 262144 Hz with the CGB fast clock
+pub const CYCLES_PER_BIT: u32 = 512;
+pub const FAST_CYCLES_PER_BIT: u32 = 16;
+
+#[derive(Serialize, Deserialize)]
+pub struct Serial {
+    pub sb: u8,
+    pub sc: u8,
+    pub cgb: bool, // honors the SC fast clock bit
+    bits_remaining: u8,
+    bit_cycles: u32,
+    // every byte sent with the internal clock, where test ROMs report their results
+    #[serde(skip)]
+    pub output: Vec<u8>,
+    #[serde(skip)]
+    pub echo: bool, // also print sent bytes to stdout
+    // With a link cable the partner's byte replaces SB at the end of a transfer. Internal-clock
+    // transfers queue SB in outgoing for the link to send, external-clock ones are driven by
+    // the partner through clocked_by_peer.
+    #[serde(skip)]
+    pub linked: bool,
+    #[serde(skip)]
+    pub outgoing: Option<u8>,
+    #[serde(skip)]
+    peer_reply: Option<u8>,
+    #[serde(skip)]
+    peer_clocked: bool,
+}
+
+impl Default for Serial {
+    fn default() -> Serial {
+        Serial::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0x00,
+            sc: 0x00,
+            cgb: false,
+            bits_remaining: 0,
+            bit_cycles: 0,
+            output: Vec::new(),
+            echo: false,
+            linked: false,
+            outgoing: None,
+            peer_reply: None,
+            peer_clocked: false,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            SB => self.sb,
+            // unused SC bits read as 1 (bit 1 is only wired up on CGB)
+            SC => self.sc | if self.cgb { 0x7C } else { 0x7E },
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            SB => self.sb = val,
+            SC => {
+                self.sc = val;
+                // only the internal clock drives a transfer without a link partner
+                let start = SC_TRANSFER_ENABLE_BIT | SC_INTERNAL_CLOCK_BIT;
+                if val & start == start {
+                    self.bits_remaining = 8;
+                    self.bit_cycles = 0;
+                    if self.linked {
+                        self.outgoing = Some(self.sb);
+                    }
+                    self.output.push(self.sb);
+                    if self.echo {
+                        print!("{}", self.sb as char);
+                        std::io::stdout().flush().ok();
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // advances the shift clock, returns true when the transfer completes (serial interrupt)
+    pub fn update(&mut self, cycles: u32) -> bool {
+        // an external-clock transfer the link partner ran
+        if std::mem::take(&mut self.peer_clocked) {
+            return true;
+        }
+
+        if self.bits_remaining > 0 {
+            let cycles_per_bit = if self.cgb && self.sc & SC_CLOCK_SPEED_BIT != 0 {
+                FAST_CYCLES_PER_BIT
+            } else {
+                CYCLES_PER_BIT
+            };
+
+            self.bit_cycles += cycles;
+            while self.bit_cycles >= cycles_per_bit && self.bits_remaining > 0 {
+                self.bit_cycles -= cycles_per_bit;
+                if !self.linked {
+                    // nothing is connected, so the incoming line stays high
+                    self.sb = (self.sb << 1) | 1;
+                }
+                self.bits_remaining -= 1;
+            }
+
+            if self.bits_remaining > 0 {
+                return false;
+            }
+            if !self.linked {
+                self.sc &= !SC_TRANSFER_ENABLE_BIT;
+                self.peer_reply = None;
+                return true;
+            }
+        }
+
+        // a linked transfer also waits for the partner's byte, however long the network takes
+        let start = SC_TRANSFER_ENABLE_BIT | SC_INTERNAL_CLOCK_BIT;
+        if self.sc & start != start {
+            return false;
+        }
+        match self.peer_reply.take() {
+            Some(byte) => {
+                self.sb = byte;
+                self.sc &= !SC_TRANSFER_ENABLE_BIT;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The link partner ran a transfer with its internal clock and sent its SB. Returns our SB
+    // for it; the partner's byte only shifts in if the game armed an external-clock transfer.
+    pub fn clocked_by_peer(&mut self, byte: u8) -> u8 {
+        let sent = self.sb;
+        if self.sc & (SC_TRANSFER_ENABLE_BIT | SC_INTERNAL_CLOCK_BIT) == SC_TRANSFER_ENABLE_BIT {
+            self.sb = byte;
+            self.sc &= !SC_TRANSFER_ENABLE_BIT;
+            self.peer_clocked = true;
+        }
+        sent
+    }
+
+    // The partner's SB in answer to our internal-clock transfer
+    pub fn receive_reply(&mut self, byte: u8) {
+        self.peer_reply = Some(byte);
+    }
+
+    // The cable was pulled: a transfer still waiting for the partner finishes with 0xFF
+    pub fn unlink(&mut self) {
+        self.linked = false;
+        self.outgoing = None;
+        if self.bits_remaining == 0 {
+            self.peer_reply.get_or_insert(0xFF);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use flate2::read::GzDecoder;
+
+use crate::gb::GB;
+
+// One line of CPU state in the gameboy-doctor format, logged before each instruction runs:
+// A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+pub fn trace_line(gb: &mut GB) -> String {
+    let cpu = &gb.cpu;
+    let pc = cpu.pc;
+    let pcmem: Vec<String> = (0..4)
+        .map(|offset| {
+            let byte = gb.mmu.read_byte(pc.wrapping_add(offset), &gb.cart, &gb.joypad, &mut gb.apu);
+            format!("{:02X}", byte)
+        })
+        .collect();
+
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+        cpu.a,
+        cpu.f,
+        cpu.b,
+        cpu.c,
+        cpu.d,
+        cpu.e,
+        cpu.h,
+        cpu.l,
+        cpu.sp,
+        pc,
+        pcmem.join(",")
+    )
+}
+
+// Steps a reference trace alongside emulation, for --compare
+pub struct TraceComparer {
+    reader: Box<dyn BufRead>,
+    instructions: u64,
+    finished: bool,
+}
+
+impl TraceComparer {
+    // reference traces are huge, so .gz files are decompressed on the fly
+    pub fn open(path: &str) -> io::Result<TraceComparer> {
+        let file = File::open(path)?;
+        let reader: Box<dyn BufRead> = if path.ends_with(".gz") {
+            Box::new(BufReader::new(GzDecoder::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        };
+
+        Ok(TraceComparer { reader, instructions: 0, finished: false })
+    }
+
+    // Compares the state about to execute against the next reference line, returning a report
+    // on the first divergence
+    pub fn check(&mut self, gb: &mut GB) -> Option<String> {
+        if self.finished {
+            return None;
+        }
+
+        let mut expected = String::new();
+        match self.reader.read_line(&mut expected) {
+            Ok(0) => {
+                println!(
+                    "Reference trace ended after {} instructions without diverging",
+                    self.instructions
+                );
+                self.finished = true;
+                return None;
+            }
+            Ok(_) => (),
+            Err(e) => {
+                println!("Unable to read reference trace: {}", e);
+                self.finished = true;
+                return None;
+            }
+        }
+
+        let expected = expected.trim_end();
+        let actual = trace_line(gb);
+        if expected == actual {
+            self.instructions += 1;
+            return None;
+        }
+
+        let mismatched: Vec<String> = expected
+            .split_whitespace()
+            .zip(actual.split_whitespace())
+            .filter(|(e, a)| e != a)
+            .map(|(e, a)| format!("{} (got {})", e, a))
+            .collect();
+
+        Some(format!(
+            "Diverged from the reference trace at instruction {}\n  expected: {}\n  actual:   {}\n  mismatched: {}",
+            self.instructions,
+            expected,
+            actual,
+            mismatched.join(", ")
+        ))
+    }
+}
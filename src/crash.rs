@@ -0,0 +1,110 @@
+use std::fmt::Write;
+use std::fs;
+use std::panic;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::Local;
+
+use crate::gb::GB;
+use crate::ppu::PPUMemory;
+
+// panic message + location captured by the hook, picked up when the crash report is written
+static LAST_PANIC: Mutex<Option<String>> = Mutex::new(None);
+
+// Keeps the default hook's stderr output and remembers the message for the crash report
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let Ok(mut last_panic) = LAST_PANIC.lock() {
+            *last_panic = Some(info.to_string());
+        }
+        default_hook(info);
+    }));
+}
+
+// Reads memory without going through the MMU, which may be what panicked
+fn peek(gb: &GB, addr: u16) -> Option<u8> {
+    match addr {
+        0x0000..=0x7FFF => gb.cart.rom.get(gb.cart.rom_offset(addr)).copied(),
+        _ => gb.mmu.ram.get(addr as usize).copied(),
+    }
+}
+
+fn hex(value: Option<u8>) -> String {
+    value.map_or("??".to_string(), |v| format!("{:02X}", v))
+}
+
+pub fn crash_report(gb: &GB) -> String {
+    let cpu = &gb.cpu;
+    let cart = &gb.cart;
+    let mut report = String::new();
+
+    let panic_message = LAST_PANIC.lock().ok().and_then(|p| p.clone());
+    let _ = writeln!(report, "Dot Matrix crash report ({})", Local::now().to_rfc3339());
+    let _ = writeln!(report, "{}", panic_message.unwrap_or("unknown panic".to_string()));
+    let _ = writeln!(report);
+
+    let _ = writeln!(report, "ROM: {}", cart.title);
+    let _ = writeln!(
+        report,
+        "Cartridge type: {:02X}, header checksum: {}, global checksum: {}{}",
+        cart.cartridge_type,
+        hex(cart.rom.get(0x14D).copied()),
+        hex(cart.rom.get(0x14E).copied()),
+        hex(cart.rom.get(0x14F).copied()),
+    );
+    let _ = writeln!(
+        report,
+        "ROM bank: {:02X}, RAM bank: {:02X}, RAM enabled: {}",
+        cart.rom_bank(),
+        cart.ram_bank_selected,
+        cart.ram_enabled
+    );
+    let _ = writeln!(report);
+
+    let _ = writeln!(
+        report,
+        "PC: {:04X}  opcode: {} {} {}",
+        cpu.pc,
+        hex(peek(gb, cpu.pc)),
+        hex(peek(gb, cpu.pc.wrapping_add(1))),
+        hex(peek(gb, cpu.pc.wrapping_add(2))),
+    );
+    let _ = writeln!(
+        report,
+        "A: {:02X} F: {:02X} B: {:02X} C: {:02X} D: {:02X} E: {:02X} H: {:02X} L: {:02X} SP: {:04X}",
+        cpu.a, cpu.f, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.sp
+    );
+    let _ = writeln!(report, "IME: {} halted: {} stopped: {}", cpu.ime, cpu.halted, cpu.stopped);
+    let _ = writeln!(
+        report,
+        "LCDC: {} STAT: {} LY: {} IF: {} IE: {}",
+        hex(peek(gb, PPUMemory::LCDC as u16)),
+        hex(peek(gb, PPUMemory::STAT as u16)),
+        hex(peek(gb, PPUMemory::LY as u16)),
+        hex(peek(gb, 0xFF0F)),
+        hex(peek(gb, 0xFFFF)),
+    );
+    let _ = writeln!(report);
+
+    let _ = writeln!(report, "Recent instructions (oldest first):");
+    for (pc, opcode) in &cpu.recent {
+        let _ = writeln!(report, "{:04X}: {:02X}", pc, opcode);
+    }
+
+    report
+}
+
+// Writes <save path>.crash, i.e. next to the ROM or in --save-dir, falling back to stderr if
+// that fails
+pub fn write_crash_report(gb: &GB, save_path: &str) {
+    let report = crash_report(gb);
+    let mut path = PathBuf::from(save_path);
+    path.set_extension("crash");
+
+    match fs::write(&path, &report) {
+        Ok(()) => eprintln!("Crash report written to {}", path.display()),
+        Err(e) => eprintln!("Unable to write crash report ({}):\n{}", e, report),
+    }
+}
@@ -0,0 +1,187 @@
+use std::io::{self, BufRead, Write};
+
+use crate::cpu::FlagRegister;
+use crate::disasm;
+use crate::gb::GB;
+use crate::mmu::WriteWatch;
+
+const HELP: &str = "\
+s [N]          step N instructions (default 1)
+c              continue until a breakpoint
+b ADDR         set a PC breakpoint, b alone lists them
+d ADDR         delete a breakpoint
+p              print registers, flags and the next instruction
+x ADDR [LEN]   hexdump LEN bytes (hex, default 10) through the MMU
+watchvram ADDR [END]
+               stop after a write to a VRAM/OAM address or range, watchvram off clears it
+q              quit";
+
+fn parse_hex<T: TryFrom<u32>>(s: &str) -> Result<T, String> {
+    let digits = s.strip_prefix("0x").or(s.strip_prefix("0X")).unwrap_or(s);
+    u32::from_str_radix(digits, 16)
+        .ok()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or(format!("bad hex value '{}'", s))
+}
+
+// --debug: runs the ROM headless under a stdin REPL, stopped before the first instruction.
+// Addresses are hex, breakpoints match the PC of the next instruction to execute.
+pub struct Debugger {
+    gb: GB,
+    breakpoints: Vec<u16>,
+}
+
+impl Debugger {
+    pub fn new(gb: GB) -> Debugger {
+        Debugger { gb, breakpoints: Vec::new() }
+    }
+
+    pub fn registers(&self) -> String {
+        let cpu = &self.gb.cpu;
+        let flag =
+            |flag: FlagRegister, name: char| if cpu.get_flag(flag) != 0 { name } else { '-' };
+        format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X}  {}{}{}{}  IME:{} HALT:{}",
+            cpu.a,
+            cpu.f,
+            cpu.b,
+            cpu.c,
+            cpu.d,
+            cpu.e,
+            cpu.h,
+            cpu.l,
+            cpu.sp,
+            cpu.pc,
+            flag(FlagRegister::Zero, 'Z'),
+            flag(FlagRegister::Sub, 'N'),
+            flag(FlagRegister::HalfCarry, 'H'),
+            flag(FlagRegister::Carry, 'C'),
+            cpu.ime as u8,
+            cpu.halted as u8
+        )
+    }
+
+    fn hexdump(&mut self, addr: u16, len: u16) -> String {
+        let gb = &mut self.gb;
+        let mut lines = Vec::new();
+        for row in (0..len).step_by(16) {
+            let start = addr.wrapping_add(row);
+            let bytes: Vec<String> = (0..16.min(len - row))
+                .map(|offset| {
+                    let byte = gb.mmu.read_byte(
+                        start.wrapping_add(offset),
+                        &gb.cart,
+                        &gb.joypad,
+                        &mut gb.apu,
+                    );
+                    format!("{:02X}", byte)
+                })
+                .collect();
+            lines.push(format!("{:04X}: {}", start, bytes.join(" ")));
+        }
+        lines.join("\n")
+    }
+
+    // Steps once, returning true if the instruction hit the VRAM watch. GB::step already
+    // printed the writing PC and value.
+    fn step_watched(&mut self) -> bool {
+        self.gb.step();
+        match &mut self.gb.mmu.write_watch {
+            Some(watch) => std::mem::take(&mut watch.triggered),
+            None => false,
+        }
+    }
+
+    // Runs at least one instruction, then stops when the PC lands on a breakpoint or the VRAM
+    // watch is hit
+    fn continue_to_breakpoint(&mut self) {
+        loop {
+            if self.step_watched() {
+                return;
+            }
+            if self.gb.cpu.locked {
+                return; // execute already reported it
+            }
+            if self.breakpoints.contains(&self.gb.cpu.pc) {
+                println!("Breakpoint at {:04X}", self.gb.cpu.pc);
+                return;
+            }
+        }
+    }
+
+    // Handles one command line, returns false on quit
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.as_slice() {
+            [] => {}
+            ["s"] => {
+                self.step_watched();
+            }
+            ["s", count] => {
+                let count: u32 = count.parse().map_err(|e| format!("bad count: {}", e))?;
+                for _ in 0..count {
+                    if self.step_watched() {
+                        break;
+                    }
+                }
+            }
+            ["c"] => self.continue_to_breakpoint(),
+            ["b"] => {
+                let list: Vec<String> =
+                    self.breakpoints.iter().map(|b| format!("{:04X}", b)).collect();
+                println!(
+                    "Breakpoints: {}",
+                    if list.is_empty() { "none".to_string() } else { list.join(" ") }
+                );
+            }
+            ["b", addr] => {
+                let addr = parse_hex(addr)?;
+                if !self.breakpoints.contains(&addr) {
+                    self.breakpoints.push(addr);
+                }
+            }
+            ["d", addr] => {
+                let addr = parse_hex(addr)?;
+                self.breakpoints.retain(|&b| b != addr);
+            }
+            ["p"] => {
+                println!("{}", self.registers());
+                let pc = self.gb.cpu.pc;
+                println!("{:04X}: {}", pc, disasm::disassemble_at(&mut self.gb, pc));
+            }
+            ["x", addr] => println!("{}", self.hexdump(parse_hex(addr)?, 0x10)),
+            ["x", addr, len] => println!("{}", self.hexdump(parse_hex(addr)?, parse_hex(len)?)),
+            ["watchvram", "off"] => self.gb.mmu.write_watch = None,
+            ["watchvram", addr] => {
+                let addr = parse_hex(addr)?;
+                self.gb.mmu.write_watch = Some(WriteWatch::new(addr, addr)?);
+            }
+            ["watchvram", start, end] => {
+                let watch = WriteWatch::new(parse_hex(start)?, parse_hex(end)?)?;
+                self.gb.mmu.write_watch = Some(watch);
+            }
+            ["q"] => return Ok(false),
+            _ => return Err(format!("unknown command '{}'\n{}", line.trim(), HELP)),
+        }
+        Ok(true)
+    }
+
+    pub fn run(&mut self) {
+        println!("{}", HELP);
+        let stdin = io::stdin();
+        loop {
+            print!("[{:04X}]> ", self.gb.cpu.pc);
+            io::stdout().flush().ok();
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                return; // EOF
+            }
+            match self.command(&line) {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+}
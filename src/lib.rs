@@ -0,0 +1,41 @@
+pub mod app;
+
+#[path = "core/apu.rs"]
+pub mod apu;
+#[path = "renderer/audio.rs"]
+pub mod audio;
+#[path = "core/cart.rs"]
+pub mod cart;
+#[path = "core/consts.rs"]
+pub mod consts;
+#[path = "core/cpu.rs"]
+pub mod cpu;
+pub mod crash;
+pub mod debugger;
+#[path = "core/disasm.rs"]
+pub mod disasm;
+#[cfg(feature = "gamepad")]
+#[path = "renderer/gamepad.rs"]
+pub mod gamepad;
+#[path = "core/gb.rs"]
+pub mod gb;
+#[path = "core/ips.rs"]
+pub mod ips;
+#[path = "core/joypad.rs"]
+pub mod joypad;
+pub mod link;
+#[path = "core/mmu.rs"]
+pub mod mmu;
+#[path = "renderer/palette.rs"]
+pub mod palette;
+#[path = "core/ppu.rs"]
+pub mod ppu;
+#[path = "core/serial.rs"]
+pub mod serial;
+#[path = "renderer/tiles.rs"]
+pub mod tiles;
+#[path = "core/trace.rs"]
+pub mod trace;
+
+#[path = "renderer/video.rs"]
+pub mod video;
@@ -0,0 +1,81 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::serial::Serial;
+
+// Every message is a tag byte followed by one data byte
+const TRANSFER: u8 = 0x01; // the sender ran an internal-clock transfer, data is its SB
+const REPLY: u8 = 0x02; // the answer to a TRANSFER, data is the receiver's SB
+
+// --link-server / --link-connect: a link cable to another instance over TCP. Whichever side
+// starts an internal-clock transfer sends its SB, the other side answers with its own, and
+// both finish the transfer and raise the serial interrupt. Either side can be the master.
+pub struct Link {
+    stream: TcpStream,
+    received: Vec<u8>, // bytes of a message that hasn't fully arrived yet
+}
+
+impl Link {
+    // Blocks until the other instance connects
+    pub fn listen(port: u16) -> io::Result<Link> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        println!("Waiting for a link partner on port {}", port);
+        let (stream, peer) = listener.accept()?;
+        println!("Link partner connected from {}", peer);
+        Link::from_stream(stream)
+    }
+
+    pub fn connect(addr: &str) -> io::Result<Link> {
+        let stream = TcpStream::connect(addr)?;
+        println!("Connected to link partner at {}", addr);
+        Link::from_stream(stream)
+    }
+
+    fn from_stream(stream: TcpStream) -> io::Result<Link> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Link { stream, received: Vec::new() })
+    }
+
+    fn send(&mut self, tag: u8, data: u8) -> io::Result<()> {
+        self.stream.write_all(&[tag, data])
+    }
+
+    // Sends a transfer the game just started, then answers or completes transfers with
+    // whatever the partner sent since the last call. Never blocks. Errors once the
+    // connection is gone.
+    pub fn update(&mut self, serial: &mut Serial) -> io::Result<()> {
+        if let Some(sb) = serial.outgoing.take() {
+            self.send(TRANSFER, sb)?;
+        }
+
+        let mut buf = [0; 64];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed")),
+                Ok(len) => self.received.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let complete = self.received.len() / 2 * 2;
+        let messages: Vec<u8> = self.received.drain(..complete).collect();
+        for message in messages.chunks(2) {
+            match message[0] {
+                TRANSFER => {
+                    let reply = serial.clocked_by_peer(message[1]);
+                    self.send(REPLY, reply)?;
+                }
+                REPLY => serial.receive_reply(message[1]),
+                tag => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("unknown message tag {:02X}", tag),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,43 +1,296 @@
-mod app;
-
-#[path = "core/apu.rs"]
-mod apu;
-#[path = "renderer/audio.rs"]
-mod audio;
-#[path = "core/cart.rs"]
-mod cart;
-#[path = "core/consts.rs"]
-mod consts;
-#[path = "core/cpu.rs"]
-mod cpu;
-#[path = "core/gb.rs"]
-mod gb;
-#[path = "core/joypad.rs"]
-mod joypad;
-#[path = "core/mmu.rs"]
-mod mmu;
-#[path = "core/ppu.rs"]
-mod ppu;
-
-#[path = "renderer/video.rs"]
-mod video;
-
 use clap::Parser;
+use dot_matrix::app::{self, Options, SyncMode};
+use dot_matrix::consts::SCALE_FACTOR;
+use dot_matrix::cpu::InitialRegisters;
+use dot_matrix::crash;
+use dot_matrix::gb::Model;
+use dot_matrix::joypad::JoypadButton;
+use dot_matrix::mmu::WriteWatch;
+use dot_matrix::palette::{ColorCorrection, Palette};
+use dot_matrix::video::{self, Crop};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    #[arg(required = true)]
-    rom: String,
+    #[arg(required_unless_present = "list_keys")]
+    rom: Option<String>,
 
     #[arg(long)]
     turbo: bool,
+
+    /// Colorize DMG games with the palette the CGB boot ROM would pick
+    #[arg(long)]
+    dmg_palette_from_bootrom: bool,
+
+    /// Sample BGP per pixel so mid-scanline palette writes take effect
+    #[arg(long)]
+    accurate_ppu: bool,
+
+    /// Seconds between flushes of battery-backed RAM to the .sav file (at least 1)
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    autosave_interval: u32,
+
+    /// Load savestates even if they were taken from a different ROM
+    #[arg(long)]
+    force_loadstate: bool,
+
+    /// Buttons that latch on one press and release on the next, e.g. --toggle b,a
+    #[arg(long, value_delimiter = ',')]
+    toggle: Vec<JoypadButton>,
+
+    /// Compare each instruction against a gameboy-doctor style trace (optionally .gz) and stop
+    /// at the first divergence
+    #[arg(long, value_name = "TRACEFILE")]
+    compare: Option<String>,
+
+    /// Only display the x,y,w,h region of the 160x144 frame, scaled to the window
+    #[arg(long, value_name = "X,Y,W,H", default_value = "0,0,160,144")]
+    crop: Crop,
+
+    /// Initial window size as a multiple of the frame (1-8)
+    #[arg(long, default_value_t = SCALE_FACTOR, value_parser = parse_scale)]
+    scale: u32,
+
+    /// Start in fullscreen
+    #[arg(long)]
+    fullscreen: bool,
+
+    /// Only scale the frame by whole multiples, leaving a border instead of uneven pixels
+    #[arg(long)]
+    integer_scale: bool,
+
+    /// Show the emulated frame rate and speed in the corner of the screen
+    #[arg(long)]
+    show_fps: bool,
+
+    /// Refuse to run ROMs whose header checksum doesn't match, like the boot ROM would
+    #[arg(long)]
+    strict: bool,
+
+    /// How far (0-1) the left stick must be pushed to press a direction. Controllers are
+    /// only read in builds with the gamepad feature
+    #[arg(long, value_name = "AMOUNT", default_value_t = 0.5, value_parser = parse_blend_factor)]
+    gamepad_deadzone: f32,
+
+    /// On exit, write an IPS patch of RAM changes since the baseline (power-on, or the last
+    /// F3 press)
+    #[arg(long, value_name = "FILE")]
+    ips_out: Option<String>,
+
+    /// Buttons that repeatedly press and release while held, e.g. --autofire a
+    #[arg(long, value_delimiter = ',')]
+    autofire: Vec<JoypadButton>,
+
+    /// Autofire presses per second
+    #[arg(long, default_value_t = 15.0)]
+    autofire_rate: f32,
+
+    /// Frames between a key event and the joypad seeing it. Non-zero values are for
+    /// experimenting with input latency
+    #[arg(long, default_value_t = 0)]
+    input_delay: u64,
+
+    /// Blend each frame with the previous one like the DMG's slow LCD, FACTOR (0-1) is the
+    /// previous frame's weight
+    #[arg(long, value_name = "FACTOR", value_parser = parse_blend_factor)]
+    ghosting: Option<f32>,
+
+    /// DMG screen colors: grayscale, dmg-green, pocket, or four RRGGBB colors from lightest to
+    /// darkest, e.g. --palette E0F8D0,88C070,346856,081820. --dmg-palette-from-bootrom takes
+    /// precedence
+    #[arg(long, value_name = "NAME|COLORS", default_value = "grayscale")]
+    palette: Palette,
+
+    /// Color correction for GBC colors: none or gbc (default: gbc for GBC games, none otherwise)
+    #[arg(long, value_name = "none|gbc")]
+    color_correction: Option<ColorCorrection>,
+
+    /// Directory for battery saves and savestates, named after the ROM file (<dir>/<rom>.sav,
+    /// <dir>/<rom>.st). Created if missing; defaults to the ROM's directory
+    #[arg(long, value_name = "DIR")]
+    save_dir: Option<String>,
+
+    /// Pace emulation by wall-clock frame time (video) or by the audio device draining
+    /// samples (audio, avoids crackle). Turbo and --no-sound always use video timing
+    #[arg(long, value_name = "audio|video", default_value = "video")]
+    sync: SyncMode,
+
+    /// Don't open an audio device, the APU is still emulated but produces no output
+    #[arg(long)]
+    no_sound: bool,
+
+    /// Open a second window showing the 384 VRAM tiles through BGP, with recently written
+    /// tiles highlighted
+    #[arg(long)]
+    debug_vram: bool,
+
+    /// Periodically print the sound registers and channel state
+    #[arg(long)]
+    log_apu: bool,
+
+    /// Run this 256-byte DMG boot ROM (logo animation) before the game instead of starting
+    /// directly at 0x100
+    #[arg(long, value_name = "PATH")]
+    boot_rom: Option<String>,
+
+    /// Hardware to emulate: dmg, mgb (Pocket) or cgb. Sets the boot register values and
+    /// whether CGB games run in color (default: cgb for GBC games, dmg otherwise)
+    #[arg(long, value_name = "dmg|mgb|cgb")]
+    model: Option<Model>,
+
+    /// Start from these register values instead of the post-boot state, as hex NAME=VALUE
+    /// pairs, e.g. --bootless-state A=11,SP=DFF0,PC=0150 (A-L, F, SP, PC). Also applies to
+    /// --debug
+    #[arg(long, value_name = "REG=VAL,...", conflicts_with = "boot_rom")]
+    bootless_state: Option<InitialRegisters>,
+
+    /// Print bytes sent over the serial port to stdout (test ROMs report results this way)
+    #[arg(long)]
+    echo_serial: bool,
+
+    /// Log the PC and value of every write to a VRAM or OAM range, e.g. --watch-vram 8000,800F
+    #[arg(long, value_name = "START,END", value_parser = parse_write_watch)]
+    watch_vram: Option<WriteWatch>,
+
+    /// Run headless for this many frames, print a hash of the final frame and exit
+    #[arg(long, value_name = "FRAMES")]
+    hash_after: Option<u64>,
+
+    /// Run headless for exactly this many frames as fast as possible, print the speed and exit
+    #[arg(long, value_name = "FRAMES", conflicts_with = "hash_after")]
+    frames: Option<u64>,
+
+    /// With --frames, save the final frame as a PNG
+    #[arg(long, value_name = "PATH", requires = "frames")]
+    frames_png: Option<String>,
+
+    /// Run headless under an interactive debugger on stdin (step, breakpoints, registers,
+    /// memory dumps)
+    #[arg(long)]
+    debug: bool,
+
+    /// Print the CPU state and disassembly of every instruction before it runs (very slow)
+    #[arg(long)]
+    trace: bool,
+
+    /// Wait for another instance to connect a link cable on this TCP port
+    #[arg(long, value_name = "PORT", conflicts_with = "link_connect")]
+    link_server: Option<u16>,
+
+    /// Connect a link cable to an instance started with --link-server
+    #[arg(long, value_name = "HOST:PORT")]
+    link_connect: Option<String>,
+
+    /// Print the cartridge header and exit
+    #[arg(long)]
+    info: bool,
+
+    /// Print the key bindings and exit
+    #[arg(long)]
+    list_keys: bool,
+}
+
+fn parse_blend_factor(s: &str) -> Result<f32, String> {
+    let factor: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if !(0.0..=1.0).contains(&factor) {
+        return Err("must be between 0 and 1".to_string());
+    }
+    Ok(factor)
+}
+
+fn parse_scale(s: &str) -> Result<u32, String> {
+    let scale: u32 = s.parse().map_err(|e| format!("{}", e))?;
+    if !(1..=8).contains(&scale) {
+        return Err("must be between 1 and 8".to_string());
+    }
+    Ok(scale)
+}
+
+fn parse_write_watch(s: &str) -> Result<WriteWatch, String> {
+    let parse_addr = |addr: &str| {
+        let addr = addr.trim();
+        let addr = addr.strip_prefix("0x").or(addr.strip_prefix("0X")).unwrap_or(addr);
+        u16::from_str_radix(addr, 16).map_err(|e| format!("bad address '{}': {}", addr, e))
+    };
+    let (start, end) = s.split_once(',').ok_or("expected START,END in hex")?;
+    WriteWatch::new(parse_addr(start)?, parse_addr(end)?)
 }
 
 fn main() {
     let cli = Cli::parse();
-    let rom_path = cli.rom;
-    let turbo = cli.turbo;
+    if cli.list_keys {
+        video::print_key_bindings();
+        return;
+    }
+
+    let rom_path = cli.rom.expect("clap enforces the ROM argument");
+    if cli.info {
+        app::print_info(&rom_path);
+        return;
+    }
+
+    if let Some(frames) = cli.hash_after {
+        crash::install_panic_hook();
+        app::run_hash_after(&rom_path, frames, cli.model, cli.accurate_ppu);
+        return;
+    }
+
+    if let Some(frames) = cli.frames {
+        crash::install_panic_hook();
+        app::run_frames(
+            &rom_path,
+            frames,
+            cli.frames_png.as_deref(),
+            cli.palette,
+            cli.model,
+            cli.accurate_ppu,
+        );
+        return;
+    }
+
+    if cli.debug {
+        crash::install_panic_hook();
+        app::run_debugger(&rom_path, cli.model, cli.accurate_ppu, cli.bootless_state.as_ref());
+        return;
+    }
+
+    let options = Options {
+        turbo: cli.turbo,
+        dmg_palette_from_bootrom: cli.dmg_palette_from_bootrom,
+        accurate_ppu: cli.accurate_ppu,
+        autosave_interval: cli.autosave_interval,
+        toggle_buttons: cli.toggle,
+        force_loadstate: cli.force_loadstate,
+        compare: cli.compare,
+        crop: cli.crop,
+        scale: cli.scale,
+        fullscreen: cli.fullscreen,
+        integer_scale: cli.integer_scale,
+        show_fps: cli.show_fps,
+        strict: cli.strict,
+        gamepad_deadzone: cli.gamepad_deadzone,
+        ips_out: cli.ips_out,
+        autofire_buttons: cli.autofire,
+        autofire_rate: cli.autofire_rate,
+        input_delay: cli.input_delay,
+        ghosting: cli.ghosting,
+        watch_vram: cli.watch_vram,
+        color_correction: cli.color_correction,
+        log_apu: cli.log_apu,
+        save_dir: cli.save_dir,
+        echo_serial: cli.echo_serial,
+        boot_rom: cli.boot_rom,
+        bootless_state: cli.bootless_state,
+        model: cli.model,
+        palette: cli.palette,
+        no_sound: cli.no_sound,
+        sync: cli.sync,
+        debug_vram: cli.debug_vram,
+        trace: cli.trace,
+        link_server: cli.link_server,
+        link_connect: cli.link_connect,
+    };
 
-    app::run(rom_path, turbo).expect("eframe failed to launch");
+    crash::install_panic_hook();
+    app::run(rom_path, options).expect("eframe failed to launch");
 }
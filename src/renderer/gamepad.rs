@@ -0,0 +1,87 @@
+use gilrs::{Axis, Button, Gilrs};
+
+use crate::joypad::JoypadButton;
+
+pub const BUTTON_BINDINGS: &[(Button, JoypadButton)] = &[
+    (Button::DPadUp, JoypadButton::Up),
+    (Button::DPadDown, JoypadButton::Down),
+    (Button::DPadLeft, JoypadButton::Left),
+    (Button::DPadRight, JoypadButton::Right),
+    (Button::South, JoypadButton::A), // Xbox A, DualShock cross
+    (Button::East, JoypadButton::B),  // Xbox B, DualShock circle
+    (Button::Start, JoypadButton::Start),
+    (Button::Select, JoypadButton::Select),
+];
+
+const ALL_BUTTONS: [JoypadButton; 8] = [
+    JoypadButton::Right,
+    JoypadButton::Left,
+    JoypadButton::Up,
+    JoypadButton::Down,
+    JoypadButton::A,
+    JoypadButton::B,
+    JoypadButton::Select,
+    JoypadButton::Start,
+];
+
+// Controller input for the `gamepad` feature. Every connected pad drives the same joypad, the
+// D-pad and left stick both work as directions. Without a usable controller backend this
+// reports nothing, so the keyboard keeps working alone.
+pub struct Gamepad {
+    gilrs: Option<Gilrs>,
+    deadzone: f32, // stick deflection (0-1) needed to press a direction
+    held: Vec<JoypadButton>,
+}
+
+impl Gamepad {
+    pub fn new(deadzone: f32) -> Gamepad {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                println!("Warning: gamepad support unavailable: {}", e);
+                None
+            }
+        };
+        Gamepad { gilrs, deadzone, held: Vec::new() }
+    }
+
+    // (button, pressed) for every button that changed since the last poll
+    pub fn poll(&mut self) -> Vec<(JoypadButton, bool)> {
+        let Some(gilrs) = &mut self.gilrs else {
+            return Vec::new();
+        };
+        // gilrs only updates pad state while its events are drained
+        while gilrs.next_event().is_some() {}
+
+        let mut down = Vec::new();
+        for (_, pad) in gilrs.gamepads() {
+            for &(button, joypad_button) in BUTTON_BINDINGS {
+                if pad.is_pressed(button) {
+                    down.push(joypad_button);
+                }
+            }
+            // stick Y is positive upwards
+            let (x, y) = (pad.value(Axis::LeftStickX), pad.value(Axis::LeftStickY));
+            if x > self.deadzone {
+                down.push(JoypadButton::Right);
+            }
+            if x < -self.deadzone {
+                down.push(JoypadButton::Left);
+            }
+            if y > self.deadzone {
+                down.push(JoypadButton::Up);
+            }
+            if y < -self.deadzone {
+                down.push(JoypadButton::Down);
+            }
+        }
+
+        let changes = ALL_BUTTONS
+            .iter()
+            .filter(|button| down.contains(button) != self.held.contains(button))
+            .map(|&button| (button, down.contains(&button)))
+            .collect();
+        self.held = down;
+        changes
+    }
+}
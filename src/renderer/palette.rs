@@ -0,0 +1,184 @@
+use std::str::FromStr;
+
+use crate::cart::Cart;
+
+// four 0xRRGGBB colors, lightest (shade 0) to darkest (shade 3)
+#[derive(Clone, Copy)]
+pub struct Palette {
+    pub colors: [u32; 4],
+}
+
+impl Palette {
+    pub const fn new(colors: [u32; 4]) -> Palette {
+        Palette { colors }
+    }
+
+    pub fn color(&self, shade: u8) -> egui::Color32 {
+        let rgb = self.colors[shade as usize & 0b11];
+        egui::Color32::from_rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+    }
+}
+
+// DMG screen palettes for --palette
+pub const GRAYSCALE_PALETTE: Palette = Palette::new([0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]);
+pub const DMG_GREEN_PALETTE: Palette = Palette::new([0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]);
+pub const POCKET_PALETTE: Palette = Palette::new([0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]);
+
+// a preset name, or four custom RRGGBB colors from lightest to darkest
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "grayscale" => return Ok(GRAYSCALE_PALETTE),
+            "dmg-green" => return Ok(DMG_GREEN_PALETTE),
+            "pocket" => return Ok(POCKET_PALETTE),
+            _ => {}
+        }
+
+        let colors = s
+            .split(',')
+            .map(|color| {
+                let color = color.trim();
+                let hex = color.strip_prefix('#').unwrap_or(color);
+                match hex.len() {
+                    6 => u32::from_str_radix(hex, 16).ok(),
+                    _ => None,
+                }
+                .ok_or(format!("bad color '{}', expected RRGGBB", color))
+            })
+            .collect::<Result<Vec<u32>, String>>()?;
+        let colors: [u32; 4] = colors.try_into().map_err(|_| {
+            format!(
+                "unknown palette '{}' (expected grayscale, dmg-green, pocket or four RRGGBB colors)",
+                s
+            )
+        })?;
+        Ok(Palette::new(colors))
+    }
+}
+
+// palettes the CGB boot ROM assigns to a DMG game: one for BG/window, one per OBP
+#[derive(Clone, Copy)]
+pub struct Colorization {
+    pub bg: Palette,
+    pub obj0: Palette,
+    pub obj1: Palette,
+}
+
+impl Colorization {
+    const fn uniform(palette: Palette) -> Colorization {
+        Colorization { bg: palette, obj0: palette, obj1: palette }
+    }
+}
+
+const BROWN: Palette = Palette::new([0xFFFFFF, 0xFFAD63, 0x843100, 0x000000]);
+const RED: Palette = Palette::new([0xFFFFFF, 0xFF8584, 0x943A3A, 0x000000]);
+const DARK_BROWN: Palette = Palette::new([0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108]);
+const BLUE: Palette = Palette::new([0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000]);
+const GREEN: Palette = Palette::new([0xFFFFFF, 0x7BFF31, 0x008400, 0x000000]);
+const DARK_GREEN: Palette = Palette::new([0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000]);
+const PASTEL: Palette = Palette::new([0xFFFFA5, 0xFE9494, 0x9394FE, 0x000000]);
+const ORANGE: Palette = Palette::new([0xFFFFFF, 0xFFFF00, 0xFE0000, 0x000000]);
+const GRAYSCALE: Palette = Palette::new([0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000]);
+
+// Used for unrecognized titles and non-Nintendo licensees (Right + A on the boot screen)
+pub const DEFAULT_COLORIZATION: Colorization =
+    Colorization { bg: DARK_GREEN, obj0: RED, obj1: RED };
+
+// Title checksums for a subset of Nintendo-published titles, each mapped to one of the boot
+// ROM's palette combinations. Some checksums collide, so those entries also match the 4th
+// title character like the boot ROM does.
+const COLORIZATION_TABLE: &[(u8, Option<u8>, Colorization)] = &[
+    (0x14, None, Colorization { bg: RED, obj0: GREEN, obj1: RED }), // POKEMON RED
+    (0x61, Some(b'E'), Colorization { bg: BLUE, obj0: RED, obj1: BLUE }), // POKEMON BLUE
+    (0xAA, None, Colorization { bg: DARK_GREEN, obj0: RED, obj1: BLUE }), // POKEMON GREEN
+    (0xDB, None, Colorization::uniform(ORANGE)),                    // TETRIS
+    (0x0D, Some(b'R'), Colorization::uniform(ORANGE)),              // TETRIS2
+    (0x3C, None, Colorization { bg: PASTEL, obj0: RED, obj1: BLUE }), // DR.MARIO
+    (0x70, None, Colorization { bg: GREEN, obj0: RED, obj1: BLUE }), // ZELDA
+    (0x46, Some(b'E'), Colorization { bg: BROWN, obj0: RED, obj1: BLUE }), // SUPER MARIOLAND
+    (0xC9, None, Colorization { bg: PASTEL, obj0: RED, obj1: GREEN }), // MARIOLAND2
+    (0x19, None, Colorization::uniform(BROWN)),                     // DONKEY KONG
+    (0x18, Some(b'K'), Colorization::uniform(DARK_BROWN)),          // DONKEYKONGLAND
+    (0x88, None, Colorization::uniform(BLUE)),                      // ALLEY WAY
+    (0xD1, None, Colorization { bg: GREEN, obj0: RED, obj1: BLUE }), // TENNIS
+    (0x34, None, Colorization::uniform(GRAYSCALE)),                 // GAMEBOY GALLERY
+];
+
+// Mirrors the CGB boot ROM: only Nintendo-licensed DMG games get a title-specific palette
+pub fn colorization_for(cart: &Cart) -> Option<Colorization> {
+    if cart.is_cgb_compatible() {
+        return None;
+    }
+    if !cart.is_nintendo_licensee() {
+        return Some(DEFAULT_COLORIZATION);
+    }
+
+    let checksum = cart.title_checksum();
+    let fourth_char = cart.rom[0x137];
+    let entry = COLORIZATION_TABLE.iter().find(|(sum, disambiguation, _)| {
+        *sum == checksum && disambiguation.is_none_or(|c| c == fourth_char)
+    });
+
+    Some(entry.map_or(DEFAULT_COLORIZATION, |(_, _, colorization)| *colorization))
+}
+
+// How 15-bit CGB colors are mapped to sRGB, set with --color-correction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorCorrection {
+    None, // plain 5-bit to 8-bit expansion, oversaturated compared to a real screen
+    Gbc,  // approximates the CGB LCD's gamut and contrast
+}
+
+impl FromStr for ColorCorrection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(ColorCorrection::None),
+            "gbc" => Ok(ColorCorrection::Gbc),
+            _ => Err(format!("unknown color correction '{}' (expected none or gbc)", s)),
+        }
+    }
+}
+
+impl ColorCorrection {
+    // GBC games are corrected unless asked otherwise, DMG shades are left alone
+    pub fn default_for(cart: &Cart) -> ColorCorrection {
+        if cart.is_cgb_compatible() {
+            ColorCorrection::Gbc
+        } else {
+            ColorCorrection::None
+        }
+    }
+}
+
+// Converts a CGB palette entry (0bBBBBBGGGGGRRRRR) to sRGB.
+//
+// The GBC correction is the common Gambatte/higan curve: each output channel mixes the 5-bit
+// inputs, which both desaturates and bleeds the channels into each other like the LCD does
+//   r' = (26r + 4g + 2b) / 4
+//   g' = (24g + 8b) / 4
+//   b' = (6r + 4g + 22b) / 4
+// with each sum clamped to 960 first, so channels top out at 240 rather than 255.
+pub fn cgb_color(rgb555: u16, correction: ColorCorrection) -> egui::Color32 {
+    let r = (rgb555 & 0x1F) as u32;
+    let g = ((rgb555 >> 5) & 0x1F) as u32;
+    let b = ((rgb555 >> 10) & 0x1F) as u32;
+
+    match correction {
+        ColorCorrection::None => {
+            let expand = |c: u32| ((c << 3) | (c >> 2)) as u8;
+            egui::Color32::from_rgb(expand(r), expand(g), expand(b))
+        }
+        ColorCorrection::Gbc => {
+            let mix = |sum: u32| (sum.min(960) >> 2) as u8;
+            egui::Color32::from_rgb(
+                mix(r * 26 + g * 4 + b * 2),
+                mix(g * 24 + b * 8),
+                mix(r * 6 + g * 4 + b * 22),
+            )
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::gb::GB;
+use crate::mmu::{TILE_COUNT, TILE_DATA_START};
+use crate::palette::Palette;
+use crate::ppu::PPUMemory;
+
+const TILES_PER_ROW: usize = 16;
+const TILE_ROWS: usize = TILE_COUNT / TILES_PER_ROW; // 24
+const VIEWER_SCALE: f32 = 3.0;
+const HEAT_COLOR: egui::Color32 = egui::Color32::from_rgb(0xFF, 0x40, 0x20);
+
+// --debug-vram: a second window with all 384 tiles of 0x8000-0x97FF in a 16x24 grid, colored
+// through the current BGP. Tiles written recently are tinted using the MMU's VRAM heatmap.
+pub struct TileViewer {
+    texture: Option<egui::TextureHandle>,
+    palette: Palette, // the main window's BG colors
+}
+
+impl TileViewer {
+    pub fn new(gb: &mut GB, palette: Palette) -> TileViewer {
+        gb.set_vram_heatmap(true);
+        TileViewer { texture: None, palette }
+    }
+
+    fn pixels(&self, gb: &GB) -> Vec<egui::Color32> {
+        let bgp = gb.mmu.ram[PPUMemory::BGP as usize];
+        let mut pixels = vec![egui::Color32::BLACK; TILE_COUNT * 64];
+        for tile in 0..TILE_COUNT {
+            let heat = gb.mmu.vram_heatmap.as_ref().map_or(0.0, |heatmap| heatmap.heat(tile));
+            let tile_x = (tile % TILES_PER_ROW) * 8;
+            let tile_y = (tile / TILES_PER_ROW) * 8;
+            for line in 0..8 {
+                let addr = TILE_DATA_START as usize + tile * 16 + line * 2;
+                let (low, high) = (gb.mmu.ram[addr], gb.mmu.ram[addr + 1]);
+                for x in 0..8 {
+                    let bit = 7 - x;
+                    let color_index = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+                    let color = self.palette.color((bgp >> (color_index * 2)) & 0b11);
+                    let row = tile_y + line;
+                    pixels[row * TILES_PER_ROW * 8 + tile_x + x] = if heat > 0.0 {
+                        color.lerp_to_gamma(HEAT_COLOR, heat * 0.6)
+                    } else {
+                        color
+                    };
+                }
+            }
+        }
+        pixels
+    }
+
+    // Called every repaint, returns false once the window is closed, which also stops the
+    // heatmap tracking
+    pub fn show(&mut self, ctx: &egui::Context, gb: &mut GB) -> bool {
+        let size = [TILES_PER_ROW * 8, TILE_ROWS * 8];
+        let image = egui::ColorImage::new(size, self.pixels(gb));
+        let opts = egui::TextureOptions::NEAREST;
+        let texture = match &mut self.texture {
+            Some(handle) => {
+                handle.set(image, opts);
+                handle.id()
+            }
+            None => {
+                let handle = ctx.load_texture("tiles", image, opts);
+                let id = handle.id();
+                self.texture = Some(handle);
+                id
+            }
+        };
+
+        let display_size = egui::vec2(size[0] as f32, size[1] as f32) * VIEWER_SCALE;
+        let builder = egui::ViewportBuilder::default()
+            .with_title("Dot Matrix - VRAM tiles")
+            .with_inner_size(display_size);
+        let close_requested = ctx.show_viewport_immediate(
+            egui::ViewportId::from_hash_of("tiles"),
+            builder,
+            |ui, _| {
+                ui.centered_and_justified(|ui| {
+                    ui.add(egui::Image::new((texture, display_size)).shrink_to_fit());
+                });
+                ui.input(|i| i.viewport().close_requested())
+            },
+        );
+
+        if close_requested {
+            gb.set_vram_heatmap(false);
+        }
+        !close_requested
+    }
+}
@@ -1,23 +1,298 @@
-use crate::consts::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+
+use crate::app::Options;
+use crate::consts::{FRAME_RATE, SCREEN_HEIGHT, SCREEN_WIDTH};
+#[cfg(feature = "gamepad")]
+use crate::gamepad::Gamepad;
 use crate::gb::GB;
 use crate::joypad::JoypadButton;
-use std::time::{Duration, Instant};
+use crate::palette::{self, ColorCorrection, Colorization, Palette};
+use crate::ppu::Layer;
+
+pub const KEY_BINDINGS: &[(egui::Key, JoypadButton)] = &[
+    (egui::Key::ArrowUp, JoypadButton::Up),
+    (egui::Key::ArrowDown, JoypadButton::Down),
+    (egui::Key::ArrowLeft, JoypadButton::Left),
+    (egui::Key::ArrowRight, JoypadButton::Right),
+    (egui::Key::Z, JoypadButton::B),
+    (egui::Key::X, JoypadButton::A),
+    (egui::Key::Enter, JoypadButton::Start),
+    (egui::Key::Space, JoypadButton::Select),
+];
+
+const FPS_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+// fast-forwards while held, like --turbo
+pub const TURBO_KEY: egui::Key = egui::Key::Tab;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Hotkey {
+    SaveState,
+    LoadState,
+    MarkRamBaseline, // for --ips-out
+    SaveSlot(u8),
+    LoadSlot(u8),
+    Pause,
+    Screenshot,
+}
+
+pub const HOTKEYS: &[(egui::Key, Hotkey)] = &[
+    (egui::Key::F1, Hotkey::SaveState),
+    (egui::Key::F2, Hotkey::LoadState),
+    (egui::Key::F3, Hotkey::MarkRamBaseline),
+    (egui::Key::F4, Hotkey::Screenshot),
+    (egui::Key::F5, Hotkey::SaveSlot(0)),
+    (egui::Key::F6, Hotkey::SaveSlot(1)),
+    (egui::Key::F7, Hotkey::SaveSlot(2)),
+    (egui::Key::F8, Hotkey::SaveSlot(3)),
+    (egui::Key::F9, Hotkey::LoadSlot(0)),
+    (egui::Key::F10, Hotkey::LoadSlot(1)),
+    (egui::Key::F11, Hotkey::LoadSlot(2)),
+    (egui::Key::F12, Hotkey::LoadSlot(3)),
+    (egui::Key::P, Hotkey::Pause),
+];
+
+// key_pressed also fires on key repeat, this only counts the initial press
+fn fresh_press(input: &egui::InputState, key: egui::Key) -> bool {
+    input.events.iter().any(
+        |e| matches!(e, egui::Event::Key { key: k, pressed: true, repeat: false, .. } if *k == key),
+    )
+}
+
+pub fn print_key_bindings() {
+    println!("Joypad:");
+    for (key, button) in KEY_BINDINGS {
+        println!("  {:<10} {:?}", key.name(), button);
+    }
+    println!("Hotkeys:");
+    for (key, hotkey) in HOTKEYS {
+        println!("  {:<10} {:?}", key.name(), hotkey);
+    }
+    println!("  {:<10} Turbo (while held)", TURBO_KEY.name());
+    println!("Turbo is always on with --turbo");
+    println!("Buttons passed to --toggle stay held until pressed again");
+    println!("Buttons passed to --autofire repeat at --autofire-rate while held");
+}
+
+// Region of the 160x144 frame that gets displayed, set with --crop x,y,w,h
+#[derive(Clone, Copy, Debug)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .split(',')
+            .map(|part| part.trim().parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|e| format!("expected x,y,w,h ({})", e))?;
+        let &[x, y, width, height] = parts.as_slice() else {
+            return Err("expected x,y,w,h".to_string());
+        };
+
+        if width == 0 || height == 0 || x + width > SCREEN_WIDTH || y + height > SCREEN_HEIGHT {
+            return Err(format!(
+                "crop must be non-empty and lie within {}x{}",
+                SCREEN_WIDTH, SCREEN_HEIGHT
+            ));
+        }
+        Ok(Crop { x, y, width, height })
+    }
+}
+
+// <save path without extension>-<timestamp>.png, next to the .sav and .st files
+pub fn screenshot_path(save_path: &str) -> PathBuf {
+    let mut path = PathBuf::from(save_path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    path.set_file_name(format!("{}-{}.png", stem, Local::now().format("%Y%m%d-%H%M%S%.3f")));
+    path
+}
+
+pub fn save_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+    pixels: &[egui::Color32],
+) -> Result<(), String> {
+    let rgb: Vec<u8> = pixels.iter().flat_map(|pixel| [pixel.r(), pixel.g(), pixel.b()]).collect();
+    image::save_buffer(path, &rgb, width, height, image::ColorType::Rgb8)
+        .map_err(|e| format!("Unable to write {}: {}", path.display(), e))
+}
+
+// per-channel linear mix, factor is the weight of `previous`
+fn blend(current: egui::Color32, previous: egui::Color32, factor: f32) -> egui::Color32 {
+    let mix = |c: u8, p: u8| (c as f32 * (1.0 - factor) + p as f32 * factor).round() as u8;
+    egui::Color32::from_rgb(
+        mix(current.r(), previous.r()),
+        mix(current.g(), previous.g()),
+        mix(current.b(), previous.b()),
+    )
+}
 
 pub struct VideoRenderer {
     texture: Option<egui::TextureHandle>,
-    autosave_timer: Instant,
+    colorization: Option<Colorization>,
+    palette: Palette, // used when there's no colorization
+    color_correction: ColorCorrection,
+    toggle_buttons: Vec<JoypadButton>, // opt-in press-once-to-hold buttons
+    toggled: Vec<JoypadButton>,        // toggle buttons currently held
+    crop: Crop,
+    integer_scale: bool,
+    autofire_buttons: Vec<JoypadButton>,
+    autofire_half_period: u64, // emulated frames spent pressed, then released
+    autofire_active: bool,     // an autofire key is held, shown in the title
+    title: String,
+    ghosting: Option<f32>,     // weight of the previous frame
+    ghost: Vec<egui::Color32>, // last displayed (already blended) frame
+    ghost_frame: Option<u64>,
+    input_delay: u64,                                   // frames
+    pending_input: VecDeque<(u64, JoypadButton, bool)>, // (frame to apply at, button, pressed)
+    pub mark_ram_baseline: bool,                        // F3 was pressed, consumed by the app
+    pub turbo_held: bool,
+    pub paused: bool, // toggled with P, the app stops stepping the GB while set
+    screenshot_pending: bool, // F4 was pressed, the next displayed frame is saved
+    show_fps: bool,
+    fps_sample: (Instant, u64), // start of the current measurement and the frame count then
+    fps: f32,                   // emulated frames per wall-clock second
+    #[cfg(feature = "gamepad")]
+    gamepad: Gamepad,
 }
 
 impl VideoRenderer {
-    pub fn new() -> Self {
-        VideoRenderer { texture: None, autosave_timer: Instant::now() + Duration::from_secs(10) }
+    pub fn new(
+        colorization: Option<Colorization>,
+        color_correction: ColorCorrection,
+        options: &Options,
+    ) -> Self {
+        VideoRenderer {
+            texture: None,
+            colorization,
+            palette: options.palette,
+            color_correction,
+            toggle_buttons: options.toggle_buttons.clone(),
+            toggled: Vec::new(),
+            crop: options.crop,
+            integer_scale: options.integer_scale,
+            autofire_buttons: options.autofire_buttons.clone(),
+            autofire_half_period: ((FRAME_RATE / (2.0 * options.autofire_rate)).round() as u64)
+                .max(1),
+            autofire_active: false,
+            title: "Dot Matrix".to_string(),
+            ghosting: options.ghosting,
+            ghost: Vec::new(),
+            ghost_frame: None,
+            input_delay: options.input_delay,
+            pending_input: VecDeque::new(),
+            mark_ram_baseline: false,
+            turbo_held: false,
+            paused: false,
+            screenshot_pending: false,
+            show_fps: options.show_fps,
+            fps_sample: (Instant::now(), 0),
+            fps: 0.0,
+            #[cfg(feature = "gamepad")]
+            gamepad: Gamepad::new(options.gamepad_deadzone),
+        }
     }
 
-    pub fn update(&mut self, ui: &mut egui::Ui, gb: &mut GB, rom_path: &String) {
-        let pixels: Vec<egui::Color32> =
-            gb.ppu.framebuffer.iter().map(|&pixel| egui::Color32::from_gray(pixel)).collect();
+    // for 15-bit CGB palette entries
+    pub fn cgb_color(&self, rgb555: u16) -> egui::Color32 {
+        palette::cgb_color(rgb555, self.color_correction)
+    }
+
+    // --show-fps: emulated frames per second and the speed relative to real hardware, in the
+    // top left corner of the screen. Drawn over the texture, so screenshots don't include it.
+    fn draw_fps(&mut self, ui: &egui::Ui, gb: &GB, screen: egui::Rect) {
+        let now = Instant::now();
+        let (sample_start, sample_frame) = self.fps_sample;
+        let elapsed = now - sample_start;
+        if elapsed >= FPS_SAMPLE_INTERVAL {
+            // a loaded savestate can move frame_count backwards
+            self.fps =
+                gb.ppu.frame_count.saturating_sub(sample_frame) as f32 / elapsed.as_secs_f32();
+            self.fps_sample = (now, gb.ppu.frame_count);
+        }
+
+        let text = format!("{:.1} FPS {:.0}%", self.fps, self.fps / FRAME_RATE * 100.0);
+        let painter = ui.painter_at(screen);
+        let galley =
+            painter.layout_no_wrap(text, egui::FontId::monospace(12.0), egui::Color32::WHITE);
+        let pos = screen.left_top() + egui::vec2(4.0, 4.0);
+        let background = egui::Rect::from_min_size(pos, galley.size()).expand(2.0);
+        painter.rect_filled(background, 2.0, egui::Color32::from_black_alpha(160));
+        painter.galley(pos, galley, egui::Color32::WHITE);
+    }
+
+    pub fn update(&mut self, ui: &mut egui::Ui, gb: &mut GB, save_path: &str) {
+        // colorization picks the BG, OBJ0 or OBJ1 palette by the layer that drew the pixel,
+        // CGB mode frames already carry their colors
+        // cropping only affects what's shown, the emulated frame is always 160x144
+        let crop = self.crop;
+        let columns = crop.x as usize..(crop.x + crop.width) as usize;
+        let mut pixels: Vec<egui::Color32> = if gb.mmu.cgb {
+            gb.ppu
+                .colors()
+                .chunks(SCREEN_WIDTH as usize)
+                .skip(crop.y as usize)
+                .take(crop.height as usize)
+                .flat_map(|row| &row[columns.clone()])
+                .map(|&rgb555| self.cgb_color(rgb555))
+                .collect()
+        } else {
+            gb.ppu
+                .framebuffer()
+                .chunks(SCREEN_WIDTH as usize)
+                .zip(gb.ppu.layers().chunks(SCREEN_WIDTH as usize))
+                .skip(crop.y as usize)
+                .take(crop.height as usize)
+                .flat_map(|(row, layers)| row[columns.clone()].iter().zip(&layers[columns.clone()]))
+                .map(|(&shade, &layer)| match &self.colorization {
+                    Some(colorization) => match layer {
+                        Layer::Bg => colorization.bg.color(shade),
+                        Layer::Obj0 => colorization.obj0.color(shade),
+                        Layer::Obj1 => colorization.obj1.color(shade),
+                    },
+                    None => self.palette.color(shade),
+                })
+                .collect()
+        };
+
+        // LCD ghosting: mix in the previous displayed frame, once per emulated frame so the
+        // blur doesn't depend on the repaint rate
+        if let Some(factor) = self.ghosting {
+            if self.ghost_frame != Some(gb.ppu.frame_count) && self.ghost.len() == pixels.len() {
+                for (pixel, previous) in pixels.iter_mut().zip(&self.ghost) {
+                    *pixel = blend(*pixel, *previous, factor);
+                }
+            } else if self.ghost.len() == pixels.len() {
+                pixels.clone_from(&self.ghost);
+            }
+            self.ghost_frame = Some(gb.ppu.frame_count);
+            self.ghost.clone_from(&pixels);
+        }
+
+        // the frame as displayed: cropped, colored and with ghosting
+        if std::mem::take(&mut self.screenshot_pending) {
+            let path = screenshot_path(save_path);
+            match save_png(&path, crop.width, crop.height, &pixels) {
+                Ok(()) => println!("Screenshot saved: {}", path.display()),
+                Err(e) => println!("Screenshot failed: {}", e),
+            }
+        }
+
         // map pixel bytes into GPU buffer
-        let image = egui::ColorImage::new([SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize], pixels);
+        let image = egui::ColorImage::new([crop.width as usize, crop.height as usize], pixels);
 
         // need to set NEAREST, else texture is blurry (from bilinear filtering)
         let opts = egui::TextureOptions::NEAREST;
@@ -35,57 +310,129 @@ impl VideoRenderer {
             }
         };
 
-        ui.centered_and_justified(|ui| {
-            ui.add(
-                // doesn't store image, but ImageSource that references existing texture
-                egui::Image::new((tex_id, egui::vec2(SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32)))
-                    .texture_options(opts)
-                    .maintain_aspect_ratio(true)
-                    .shrink_to_fit(),
-            )
+        let frame_size = egui::vec2(crop.width as f32, crop.height as f32);
+        let integer_scale = self.integer_scale;
+        let screen = ui.centered_and_justified(|ui| {
+            // doesn't store image, but ImageSource that references existing texture
+            let image = egui::Image::new((tex_id, frame_size)).texture_options(opts);
+            if integer_scale {
+                // the largest whole multiple of physical pixels that fits, so every Game Boy
+                // pixel comes out the same size
+                let pixels_per_point = ui.ctx().pixels_per_point();
+                let fit = ui.available_size() * pixels_per_point / frame_size;
+                let factor = fit.x.min(fit.y).floor().max(1.0);
+                ui.add(image.fit_to_exact_size(frame_size * factor / pixels_per_point))
+            } else {
+                ui.add(image.maintain_aspect_ratio(true).shrink_to_fit())
+            }
         });
+        if self.show_fps {
+            self.draw_fps(ui, gb, screen.inner.rect);
+        }
 
-        let mut do_savetate = false;
-        let mut do_loadstate = false;
+        let mut do_savestate = None; // Some(slot), None being the F1/F2 state
+        let mut do_loadstate = None;
+        let mut autofire_active = false;
+        let mut input_events: Vec<(JoypadButton, bool)> = Vec::new(); // (button, pressed)
 
-        let autosave_due = gb.cart.battery_support && Instant::now() > self.autosave_timer;
+        // pressed for the first half of each period, counted in emulated frames
+        let autofire_on = (gb.ppu.frame_count / self.autofire_half_period).is_multiple_of(2);
 
         ui.input(|i| {
-            for (key, button) in [
-                (egui::Key::ArrowUp, JoypadButton::Up),
-                (egui::Key::ArrowDown, JoypadButton::Down),
-                (egui::Key::ArrowLeft, JoypadButton::Left),
-                (egui::Key::ArrowRight, JoypadButton::Right),
-                (egui::Key::Z, JoypadButton::B),
-                (egui::Key::X, JoypadButton::A),
-                (egui::Key::Enter, JoypadButton::Start),
-                (egui::Key::Space, JoypadButton::Select),
-            ] {
+            for &(key, button) in KEY_BINDINGS {
+                if self.toggle_buttons.contains(&button) {
+                    if fresh_press(i, key) {
+                        if let Some(index) = self.toggled.iter().position(|&b| b == button) {
+                            self.toggled.remove(index);
+                            input_events.push((button, false));
+                        } else {
+                            self.toggled.push(button);
+                            input_events.push((button, true));
+                        }
+                    }
+                    continue;
+                }
+
+                if self.autofire_buttons.contains(&button) {
+                    if i.key_down(key) {
+                        autofire_active = true;
+                    }
+                    if i.key_down(key) && autofire_on {
+                        input_events.push((button, true));
+                    } else {
+                        input_events.push((button, false));
+                    }
+                    continue;
+                }
+
                 if i.key_pressed(key) {
-                    gb.joypad.press_button(button);
+                    input_events.push((button, true));
                 }
                 if i.key_released(key) {
-                    gb.joypad.release_button(button);
+                    input_events.push((button, false));
                 }
             }
 
-            if i.key_pressed(egui::Key::F1) {
-                do_savetate = true;
-            }
-            if i.key_pressed(egui::Key::F2) {
-                do_loadstate = true;
+            self.turbo_held = i.key_down(TURBO_KEY);
+
+            for &(key, hotkey) in HOTKEYS {
+                // holding a key down would otherwise save or load on every repeat
+                if fresh_press(i, key) {
+                    match hotkey {
+                        Hotkey::SaveState => do_savestate = Some(None),
+                        Hotkey::LoadState => do_loadstate = Some(None),
+                        Hotkey::MarkRamBaseline => self.mark_ram_baseline = true,
+                        Hotkey::SaveSlot(slot) => do_savestate = Some(Some(slot)),
+                        Hotkey::LoadSlot(slot) => do_loadstate = Some(Some(slot)),
+                        Hotkey::Pause => self.paused = !self.paused,
+                        Hotkey::Screenshot => self.screenshot_pending = true,
+                    }
+                }
             }
         });
 
-        if do_savetate {
-            gb.savestate(rom_path);
+        // controller buttons go through the same delay as keys
+        #[cfg(feature = "gamepad")]
+        input_events.extend(self.gamepad.poll());
+
+        // with --input-delay, input reaches the joypad a fixed number of emulated frames later
+        let apply_at = gb.ppu.frame_count + self.input_delay;
+        self.pending_input.extend(input_events.into_iter().map(|(b, p)| (apply_at, b, p)));
+        while let Some(&(frame, button, pressed)) = self.pending_input.front() {
+            if frame > gb.ppu.frame_count {
+                break;
+            }
+            if pressed {
+                gb.joypad.press_button(button);
+            } else {
+                gb.joypad.release_button(button);
+            }
+            self.pending_input.pop_front();
+        }
+
+        self.autofire_active = autofire_active;
+        let mut title = "Dot Matrix".to_string();
+        if self.paused {
+            title += " [paused]";
         }
-        if do_loadstate {
-            gb.loadstate(rom_path);
+        if self.autofire_active {
+            title += " [autofire]";
+        }
+        if title != self.title {
+            ui.ctx().send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.title = title;
         }
 
-        if autosave_due {
-            gb.mmu.saveram(rom_path, &gb.cart);
+        // a failed save or load is reported and the game keeps running
+        if let Some(slot) = do_savestate {
+            if let Err(e) = gb.savestate(save_path, slot) {
+                println!("Error: {}", e);
+            }
+        }
+        if let Some(slot) = do_loadstate {
+            if let Err(e) = gb.loadstate(save_path, slot) {
+                println!("Error: {}", e);
+            }
         }
 
         ui.ctx().request_repaint();
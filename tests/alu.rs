@@ -0,0 +1,65 @@
+mod common;
+
+use common::gb_with_program;
+use dot_matrix::cpu::FlagRegister;
+use dot_matrix::gb::GB;
+
+fn run(gb: &mut GB, instructions: usize) {
+    for _ in 0..instructions {
+        gb.step();
+    }
+}
+
+#[test]
+fn inc_half_carry_comes_from_the_low_nibble() {
+    // LD A,0x0F; INC A
+    let mut gb = gb_with_program(&[0x3E, 0x0F, 0x3C]);
+    run(&mut gb, 2);
+    assert_eq!(gb.cpu.a, 0x10);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::HalfCarry), 1);
+
+    // LD A,0x10; INC A
+    let mut gb = gb_with_program(&[0x3E, 0x10, 0x3C]);
+    run(&mut gb, 2);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::HalfCarry), 0);
+
+    // LD A,0xFF; INC A: wraps to zero, carrying out of the low nibble, and leaves C alone
+    let mut gb = gb_with_program(&[0x3E, 0xFF, 0x3C]);
+    run(&mut gb, 2);
+    assert_eq!(gb.cpu.a, 0x00);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::Zero), 1);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::HalfCarry), 1);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::Carry), 0);
+}
+
+#[test]
+fn dec_half_carry_borrows_from_bit_4() {
+    // LD A,0x10; DEC A
+    let mut gb = gb_with_program(&[0x3E, 0x10, 0x3D]);
+    run(&mut gb, 2);
+    assert_eq!(gb.cpu.a, 0x0F);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::HalfCarry), 1);
+
+    // LD A,0x11; DEC A
+    let mut gb = gb_with_program(&[0x3E, 0x11, 0x3D]);
+    run(&mut gb, 2);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::HalfCarry), 0);
+}
+
+#[test]
+fn sbc_includes_the_carry_in_both_borrows() {
+    // SCF; LD A,0x00; SBC A,0xFF: 0x00 - 0xFF - 1 borrows out of both nibbles
+    let mut gb = gb_with_program(&[0x37, 0x3E, 0x00, 0xDE, 0xFF]);
+    run(&mut gb, 3);
+    assert_eq!(gb.cpu.a, 0x00);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::Zero), 1);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::HalfCarry), 1);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::Carry), 1);
+
+    // SCF; LD A,0x10; SBC A,0x0F: the carry makes the low nibble borrow, not the high one
+    let mut gb = gb_with_program(&[0x37, 0x3E, 0x10, 0xDE, 0x0F]);
+    run(&mut gb, 3);
+    assert_eq!(gb.cpu.a, 0x00);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::HalfCarry), 1);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::Carry), 0);
+}
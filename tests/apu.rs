@@ -0,0 +1,47 @@
+use dot_matrix::apu::APU;
+use dot_matrix::consts::APU_RAM;
+use ringbuf::{traits::Split, HeapRb};
+
+fn apu() -> APU {
+    let (producer, _consumer) = HeapRb::<f32>::new(1).split();
+    APU::new(producer, 44100.0)
+}
+
+// Triggers a channel with length enabled and counts the length steps until it turns itself off
+fn length_steps(dac_register: u16, dac_on: u8, trigger_register: u16, channel: u8) -> u32 {
+    let mut apu = apu();
+    apu.write_register(dac_register, dac_on);
+    apu.write_register(trigger_register, 0b1100_0000);
+    let mut steps = 0;
+    while apu.read_register(APU_RAM::NR52) & 1 << channel != 0 && steps < 1000 {
+        apu.clock_length_timers();
+        steps += 1;
+    }
+    steps
+}
+
+#[test]
+fn triggering_with_length_0_plays_the_full_length() {
+    assert_eq!(length_steps(APU_RAM::NR12, 0xF0, APU_RAM::NR14, 0), 64);
+    assert_eq!(length_steps(APU_RAM::NR22, 0xF0, APU_RAM::NR24, 1), 64);
+    assert_eq!(length_steps(APU_RAM::NR30, 0x80, APU_RAM::NR34, 2), 256);
+    assert_eq!(length_steps(APU_RAM::NR42, 0xF0, APU_RAM::NR44, 3), 64);
+}
+
+#[test]
+fn retriggering_an_expired_channel_reloads_its_length() {
+    let mut apu = apu();
+    apu.write_register(APU_RAM::NR12, 0xF0);
+    apu.write_register(APU_RAM::NR11, 0x3F); // one step left
+    apu.write_register(APU_RAM::NR14, 0b1100_0000);
+    apu.clock_length_timers();
+    assert_eq!(apu.read_register(APU_RAM::NR52) & 1, 0);
+
+    apu.write_register(APU_RAM::NR14, 0b1100_0000);
+    for _ in 0..63 {
+        apu.clock_length_timers();
+    }
+    assert_eq!(apu.read_register(APU_RAM::NR52) & 1, 1);
+    apu.clock_length_timers();
+    assert_eq!(apu.read_register(APU_RAM::NR52) & 1, 0);
+}
@@ -0,0 +1,132 @@
+use dot_matrix::cart::Cart;
+
+fn test_rom() -> Vec<u8> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/roms/cpu_instrs/01-special.gb");
+    std::fs::read(path).expect("read test ROM")
+}
+
+#[test]
+fn checksums_match_a_good_dump() {
+    let cart = Cart::from_rom(test_rom());
+    assert!(cart.header_checksum_ok());
+    assert!(cart.global_checksum_ok());
+}
+
+#[test]
+fn checksums_catch_a_corrupt_header() {
+    let mut rom = test_rom();
+    rom[0x134] ^= 0xFF;
+    let cart = Cart::from_rom(rom);
+    assert!(!cart.header_checksum_ok());
+    assert!(!cart.global_checksum_ok());
+}
+
+// ROM of the given header size code whose banks start with their bank number
+fn banked_rom(cartridge_type: u8, rom_size_code: u8) -> Vec<u8> {
+    let banks = 2 << rom_size_code;
+    let mut rom = vec![0; banks * 0x4000];
+    for bank in 0..banks {
+        rom[bank * 0x4000] = bank as u8;
+    }
+    rom[0x147] = cartridge_type;
+    rom[0x148] = rom_size_code;
+    rom
+}
+
+// 256 KiB MBC2+BATTERY
+fn mbc2_rom() -> Vec<u8> {
+    banked_rom(0x06, 0x03)
+}
+
+#[test]
+fn mbc2_decodes_registers_by_address_bit_8() {
+    let mut cart = Cart::from_rom(mbc2_rom());
+    cart.write_rom(0x2100, 0x05);
+    assert_eq!(cart.read_rom(0x4000), 5);
+    cart.write_rom(0x0100, 0x00); // bank 0 maps to 1
+    assert_eq!(cart.read_rom(0x4000), 1);
+
+    // with bit 8 clear it's the RAM enable register, the bank doesn't change
+    cart.write_rom(0x2000, 0x0A);
+    assert_eq!(cart.read_rom(0x4000), 1);
+    assert!(cart.ram_enabled);
+}
+
+#[test]
+fn mbc2_ram_is_512_nibbles() {
+    let mut cart = Cart::from_rom(mbc2_rom());
+    assert_eq!(cart.ram.len(), 512);
+    cart.write_rom(0x0000, 0x0A);
+    cart.write_ram(0xA000, 0x5A);
+    assert_eq!(cart.read_ram(0xA000), 0xFA);
+    assert_eq!(cart.read_ram(0xA200), 0xFA); // repeats every 512 bytes
+}
+
+#[test]
+fn mbc1_bank_register_is_5_bits_and_never_0() {
+    let mut cart = Cart::from_rom(banked_rom(0x01, 0x06)); // 2 MiB
+    cart.write_rom(0x2000, 0x00);
+    assert_eq!(cart.read_rom(0x4000), 1);
+    cart.write_rom(0x2000, 0xE5); // only bits 0-4 are kept
+    assert_eq!(cart.read_rom(0x4000), 0x05);
+    cart.write_rom(0x2000, 0x20); // the zero check only looks at the 5 bit value
+    assert_eq!(cart.read_rom(0x4000), 1);
+
+    // the upper bits come from 0x4000-0x5FFF, so bank 0x20 reads as 0x21
+    cart.write_rom(0x4000, 0x01);
+    cart.write_rom(0x2000, 0x00);
+    assert_eq!(cart.read_rom(0x4000), 0x21);
+    cart.write_rom(0x4000, 0x03);
+    cart.write_rom(0x2000, 0x1F);
+    assert_eq!(cart.read_rom(0x4000), 0x7F);
+}
+
+#[test]
+fn mbc3_bank_register_is_7_bits_and_never_0() {
+    let mut cart = Cart::from_rom(banked_rom(0x11, 0x06)); // 2 MiB
+    cart.write_rom(0x2000, 0x00);
+    assert_eq!(cart.read_rom(0x4000), 1);
+    cart.write_rom(0x2000, 0x7F);
+    assert_eq!(cart.read_rom(0x4000), 0x7F);
+    cart.write_rom(0x2000, 0x85); // bit 7 is ignored
+    assert_eq!(cart.read_rom(0x4000), 0x05);
+    cart.write_rom(0x2000, 0x80);
+    assert_eq!(cart.read_rom(0x4000), 1);
+}
+
+#[test]
+fn mbc5_bank_register_is_9_bits_and_can_be_0() {
+    let mut cart = Cart::from_rom(banked_rom(0x19, 0x07)); // 4 MiB
+    cart.write_rom(0x2000, 0x00); // bank 0 can be mapped to 0x4000-0x7FFF
+    assert_eq!(cart.read_rom(0x4000), 0);
+    cart.write_rom(0x2000, 0xFF);
+    assert_eq!(cart.read_rom(0x4000), 0xFF);
+
+    // bit 8 lives at 0x3000-0x3FFF and only keeps bit 0
+    cart.write_rom(0x3000, 0xFF);
+    assert_eq!(cart.rom_bank_selected, 0x1FF);
+    cart.write_rom(0x2000, 0x05);
+    assert_eq!(cart.rom_bank_selected, 0x105);
+    // a 4 MiB ROM only has 256 banks, so bit 8 is masked off when reading
+    assert_eq!(cart.read_rom(0x4000), 0x05);
+    cart.write_rom(0x3000, 0x00);
+    assert_eq!(cart.rom_bank_selected, 0x005);
+}
+
+#[test]
+fn rom_offset_follows_mbc1_mode_1_and_the_rom_size() {
+    let mut cart = Cart::from_rom(banked_rom(0x01, 0x06)); // 2 MiB
+    cart.write_rom(0x4000, 0x01);
+    cart.write_rom(0x2000, 0x05);
+    assert_eq!(cart.rom_offset(0x0123), 0x0123);
+    assert_eq!(cart.rom_offset(0x4123), 0x25 * 0x4000 + 0x123);
+    cart.write_rom(0x6000, 0x01); // mode 1 maps the upper bits over 0x0000-0x3FFF too
+    assert_eq!(cart.rom_offset(0x0123), 0x20 * 0x4000 + 0x123);
+
+    // a 256 KiB ROM has no bank 0x25, only its low 4 bits are wired up
+    let mut cart = Cart::from_rom(banked_rom(0x01, 0x03));
+    cart.write_rom(0x4000, 0x01);
+    cart.write_rom(0x2000, 0x05);
+    assert_eq!(cart.rom_offset(0x4000), 0x05 * 0x4000);
+    assert_eq!(cart.read_rom(0x4000), 0x05);
+}
@@ -0,0 +1,59 @@
+use dot_matrix::gb::GB;
+
+// LD B,B, which mooneye test ROMs execute once they're done
+const MOONEYE_BREAKPOINT: u8 = 0x40;
+// B, C, D, E, H, L after a passing mooneye test, a failing one leaves 0x42 everywhere
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+// Runs a Blargg or mooneye test ROM headless until it reports a result or max_frames of
+// emulated time pass. Blargg ROMs print "Passed"/"Failed" over serial, mooneye ROMs signal
+// with the LD B,B breakpoint and a register pattern. Err carries the serial output so far.
+#[allow(dead_code)]
+pub fn run_test_rom(path: &str, max_frames: u32) -> Result<(), String> {
+    let mut gb = GB::new_headless(path);
+    let max_cycles = max_frames as u64 * dot_matrix::consts::CYCLES_PER_FRAME as u64;
+    let mut cycles = 0u64;
+    let mut serial_len = 0;
+
+    while cycles < max_cycles {
+        let pc = gb.cpu.pc;
+        if gb.mmu.read_byte(pc, &gb.cart, &gb.joypad, &mut gb.apu) == MOONEYE_BREAKPOINT {
+            let cpu = &gb.cpu;
+            let registers = [cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l];
+            if registers == MOONEYE_PASS {
+                return Ok(());
+            }
+            if registers == [0x42; 6] {
+                return Err(format!("{}: mooneye test failed", path));
+            }
+        }
+
+        cycles += gb.step() as u64;
+
+        // only rescan the output when a new byte arrived
+        if gb.serial_output().len() != serial_len {
+            serial_len = gb.serial_output().len();
+            let output = String::from_utf8_lossy(gb.serial_output());
+            if output.contains("Passed") {
+                return Ok(());
+            }
+            if output.contains("Failed") {
+                return Err(format!("{}: {}", path, output.trim()));
+            }
+        }
+    }
+
+    let output = String::from_utf8_lossy(gb.serial_output()).into_owned();
+    Err(format!("{}: no result after {} frames, serial output: {:?}", path, max_frames, output))
+}
+
+// A headless GB with program copied to work RAM at 0xC000 and the PC pointing at it. The cart
+// is cpu_instrs' first ROM, only there to satisfy GB::new.
+#[allow(dead_code)] // not every test binary uses both helpers
+pub fn gb_with_program(program: &[u8]) -> GB {
+    let rom = format!("{}/tests/roms/cpu_instrs/01-special.gb", env!("CARGO_MANIFEST_DIR"));
+    let mut gb = GB::new_headless(&rom);
+    gb.load_bytes(0xC000, program);
+    gb.cpu.pc = 0xC000;
+    gb
+}
@@ -0,0 +1,213 @@
+mod common;
+
+use common::gb_with_program;
+use dot_matrix::consts::CYCLES_PER_FRAME;
+use dot_matrix::cpu::{FlagRegister, InitialRegisters, Register, CPU};
+use dot_matrix::crash;
+use dot_matrix::gb::{Model, GB};
+use dot_matrix::joypad::JoypadButton;
+
+fn run(gb: &mut GB, instructions: usize) {
+    for _ in 0..instructions {
+        gb.step();
+    }
+}
+
+#[test]
+fn sp_plus_negative_offset_flags_come_from_the_low_byte() {
+    // LD SP,0x0001; ADD SP,-1
+    let mut gb = gb_with_program(&[0x31, 0x01, 0x00, 0xE8, 0xFF]);
+    run(&mut gb, 2);
+    assert_eq!(gb.cpu.sp, 0x0000);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::HalfCarry), 1);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::Carry), 1);
+
+    // LD SP,0x0100; LD HL,SP-1: no carries out of a zero low byte
+    let mut gb = gb_with_program(&[0x31, 0x00, 0x01, 0xF8, 0xFF]);
+    run(&mut gb, 2);
+    assert_eq!(gb.cpu.get_hl(), 0x00FF);
+    assert_eq!(gb.cpu.sp, 0x0100);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::HalfCarry), 0);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::Carry), 0);
+    assert_eq!(gb.cpu.get_flag(FlagRegister::Zero), 0);
+}
+
+#[test]
+fn illegal_opcode_locks_up_the_cpu() {
+    // EI; NOP; illegal 0xD3, with an interrupt pending that must not be taken
+    let mut gb = gb_with_program(&[0xFB, 0x00, 0xD3]);
+    gb.mmu.ram[0xFFFF] = 0x01;
+    gb.mmu.ram[0xFF0F] = 0x00;
+    run(&mut gb, 3);
+    gb.mmu.ram[0xFF0F] = 0x01;
+    run(&mut gb, 10);
+    assert!(gb.cpu.locked);
+    assert_eq!(gb.cpu.pc, 0xC002);
+}
+
+#[test]
+fn stop_completes_an_armed_speed_switch() {
+    // STOP; NOP
+    let mut gb = gb_with_program(&[0x10, 0x00, 0x00]);
+    gb.cart.cgb_flag = 0x80;
+    gb.mmu.cgb = true;
+    gb.mmu.write_byte(0xFF4D, 0x01, &mut gb.cart, &mut gb.joypad, &mut gb.apu);
+    gb.step();
+    assert!(gb.mmu.double_speed());
+    assert_eq!(gb.mmu.read_byte(0xFF4D, &gb.cart, &gb.joypad, &mut gb.apu), 0xFE);
+    // the NOP's 4 CPU cycles are 2 at the PPU's clock
+    assert_eq!(gb.step(), 2);
+}
+
+#[test]
+fn frame_budgets_carry_the_overshoot() {
+    let rom = format!("{}/tests/roms/cpu_instrs/02-interrupts.gb", env!("CARGO_MANIFEST_DIR"));
+    let mut gb = GB::new_headless(&rom);
+    for _ in 0..1000 {
+        gb.run_for(CYCLES_PER_FRAME, |_| {});
+    }
+    let budget = 1000 * CYCLES_PER_FRAME as u64;
+    // the overshoot is at most one instruction plus an interrupt dispatch
+    assert!(gb.total_cycles >= budget && gb.total_cycles - budget < 24 + 20);
+    assert_eq!(gb.current_cycles as u64, gb.total_cycles - budget);
+}
+
+#[test]
+fn initial_registers_override_only_what_they_name() {
+    let registers: InitialRegisters = "a=11, F=0xBF,SP=DFF0,PC=0150".parse().unwrap();
+    let mut cpu = CPU::new();
+    registers.apply(&mut cpu);
+    assert_eq!((cpu.a, cpu.f, cpu.sp, cpu.pc), (0x11, 0xB0, 0xDFF0, 0x0150));
+    assert_eq!(cpu.get_hl(), CPU::new().get_hl());
+
+    assert!("A=100".parse::<InitialRegisters>().is_err());
+    assert!("IX=0".parse::<InitialRegisters>().is_err());
+    assert!("PC".parse::<InitialRegisters>().is_err());
+    assert!("SP=10000".parse::<InitialRegisters>().is_err());
+    assert_eq!("sp".parse::<Register>(), Ok(Register::SP));
+}
+
+#[test]
+fn models_boot_with_their_own_register_values() {
+    let mut gb = gb_with_program(&[]);
+    gb.cart.cgb_flag = 0x80;
+
+    gb.set_model(Model::Dmg);
+    assert_eq!((gb.cpu.a, gb.cpu.f), (0x01, 0xB0));
+    assert!(!gb.mmu.cgb);
+    gb.set_model(Model::Mgb);
+    assert_eq!(gb.cpu.a, 0xFF);
+    gb.set_model(Model::Cgb);
+    assert_eq!((gb.cpu.a, gb.cpu.d, gb.cpu.e), (0x11, 0xFF, 0x56));
+    assert!(gb.mmu.cgb);
+
+    // a DMG-only game on a CGB still sees A = 0x11 but runs in DMG mode
+    gb.cart.cgb_flag = 0x00;
+    gb.set_model(Model::Cgb);
+    assert_eq!((gb.cpu.a, gb.cpu.e, gb.cpu.l), (0x11, 0x08, 0x7C));
+    assert!(!gb.mmu.cgb);
+}
+
+#[test]
+fn crash_reports_list_the_recent_instructions() {
+    // LD A,0x0F; INC A; then NOPs
+    let mut gb = gb_with_program(&[0x3E, 0x0F, 0x3C]);
+    run(&mut gb, 20);
+    assert_eq!(gb.cpu.recent.len(), 16);
+    assert_eq!(gb.cpu.recent.back(), Some(&(0xC014, 0x00)));
+
+    let mut gb = gb_with_program(&[0x3E, 0x0F, 0x3C]);
+    run(&mut gb, 2);
+    let report = crash::crash_report(&gb);
+    assert!(report.contains("C000: 3E\nC002: 3C\n"), "{}", report);
+}
+
+#[test]
+fn stop_resets_div() {
+    // STOP; NOP
+    let mut gb = gb_with_program(&[0x10, 0x00, 0x00]);
+    gb.mmu.ram[0xFF04] = 0x5A;
+    gb.cpu.div_cycles = 200;
+    gb.step();
+    assert!(gb.cpu.stopped);
+    run(&mut gb, 100);
+    assert_eq!((gb.mmu.ram[0xFF04], gb.cpu.div_cycles), (0, 0));
+
+    gb.joypad.press_button(JoypadButton::A);
+    gb.step();
+    assert!(!gb.cpu.stopped);
+    assert_eq!(gb.mmu.ram[0xFF04], 0);
+}
+
+#[test]
+fn vblank_wins_when_every_interrupt_is_pending() {
+    // NOP
+    let mut gb = gb_with_program(&[0x00]);
+    gb.cpu.ime = true;
+    gb.mmu.ram[0xFFFF] = 0x1F;
+    gb.mmu.ram[0xFF0F] = 0x1F;
+    gb.step();
+    assert_eq!(gb.cpu.pc, 0x0040);
+    assert_eq!(gb.mmu.ram[0xFF0F] & 0x1F, 0x1E);
+    assert!(!gb.cpu.ime);
+}
+
+#[test]
+fn call_and_ret_wrap_the_stack_pointer() {
+    // LD SP,0x0001; CALL 0xC010, at 0xC010: RET
+    let mut gb = gb_with_program(&[0x31, 0x01, 0x00, 0xCD, 0x10, 0xC0]);
+    gb.load_bytes(0xC010, &[0xC9]);
+    gb.step();
+    assert_eq!(gb.step(), 24);
+    assert_eq!((gb.cpu.pc, gb.cpu.sp), (0xC010, 0xFFFF));
+    // the return address' high byte went to 0x0000 (ROM, so it's dropped), its low to IE
+    assert_eq!(gb.mmu.ram[0xFFFF], 0x06);
+
+    // popping from 0xFFFF wraps around to 0x0000 for the high byte
+    let rom_byte = gb.mmu.read_byte(0x0000, &gb.cart, &gb.joypad, &mut gb.apu);
+    assert_eq!(gb.step(), 16);
+    assert_eq!((gb.cpu.pc, gb.cpu.sp), ((rom_byte as u16) << 8 | 0x06, 0x0001));
+}
+
+#[test]
+fn conditional_calls_and_returns_cost_more_when_taken() {
+    // LD SP,0xDFF0; CALL NZ,0xC010; CALL Z,0xC010, at 0xC010: RET Z; RET NZ
+    let mut gb = gb_with_program(&[0x31, 0xF0, 0xDF, 0xC4, 0x10, 0xC0, 0xCC, 0x10, 0xC0]);
+    gb.load_bytes(0xC010, &[0xC8, 0xC0]);
+    gb.step();
+    gb.cpu.f = 0x80; // Z set
+    assert_eq!(gb.step(), 12);
+    assert_eq!(gb.step(), 24);
+    assert_eq!(gb.step(), 20);
+    assert_eq!(gb.cpu.pc, 0xC009);
+
+    gb.cpu.pc = 0xC011;
+    gb.cpu.sp = 0xDFEE;
+    assert_eq!(gb.step(), 8);
+    assert_eq!(gb.cpu.pc, 0xC012);
+}
+
+#[test]
+fn reti_returns_with_interrupts_enabled() {
+    // LD SP,0xDFF0; CALL 0xC010, at 0xC010: RETI
+    let mut gb = gb_with_program(&[0x31, 0xF0, 0xDF, 0xCD, 0x10, 0xC0]);
+    gb.load_bytes(0xC010, &[0xD9]);
+    run(&mut gb, 2);
+    assert!(!gb.cpu.ime);
+    assert_eq!(gb.step(), 16);
+    assert!(gb.cpu.ime);
+    assert_eq!((gb.cpu.pc, gb.cpu.sp), (0xC006, 0xDFF0));
+}
+
+#[test]
+fn ei_halt_with_an_interrupt_pending_returns_to_the_halt() {
+    // LD SP,0xDFF0; EI; HALT
+    let mut gb = gb_with_program(&[0x31, 0xF0, 0xDF, 0xFB, 0x76]);
+    gb.mmu.ram[0xFFFF] = 0x04;
+    gb.mmu.ram[0xFF0F] = 0x04;
+    run(&mut gb, 3);
+    assert_eq!(gb.cpu.pc, 0x0050);
+    assert!(!gb.cpu.halted && !gb.cpu.halt_bug);
+    // the pushed return address is the HALT, not the byte after it
+    assert_eq!((gb.mmu.ram[0xDFEF], gb.mmu.ram[0xDFEE]), (0xC0, 0x04));
+}
@@ -0,0 +1,64 @@
+mod common;
+
+// Blargg's cpu_instrs, one ROM per test so failures point at an instruction group
+fn cpu_instrs(rom: &str) {
+    let path = format!("{}/tests/roms/cpu_instrs/{}", env!("CARGO_MANIFEST_DIR"), rom);
+    if let Err(e) = common::run_test_rom(&path, 4000) {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+fn special() {
+    cpu_instrs("01-special.gb");
+}
+
+#[test]
+fn interrupts() {
+    cpu_instrs("02-interrupts.gb");
+}
+
+#[test]
+fn op_sp_hl() {
+    cpu_instrs("03-op sp,hl.gb");
+}
+
+#[test]
+fn op_r_imm() {
+    cpu_instrs("04-op r,imm.gb");
+}
+
+#[test]
+fn op_rp() {
+    cpu_instrs("05-op rp.gb");
+}
+
+#[test]
+fn ld_r_r() {
+    cpu_instrs("06-ld r,r.gb");
+}
+
+#[test]
+fn jr_jp_call_ret_rst() {
+    cpu_instrs("07-jr,jp,call,ret,rst.gb");
+}
+
+#[test]
+fn misc_instrs() {
+    cpu_instrs("08-misc instrs.gb");
+}
+
+#[test]
+fn op_r_r() {
+    cpu_instrs("09-op r,r.gb");
+}
+
+#[test]
+fn bit_ops() {
+    cpu_instrs("10-bit ops.gb");
+}
+
+#[test]
+fn op_a_hl() {
+    cpu_instrs("11-op a,(hl).gb");
+}
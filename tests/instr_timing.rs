@@ -0,0 +1,175 @@
+mod common;
+
+use common::gb_with_program;
+use dot_matrix::consts::{CB_OPCODES, OPCODES};
+use dot_matrix::cpu::FlagRegister;
+use dot_matrix::gb::GB;
+
+// Pan Docs instruction timings in M-cycles, conditional instructions when not taken. 0 marks
+// the illegal opcodes, 0xCB is the prefix alone.
+#[rustfmt::skip]
+const TIMINGS: [u8; 256] = [
+    1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1, // 0x00
+    1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1, // 0x10
+    2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 0x20
+    2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 0x30
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x40
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x50
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x60
+    2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1, // 0x70
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x80
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x90
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0xA0
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0xB0
+    2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 1, 3, 6, 2, 4, // 0xC0
+    2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4, // 0xD0
+    3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4, // 0xE0
+    3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4, // 0xF0
+];
+
+// M-cycles of the conditional instructions when the condition holds
+const TAKEN_TIMINGS: &[(u8, u8)] = &[
+    (0x20, 3),
+    (0x28, 3),
+    (0x30, 3),
+    (0x38, 3), // JR cc
+    (0xC0, 5),
+    (0xC8, 5),
+    (0xD0, 5),
+    (0xD8, 5), // RET cc
+    (0xC2, 4),
+    (0xCA, 4),
+    (0xD2, 4),
+    (0xDA, 4), // JP cc
+    (0xC4, 6),
+    (0xCC, 6),
+    (0xD4, 6),
+    (0xDC, 6), // CALL cc
+];
+
+// Instruction lengths in bytes, 0xCB counted alone like in OPCODES
+#[rustfmt::skip]
+const LENGTHS: [u8; 256] = [
+    1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1, // 0x00
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 0x10
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 0x20
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 0x30
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x40
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x50
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x60
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x70
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x80
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x90
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0xA0
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0xB0
+    1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 1, 3, 3, 2, 1, // 0xC0
+    1, 1, 3, 1, 3, 1, 2, 1, 1, 1, 3, 1, 3, 1, 2, 1, // 0xD0
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1, // 0xE0
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1, // 0xF0
+];
+
+// CB-prefixed M-cycles, prefix included: BIT n,(HL) only reads memory, the other (HL)
+// operations read and write it
+fn cb_timing(cb_opcode: u8) -> u8 {
+    match cb_opcode {
+        _ if cb_opcode & 0b111 != 6 => 2,
+        0x40..=0x7F => 3,
+        _ => 4,
+    }
+}
+
+fn taken_timing(opcode: u8) -> Option<u8> {
+    TAKEN_TIMINGS.iter().find(|&&(op, _)| op == opcode).map(|&(_, timing)| timing)
+}
+
+// Runs one instruction through CPU::execute with HL and SP pointing at work RAM, returning its
+// cycles. For conditional instructions, the flags make the condition hold if `taken`.
+fn execute(bytes: &[u8], taken: bool) -> u8 {
+    let mut gb: GB = gb_with_program(bytes);
+    gb.cpu.set_hl(0xC100);
+    gb.cpu.sp = 0xDFF0;
+    // condition bits 3-4: NZ, Z, NC, C
+    let (flag, set_when_taken) = match bytes[0] >> 3 & 0b11 {
+        0 => (FlagRegister::Zero, false),
+        1 => (FlagRegister::Zero, true),
+        2 => (FlagRegister::Carry, false),
+        _ => (FlagRegister::Carry, true),
+    };
+    gb.cpu.f = 0;
+    gb.cpu.set_flag(flag, set_when_taken == taken);
+    gb.cpu.execute(bytes[0], &mut gb.mmu, &mut gb.cart, &mut gb.joypad, &mut gb.apu)
+}
+
+#[test]
+fn opcode_timings_match_the_reference() {
+    let mut mismatches = Vec::new();
+    for opcode in 0..=0xFFu8 {
+        let timing = TIMINGS[opcode as usize];
+        if timing == 0 || opcode == 0xCB {
+            continue;
+        }
+        // 0x80 keeps LDH in HRAM and the 16-bit operand at 0xC080 in work RAM
+        let bytes = [opcode, 0x80, 0xC0];
+        let cycles = execute(&bytes, false);
+        if cycles != timing * 4 {
+            mismatches.push(format!("{:02X}: {} cycles, expected {}", opcode, cycles, timing * 4));
+        }
+        if let Some(taken) = taken_timing(opcode) {
+            let cycles = execute(&bytes, true);
+            if cycles != taken * 4 {
+                mismatches.push(format!(
+                    "{:02X} taken: {} cycles, expected {}",
+                    opcode,
+                    cycles,
+                    taken * 4
+                ));
+            }
+        }
+    }
+    for cb_opcode in 0..=0xFFu8 {
+        let cycles = execute(&[0xCB, cb_opcode], false);
+        let expected = cb_timing(cb_opcode) * 4;
+        if cycles != expected {
+            mismatches
+                .push(format!("CB {:02X}: {} cycles, expected {}", cb_opcode, cycles, expected));
+        }
+    }
+    assert!(mismatches.is_empty(), "\n{}", mismatches.join("\n"));
+}
+
+#[test]
+fn opcode_tables_match_the_reference() {
+    let mut mismatches = Vec::new();
+    for opcode in 0..=0xFFu8 {
+        let entry = &OPCODES[opcode as usize];
+        let timing = TIMINGS[opcode as usize] as i32 * 4;
+        let expected: Vec<i32> = match taken_timing(opcode) {
+            Some(taken) => vec![taken as i32 * 4, timing],
+            None => vec![timing.max(4)], // the illegal opcodes are listed as 4
+        };
+        if entry.opcode != opcode
+            || entry.bytes != LENGTHS[opcode as usize]
+            || entry.cycles != expected
+        {
+            mismatches.push(format!(
+                "{:02X} {}: {} bytes {:?} cycles, expected {} bytes {:?}",
+                opcode,
+                entry.mnemonic,
+                entry.bytes,
+                entry.cycles,
+                LENGTHS[opcode as usize],
+                expected
+            ));
+        }
+
+        let cb_entry = &CB_OPCODES[opcode as usize];
+        let cb_expected = [cb_timing(opcode) as i32 * 4];
+        if cb_entry.opcode != opcode || cb_entry.bytes != 2 || cb_entry.cycles != cb_expected {
+            mismatches.push(format!(
+                "CB {:02X} {}: {} bytes {:?} cycles, expected 2 bytes {:?}",
+                opcode, cb_entry.mnemonic, cb_entry.bytes, cb_entry.cycles, cb_expected
+            ));
+        }
+    }
+    assert!(mismatches.is_empty(), "\n{}", mismatches.join("\n"));
+}
@@ -0,0 +1,29 @@
+use dot_matrix::joypad::{Joypad, JoypadButton};
+
+#[test]
+fn holding_a_button_requests_one_interrupt() {
+    let mut joypad = Joypad::new();
+    joypad.write(0x10); // action buttons
+    assert!(!joypad.update());
+
+    joypad.press_button(JoypadButton::A);
+    assert!(joypad.update());
+    for _ in 0..10 {
+        assert_eq!(joypad.read() & 0x01, 0);
+        assert!(!joypad.update());
+    }
+
+    // releasing is a rising edge, only the next press fires again
+    joypad.release_button(JoypadButton::A);
+    assert!(!joypad.update());
+    joypad.press_button(JoypadButton::A);
+    assert!(joypad.update());
+}
+
+#[test]
+fn unselected_buttons_request_no_interrupt() {
+    let mut joypad = Joypad::new();
+    joypad.write(0x20); // directions
+    joypad.press_button(JoypadButton::Start);
+    assert!(!joypad.update());
+}
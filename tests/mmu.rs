@@ -0,0 +1,293 @@
+mod common;
+
+use common::gb_with_program;
+use dot_matrix::gb::{Model, GB};
+use dot_matrix::mmu::WriteWatch;
+use dot_matrix::ppu::PPUMode;
+
+fn read(gb: &mut GB, addr: u16) -> u8 {
+    gb.mmu.read_byte(addr, &gb.cart, &gb.joypad, &mut gb.apu)
+}
+
+fn write(gb: &mut GB, addr: u16, val: u8) {
+    gb.mmu.write_byte(addr, val, &mut gb.cart, &mut gb.joypad, &mut gb.apu);
+}
+
+#[test]
+fn ppu_modes_lock_the_cpu_out_of_vram_and_oam() {
+    let mut gb = gb_with_program(&[]);
+    gb.mmu.ppu_mode = PPUMode::HBlank;
+    write(&mut gb, 0x8000, 0x12);
+    write(&mut gb, 0xFE00, 0x34);
+
+    gb.mmu.ppu_mode = PPUMode::OAM;
+    assert_eq!(read(&mut gb, 0x8000), 0x12);
+    assert_eq!(read(&mut gb, 0xFE00), 0xFF);
+    write(&mut gb, 0xFE00, 0x00);
+
+    gb.mmu.ppu_mode = PPUMode::VRAM;
+    assert_eq!(read(&mut gb, 0x8000), 0xFF);
+    assert_eq!(read(&mut gb, 0xFE00), 0xFF);
+    write(&mut gb, 0x8000, 0x00);
+
+    // the dropped writes never landed
+    gb.mmu.ppu_mode = PPUMode::VBlank;
+    assert_eq!(read(&mut gb, 0x8000), 0x12);
+    assert_eq!(read(&mut gb, 0xFE00), 0x34);
+}
+
+#[test]
+fn echo_ram_mirrors_work_ram() {
+    let mut gb = gb_with_program(&[]);
+    write(&mut gb, 0xC123, 0x56);
+    assert_eq!(read(&mut gb, 0xE123), 0x56);
+    write(&mut gb, 0xFDFF, 0x78);
+    assert_eq!(read(&mut gb, 0xDDFF), 0x78);
+}
+
+#[test]
+fn prohibited_region_ignores_writes() {
+    let mut gb = gb_with_program(&[]);
+    gb.mmu.ppu_mode = PPUMode::HBlank;
+    write(&mut gb, 0xFEA0, 0x12);
+    assert_eq!(read(&mut gb, 0xFEA0), 0x00);
+
+    gb.mmu.ppu_mode = PPUMode::OAM;
+    assert_eq!(read(&mut gb, 0xFEFF), 0xFF);
+}
+
+#[test]
+fn oam_dma_copies_one_byte_per_m_cycle_and_owns_the_bus() {
+    let mut gb = gb_with_program(&[]);
+    gb.mmu.ppu_mode = PPUMode::HBlank;
+    for i in 0..0xA0 {
+        write(&mut gb, 0xC100 + i, i as u8);
+    }
+    write(&mut gb, 0xFF46, 0xC1);
+    assert_eq!(read(&mut gb, 0xC100), 0xFF);
+    write(&mut gb, 0xFF80, 0x12);
+    assert_eq!(read(&mut gb, 0xFF80), 0x12);
+
+    gb.mmu.update_dma(8, &gb.cart);
+    assert_eq!(gb.mmu.read_oam(0xFE01), 0x01);
+    assert_eq!(gb.mmu.read_oam(0xFE02), 0x00);
+
+    gb.mmu.update_dma(632, &gb.cart);
+    assert_eq!(read(&mut gb, 0xFE9F), 0x9F);
+}
+
+#[test]
+fn cgb_palette_data_auto_increments() {
+    let mut gb = gb_with_program(&[]);
+    gb.mmu.cgb = true;
+    gb.mmu.ppu_mode = PPUMode::HBlank;
+    // palette 1, color 2 is at byte 12
+    write(&mut gb, 0xFF68, 0x80 | 12);
+    write(&mut gb, 0xFF69, 0x1F);
+    write(&mut gb, 0xFF69, 0x7C);
+    assert_eq!(read(&mut gb, 0xFF68), 0xC0 | 14);
+    assert_eq!(gb.mmu.bg_palettes.color(1, 2), 0x7C1F);
+
+    // blocked in mode 3, but the index still moves on
+    gb.mmu.ppu_mode = PPUMode::VRAM;
+    write(&mut gb, 0xFF69, 0x00);
+    assert_eq!(read(&mut gb, 0xFF69), 0xFF);
+    assert_eq!(read(&mut gb, 0xFF68), 0xC0 | 15);
+    assert_eq!(gb.mmu.bg_palettes.color(1, 3), 0xFFFF);
+}
+
+#[test]
+fn vbk_switches_the_cpu_vram_bank() {
+    let mut gb = gb_with_program(&[]);
+    gb.mmu.cgb = true;
+    gb.mmu.ppu_mode = PPUMode::HBlank;
+    write(&mut gb, 0x9800, 0x12);
+    write(&mut gb, 0xFF4F, 0x01);
+    assert_eq!(read(&mut gb, 0xFF4F), 0xFF);
+    write(&mut gb, 0x9800, 0x34);
+    assert_eq!(read(&mut gb, 0x9800), 0x34);
+
+    write(&mut gb, 0xFF4F, 0x00);
+    assert_eq!(read(&mut gb, 0xFF4F), 0xFE);
+    assert_eq!(read(&mut gb, 0x9800), 0x12);
+    assert_eq!(gb.mmu.read_vram_bank(1, 0x9800), 0x34);
+}
+
+#[test]
+fn general_purpose_hdma_copies_everything_at_once() {
+    let mut gb = gb_with_program(&[]);
+    gb.mmu.cgb = true;
+    gb.mmu.ppu_mode = PPUMode::HBlank;
+    for i in 0..0x20 {
+        write(&mut gb, 0xC000 + i, i as u8);
+    }
+    write(&mut gb, 0xFF51, 0xC0);
+    write(&mut gb, 0xFF52, 0x00);
+    write(&mut gb, 0xFF53, 0x81);
+    write(&mut gb, 0xFF54, 0x00);
+    write(&mut gb, 0xFF55, 0x01); // two blocks
+    assert_eq!(read(&mut gb, 0x8100), 0x00);
+    assert_eq!(read(&mut gb, 0x811F), 0x1F);
+    assert_eq!(read(&mut gb, 0xFF55), 0xFF);
+    assert_eq!(gb.mmu.take_hdma_stall(), 64);
+}
+
+#[test]
+fn hblank_hdma_copies_one_block_per_hblank() {
+    let mut gb = gb_with_program(&[]);
+    gb.mmu.cgb = true;
+    gb.mmu.ppu_mode = PPUMode::HBlank;
+    for i in 0..0x30 {
+        write(&mut gb, 0xC000 + i, 0xA0 + i as u8);
+    }
+    write(&mut gb, 0xFF51, 0xC0);
+    write(&mut gb, 0xFF52, 0x00);
+    write(&mut gb, 0xFF53, 0x00);
+    write(&mut gb, 0xFF54, 0x00);
+    write(&mut gb, 0xFF55, 0x82); // three blocks
+    assert_eq!(read(&mut gb, 0xFF55), 0x02);
+
+    gb.mmu.hblank_dma(&gb.cart);
+    assert_eq!(read(&mut gb, 0x800F), 0xAF);
+    assert_eq!(read(&mut gb, 0x8010), 0x00);
+    assert_eq!(read(&mut gb, 0xFF55), 0x01);
+
+    // cancelling keeps the remaining length readable
+    write(&mut gb, 0xFF55, 0x00);
+    assert_eq!(read(&mut gb, 0xFF55), 0x81);
+    gb.mmu.hblank_dma(&gb.cart);
+    assert_eq!(read(&mut gb, 0x8010), 0x00);
+}
+
+#[test]
+fn models_start_with_their_own_io_registers() {
+    let mut gb = gb_with_program(&[]);
+    gb.cart.cgb_flag = 0x80;
+    gb.set_model(Model::Cgb);
+    assert_eq!((read(&mut gb, 0xFF04), read(&mut gb, 0xFF44)), (0x1E, 0x90));
+    assert_eq!(read(&mut gb, 0xFF4F), 0xFE);
+
+    gb.set_model(Model::Dmg);
+    assert_eq!((read(&mut gb, 0xFF04), read(&mut gb, 0xFF44)), (0xAB, 0x99));
+    assert_eq!(read(&mut gb, 0xFF4F), 0xFF);
+
+    // a DMG game on a CGB gets the longer boot
+    gb.cart.cgb_flag = 0x00;
+    gb.set_model(Model::Cgb);
+    assert_eq!(read(&mut gb, 0xFF04), 0x26);
+}
+
+#[test]
+fn oam_dma_reads_vram_during_mode_3() {
+    let mut gb = gb_with_program(&[]);
+    gb.mmu.ppu_mode = PPUMode::HBlank;
+    for i in 0..0xA0 {
+        write(&mut gb, 0x8000 + i, 0x40 + i as u8);
+    }
+
+    gb.mmu.ppu_mode = PPUMode::VRAM;
+    write(&mut gb, 0xFF46, 0x80);
+    gb.mmu.update_dma(640, &gb.cart);
+    assert_eq!(gb.mmu.read_oam(0xFE00), 0x40);
+    assert_eq!(gb.mmu.read_oam(0xFE9F), 0xDF);
+    // the CPU is still locked out of VRAM
+    assert_eq!(read(&mut gb, 0x8000), 0xFF);
+}
+
+#[test]
+fn write_watch_triggers_on_vram_writes_in_range() {
+    // LD HL,0x8010; LD (HL),0x12; LD (HL),0x34
+    let mut gb = gb_with_program(&[0x21, 0x10, 0x80, 0x36, 0x12, 0x36, 0x34]);
+    gb.mmu.write_watch = Some(WriteWatch::new(0x8010, 0x801F).unwrap());
+    gb.step();
+    assert!(!gb.mmu.write_watch.as_ref().unwrap().triggered);
+    gb.step();
+    assert!(gb.mmu.write_watch.as_ref().unwrap().triggered);
+
+    assert!(WriteWatch::new(0xC000, 0xC010).is_err());
+}
+
+#[test]
+fn ff75_only_keeps_bits_4_to_6() {
+    let mut gb = gb_with_program(&[]);
+    gb.cart.cgb_flag = 0x80;
+    gb.mmu.cgb = true;
+    write(&mut gb, 0xFF75, 0x00);
+    assert_eq!(read(&mut gb, 0xFF75), 0x8F);
+    write(&mut gb, 0xFF75, 0xFF);
+    assert_eq!(read(&mut gb, 0xFF75), 0xFF);
+    write(&mut gb, 0xFF75, 0x50);
+    assert_eq!(read(&mut gb, 0xFF75), 0xDF);
+    write(&mut gb, 0xFF72, 0x5A);
+    assert_eq!(read(&mut gb, 0xFF72), 0x5A);
+
+    // absent outside CGB mode, even for a CGB-compatible cart running on a DMG
+    gb.mmu.cgb = false;
+    assert_eq!(read(&mut gb, 0xFF75), 0xFF);
+    assert_eq!(read(&mut gb, 0xFF72), 0xFF);
+    gb.cart.cgb_flag = 0x00;
+    assert_eq!(read(&mut gb, 0xFF72), 0xFF);
+}
+
+#[test]
+fn key1_follows_the_mode_not_the_cart() {
+    // a CGB-compatible cart running in DMG mode, as on a DMG or with --model dmg
+    let mut gb = gb_with_program(&[]);
+    gb.cart.cgb_flag = 0x80;
+    gb.mmu.cgb = false;
+    write(&mut gb, 0xFF4D, 0x01);
+    assert!(!gb.mmu.speed_switch());
+    assert!(!gb.mmu.double_speed());
+
+    gb.mmu.cgb = true;
+    write(&mut gb, 0xFF4D, 0x01);
+    assert_eq!(read(&mut gb, 0xFF4D), 0x7F);
+}
+
+#[test]
+fn svbk_banks_work_ram_at_d000() {
+    let mut gb = gb_with_program(&[]);
+    gb.cart.cgb_flag = 0x80;
+    gb.set_model(Model::Cgb);
+    assert_eq!(read(&mut gb, 0xFF70), 0xF8);
+    for bank in 1..8 {
+        write(&mut gb, 0xFF70, bank);
+        write(&mut gb, 0xD000, bank * 0x11);
+        write(&mut gb, 0xC000, bank);
+    }
+
+    write(&mut gb, 0xFF70, 0xFA); // only bits 0-2 are kept
+    assert_eq!(read(&mut gb, 0xFF70), 0xFA);
+    assert_eq!(read(&mut gb, 0xD000), 0x22);
+    assert_eq!(read(&mut gb, 0xF000), 0x22); // the echo follows the bank
+    assert_eq!(read(&mut gb, 0xC000), 7); // 0xC000-0xCFFF is always bank 0
+    write(&mut gb, 0xFF70, 0x07);
+    assert_eq!(read(&mut gb, 0xD000), 0x77);
+    write(&mut gb, 0xFF70, 0x00); // bank 0 maps to 1
+    assert_eq!(read(&mut gb, 0xFF70), 0xF8);
+    assert_eq!(read(&mut gb, 0xD000), 0x11);
+
+    // DMG mode has a single bank
+    gb.set_model(Model::Dmg);
+    write(&mut gb, 0xFF70, 0x03);
+    assert_eq!(read(&mut gb, 0xD000), 0x11);
+}
+
+#[test]
+fn load_bytes_patches_the_rom_the_cpu_reads() {
+    // 32 KiB MBC1: bank 3 only has bank 1 wired up
+    let mut gb = gb_with_program(&[]);
+    write(&mut gb, 0x2000, 0x03);
+    gb.load_bytes(0x4000, &[0xAB]);
+    assert_eq!(read(&mut gb, 0x4000), 0xAB);
+    assert_eq!(gb.cart.rom[0x4000], 0xAB);
+}
+
+#[test]
+fn memory_map_reports_the_mapped_rom_bank() {
+    // 32 KiB MBC1: the upper bank bits and bit 1 aren't wired up
+    let mut gb = gb_with_program(&[]);
+    write(&mut gb, 0x4000, 0x01);
+    write(&mut gb, 0x2000, 0x03);
+    assert_eq!(gb.mmu.memory_map(&gb.cart).rom_bank, 1);
+}
@@ -0,0 +1,233 @@
+mod common;
+
+use common::gb_with_program;
+use dot_matrix::consts::{DOTS_PER_FRAME, DOTS_PER_SCANLINE, MIN_VRAM_DOTS, SCANLINES_PER_FRAME};
+use dot_matrix::gb::GB;
+use dot_matrix::ppu::{PPUMemory, PPUMode, SHADE_WHITE};
+
+// JR -2, so the CPU spins while the PPU runs
+const SPIN: &[u8] = &[0x18, 0xFE];
+
+fn run_to_mode_3(gb: &mut GB) {
+    while !matches!(gb.ppu.current_mode, PPUMode::VRAM) {
+        gb.step();
+    }
+}
+
+#[test]
+fn mode_3_is_lengthened_by_fine_scroll_and_sprites() {
+    let mut gb = gb_with_program(SPIN);
+    gb.mmu.ram[PPUMemory::SCX as usize] = 3;
+    run_to_mode_3(&mut gb);
+    assert_eq!(gb.ppu.vram_dots, MIN_VRAM_DOTS + 3);
+
+    // two sprites on line 0 over the same BG tile, only the first pays for the fetch
+    let mut gb = gb_with_program(SPIN);
+    gb.mmu.ram[PPUMemory::LCDC as usize] |= 0x02;
+    gb.mmu.ram[0xFE00..0xFE08].copy_from_slice(&[16, 8, 0, 0, 16, 10, 0, 0]);
+    run_to_mode_3(&mut gb);
+    assert_eq!(gb.ppu.vram_dots, MIN_VRAM_DOTS + (6 + 5) + 6);
+}
+
+#[test]
+fn scanlines_stay_456_dots_long() {
+    let mut gb = gb_with_program(SPIN);
+    gb.mmu.ram[PPUMemory::SCX as usize] = 7;
+    gb.mmu.ram[PPUMemory::LCDC as usize] |= 0x02;
+    gb.mmu.ram[0xFE00..0xFE04].copy_from_slice(&[16, 0, 0, 0]);
+    gb.mmu.ram[0xFE04..0xFE08].copy_from_slice(&[24, 20, 0, 0]);
+
+    let run_to_line = |gb: &mut GB, line: u8| {
+        let mut dots = 0;
+        while gb.mmu.ram[PPUMemory::LY as usize] != line {
+            dots += gb.step();
+        }
+        dots
+    };
+    run_to_line(&mut gb, 1);
+    // JR takes 12 cycles, so each line change is seen up to 12 dots late
+    let dots = run_to_line(&mut gb, 10);
+    assert!(dots.abs_diff(9 * DOTS_PER_SCANLINE) < 12, "{}", dots);
+}
+
+#[test]
+fn frames_are_70224_dots() {
+    assert_eq!(SCANLINES_PER_FRAME * DOTS_PER_SCANLINE, DOTS_PER_FRAME);
+
+    let mut gb = gb_with_program(SPIN);
+    let run_to_vblank = |gb: &mut GB| {
+        let mut dots = 0;
+        while gb.mmu.ram[PPUMemory::LY as usize] != 144 {
+            dots += gb.step();
+        }
+        while gb.mmu.ram[PPUMemory::LY as usize] == 144 {
+            dots += gb.step();
+        }
+        dots
+    };
+    run_to_vblank(&mut gb);
+    // 144 visible lines plus 10 VBlank lines, each seen up to one JR late
+    let dots = run_to_vblank(&mut gb);
+    assert!(dots.abs_diff(DOTS_PER_FRAME) < 12, "{}", dots);
+}
+
+#[test]
+fn lcd_off_resets_ly_and_lcd_on_skips_the_first_oam_scan() {
+    let mut gb = gb_with_program(SPIN);
+    while gb.mmu.ram[PPUMemory::LY as usize] != 5 {
+        gb.step();
+    }
+    gb.mmu.ram[PPUMemory::LCDC as usize] &= 0x7F;
+    gb.step();
+    assert_eq!(gb.mmu.ram[PPUMemory::LY as usize], 0);
+    assert_eq!(gb.mmu.ram[PPUMemory::STAT as usize] & 0b11, 0);
+
+    gb.mmu.ram[PPUMemory::LCDC as usize] |= 0x80;
+    let mut modes = Vec::new();
+    while gb.mmu.ram[PPUMemory::LY as usize] == 0 {
+        gb.step();
+        let mode = gb.mmu.ram[PPUMemory::STAT as usize] & 0b11;
+        if modes.last() != Some(&mode) {
+            modes.push(mode);
+        }
+    }
+    assert_eq!(modes, [0, 3, 0, 2]);
+}
+
+#[test]
+fn lcd_off_blanks_the_screen() {
+    let mut gb = gb_with_program(SPIN);
+    gb.mmu.ram[PPUMemory::BGP as usize] = 0xFF; // every BG color is black
+    gb.run_until_vblank();
+    assert!(gb.ppu.framebuffer().iter().all(|&shade| shade == 3));
+
+    gb.mmu.ram[PPUMemory::LCDC as usize] &= 0x7F;
+    gb.step();
+    assert!(gb.ppu.framebuffer().iter().all(|&shade| shade == SHADE_WHITE));
+}
+
+#[test]
+fn dmg_acid2_frame_hash_is_stable() {
+    let rom = format!("{}/tests/roms/dmg-acid2.gb", env!("CARGO_MANIFEST_DIR"));
+    let mut gb = GB::new_headless(&rom);
+    gb.run_frames(60);
+    assert_eq!(gb.framebuffer_hash(), 0xF272A8FFE3DB4C16);
+}
+
+// A black window over a white background: tile 1 is solid color 3, the window map at 0x9C00
+// is all tile 1, the BG map at 0x9800 all tile 0
+fn gb_with_window(wy: u8) -> GB {
+    let mut gb = gb_with_program(SPIN);
+    gb.load_bytes(0x8010, &[0xFF; 16]);
+    gb.load_bytes(0x9C00, &[0x01; 0x400]);
+    gb.mmu.ram[PPUMemory::LCDC as usize] = 0x91 | 0x20 | 0x40;
+    gb.mmu.ram[PPUMemory::BGP as usize] = 0xE4;
+    gb.mmu.ram[PPUMemory::WY as usize] = wy;
+    gb.mmu.ram[PPUMemory::WX as usize] = 7;
+    gb
+}
+
+// Runs to `line` of the next frame, writes WY and finishes the frame
+fn write_wy_at_line(gb: &mut GB, line: u8, wy: u8) {
+    while gb.mmu.ram[PPUMemory::LY as usize] != line {
+        gb.step();
+    }
+    gb.mmu.ram[PPUMemory::WY as usize] = wy;
+    gb.run_until_vblank();
+}
+
+fn window_rows(gb: &GB) -> Vec<usize> {
+    (0..144).filter(|row| gb.ppu.framebuffer()[row * 160] == 3).collect()
+}
+
+#[test]
+fn window_keeps_the_wy_it_started_at() {
+    let mut gb = gb_with_window(40);
+    gb.run_until_vblank();
+    assert_eq!(window_rows(&gb), (40..144).collect::<Vec<_>>());
+
+    // moving WY below a window that already started doesn't cut it short
+    write_wy_at_line(&mut gb, 60, 100);
+    assert_eq!(window_rows(&gb), (40..144).collect::<Vec<_>>());
+
+    // and moving it above LY doesn't start it retroactively, nor on any later line
+    let mut gb = gb_with_window(80);
+    gb.run_until_vblank();
+    write_wy_at_line(&mut gb, 60, 20);
+    assert_eq!(window_rows(&gb), Vec::<usize>::new());
+}
+
+// Ten solid 8x8 sprites side by side on line 40 (screen X 8, 24, ... 152) after a sprite at
+// OAM `y`/`x` in slot 0. Returns how many of the ten were drawn, and whether anything was
+// drawn in the 8 pixels at the left edge where slot 0 would show.
+fn sprites_drawn_after(y: u8, x: u8) -> (usize, bool) {
+    let mut gb = gb_with_program(SPIN);
+    gb.load_bytes(0x8010, &[0xFF; 16]);
+    gb.mmu.ram[PPUMemory::LCDC as usize] = 0x93;
+    gb.mmu.ram[PPUMemory::BGP as usize] = 0xE4;
+    gb.mmu.ram[PPUMemory::OBP0 as usize] = 0xE4;
+    let mut oam = vec![y, x, 1, 0];
+    for i in 0..10 {
+        oam.extend([16 + 40, 16 + 16 * i, 1, 0]);
+    }
+    gb.load_bytes(0xFE00, &oam);
+    gb.run_until_vblank();
+
+    let frame = gb.ppu.framebuffer();
+    let row = &frame[40 * 160..41 * 160];
+    let drawn = (0..10).filter(|i| row[8 + 16 * i] == 3).count();
+    let left_edge = (0..144).any(|line| frame[line * 160..line * 160 + 8].contains(&3));
+    (drawn, left_edge)
+}
+
+#[test]
+fn sprites_hidden_horizontally_still_use_a_slot() {
+    // X=0 ends just left of the screen, X=168 starts just right of it
+    assert_eq!(sprites_drawn_after(16 + 40, 0), (9, false));
+    assert_eq!(sprites_drawn_after(16 + 40, 168), (9, false));
+}
+
+#[test]
+fn sprites_hidden_vertically_use_no_slot() {
+    // Y=0 ends on line -9 and Y=160 starts on line 144: neither is on a visible line for the
+    // OAM scan to pick, so all ten on line 40 draw
+    assert_eq!(sprites_drawn_after(0, 8), (10, false));
+    assert_eq!(sprites_drawn_after(160, 8), (10, false));
+    // the same sprite on line 40 does show, and pushes out the tenth
+    assert_eq!(sprites_drawn_after(16 + 40, 8), (9, true));
+}
+
+// Steps the PPU alone one dot at a time until LY reads `line`, then counts the dots until
+// the STAT interrupt is requested
+fn dots_from_ly_to_stat_interrupt(lyc: u8, line: u8) -> u32 {
+    let mut gb = gb_with_program(SPIN);
+    gb.mmu.ram[PPUMemory::LYC as usize] = lyc;
+    gb.mmu.ram[PPUMemory::STAT as usize] = 0x40; // LYC=LY interrupt only
+    let mut dot = |gb: &mut GB| {
+        gb.ppu.update(1, &mut gb.mmu, &mut gb.cpu, &mut gb.cart, &mut gb.joypad, &mut gb.apu)
+    };
+    while gb.mmu.ram[PPUMemory::LY as usize] == line {
+        dot(&mut gb);
+    }
+    while gb.mmu.ram[PPUMemory::LY as usize] != line {
+        dot(&mut gb);
+    }
+    gb.mmu.ram[0xFF0F] = 0;
+    let mut dots = 0;
+    while gb.mmu.ram[0xFF0F] & 0x02 == 0 {
+        dot(&mut gb);
+        dots += 1;
+        assert!(dots < 456, "no STAT interrupt on line {}", line);
+    }
+    dots
+}
+
+#[test]
+fn lyc_interrupt_fires_at_dot_4() {
+    assert_eq!(dots_from_ly_to_stat_interrupt(72, 72), 4);
+}
+
+#[test]
+fn lyc_0_matches_during_line_153() {
+    assert_eq!(dots_from_ly_to_stat_interrupt(0, 153), 8);
+}
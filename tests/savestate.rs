@@ -0,0 +1,74 @@
+use dot_matrix::gb::GB;
+use std::path::PathBuf;
+
+const ROM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/roms/cpu_instrs/01-special.gb");
+
+// Deserializing the PPU's frame buffers in a debug build needs more stack than a test thread
+// gets, so the tests run on a thread with a main thread's 8 MiB
+fn with_main_thread_stack(test: fn()) {
+    std::thread::Builder::new().stack_size(8 * 1024 * 1024).spawn(test).unwrap().join().unwrap();
+}
+
+// states are written next to the path they're given, keep them out of the repo
+fn temp_rom_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dot-matrix-{}-{}.gb", name, std::process::id()))
+}
+
+#[test]
+fn loading_a_savestate_resumes_exactly() {
+    with_main_thread_stack(savestate_round_trip);
+}
+
+fn savestate_round_trip() {
+    let rom_path = temp_rom_path("round-trip");
+    let state_path = rom_path.to_str().unwrap();
+
+    let mut gb = GB::new_headless(ROM);
+    gb.run_frames(30);
+    gb.savestate(state_path, Some(9)).unwrap();
+    let saved_ram = gb.mmu.ram.clone();
+    let saved_pc = gb.cpu.pc;
+    gb.run_frames(30);
+    let expected_hash = gb.framebuffer_hash();
+
+    // scramble everything the state should restore
+    gb.mmu.ram.iter_mut().enumerate().for_each(|(i, byte)| *byte = i as u8 ^ 0x5A);
+    gb.cpu.pc = 0x1234;
+    gb.cpu.a = 0xFF;
+    gb.ppu.current_cycles = 7;
+    let loaded = gb.loadstate(state_path, Some(9));
+    std::fs::remove_file(rom_path.with_extension("st9")).unwrap();
+    loaded.unwrap();
+
+    assert_eq!(gb.cpu.pc, saved_pc);
+    assert!(gb.mmu.ram == saved_ram, "RAM differs after loading the state");
+    gb.run_frames(30);
+    assert_eq!(gb.framebuffer_hash(), expected_hash);
+}
+
+#[test]
+fn a_truncated_savestate_leaves_the_game_running() {
+    with_main_thread_stack(truncated_savestate);
+}
+
+fn truncated_savestate() {
+    let rom_path = temp_rom_path("truncated");
+    let state_path = rom_path.to_str().unwrap();
+    let state_file = rom_path.with_extension("st");
+
+    let mut gb = GB::new_headless(ROM);
+    gb.run_frames(10);
+    gb.savestate(state_path, None).unwrap();
+    let bytes = std::fs::read(&state_file).unwrap();
+    std::fs::write(&state_file, &bytes[..bytes.len() / 2]).unwrap();
+
+    gb.run_frames(10);
+    let pc = gb.cpu.pc;
+    let ram = gb.mmu.ram.clone();
+    let loaded = gb.loadstate(state_path, None);
+    std::fs::remove_file(&state_file).unwrap();
+
+    assert!(loaded.unwrap_err().starts_with("Savestate load failed"));
+    assert_eq!(gb.cpu.pc, pc);
+    assert!(gb.mmu.ram == ram, "a failed load changed RAM");
+}
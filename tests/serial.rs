@@ -0,0 +1,38 @@
+use dot_matrix::serial::{Serial, SB, SC};
+
+fn linked_serial(sb: u8) -> Serial {
+    let mut serial = Serial::new();
+    serial.linked = true;
+    serial.write(SB, sb);
+    serial
+}
+
+#[test]
+fn linked_transfer_exchanges_sb_with_the_partner() {
+    let mut master = linked_serial(0x12);
+    let mut slave = linked_serial(0x34);
+    slave.write(SC, 0x80); // external clock, armed
+
+    master.write(SC, 0x81);
+    let sent = master.outgoing.take().expect("the master queues its SB for the link");
+    let reply = slave.clocked_by_peer(sent);
+    assert!(slave.update(4));
+    assert_eq!(slave.read(SB), 0x12);
+    assert_eq!(slave.read(SC) & 0x80, 0);
+
+    // the bits have shifted, but the transfer waits for the partner's byte
+    assert!(!master.update(8 * 512));
+    master.receive_reply(reply);
+    assert!(master.update(4));
+    assert_eq!(master.read(SB), 0x34);
+}
+
+#[test]
+fn unlinking_finishes_a_waiting_transfer_with_ff() {
+    let mut master = linked_serial(0x12);
+    master.write(SC, 0x81);
+    assert!(!master.update(8 * 512));
+    master.unlink();
+    assert!(master.update(4));
+    assert_eq!(master.read(SB), 0xFF);
+}
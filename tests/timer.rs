@@ -0,0 +1,25 @@
+mod common;
+
+use common::gb_with_program;
+use dot_matrix::gb::GB;
+
+fn run(gb: &mut GB, instructions: usize) {
+    for _ in 0..instructions {
+        gb.step();
+    }
+}
+
+#[test]
+fn tima_counts_at_the_selected_rate() {
+    // LD A,0x05; LDH (TAC),A; XOR A; LDH (TIMA),A; JR -2
+    let mut gb = gb_with_program(&[0x3E, 0x05, 0xE0, 0x07, 0xAF, 0xE0, 0x05, 0x18, 0xFE]);
+    run(&mut gb, 4);
+
+    // TAC 0b101 is 262144 Hz, one increment per 16 cycles
+    let mut cycles = 0;
+    while cycles < 1600 {
+        cycles += gb.step();
+    }
+    let tima = gb.mmu.ram[0xFF05] as u32;
+    assert!((cycles / 16).abs_diff(tima) <= 1, "TIMA {} after {} cycles", tima, cycles);
+}